@@ -1,7 +1,75 @@
 use serde::{Deserialize, Serialize};
-use std::{fmt::Display, str::FromStr};
-use symm::atom::Atom;
+use std::{borrow::Cow, fmt::Display, str::FromStr};
+use symm::{atom::Atom, ANGBOHR};
 
+pub mod io;
+pub mod symmetry;
+
+/// Length units for Cartesian coordinates
+#[derive(
+    Debug, Default, PartialEq, Eq, Copy, Clone, Serialize, Deserialize,
+)]
+pub enum Unit {
+    #[default]
+    Angstrom,
+    Bohr,
+}
+
+impl Unit {
+    /// the factor to multiply a length in `self` by to convert it to `to`
+    pub fn factor(self, to: Unit) -> f64 {
+        match (self, to) {
+            (Unit::Angstrom, Unit::Angstrom) | (Unit::Bohr, Unit::Bohr) => 1.0,
+            (Unit::Bohr, Unit::Angstrom) => ANGBOHR,
+            (Unit::Angstrom, Unit::Bohr) => 1.0 / ANGBOHR,
+        }
+    }
+}
+
+/// convert the coordinates of `atoms` in place from `from` units to `to` units
+pub fn convert(atoms: &mut [Atom], from: Unit, to: Unit) {
+    if from == to {
+        return;
+    }
+    let f = from.factor(to);
+    for atom in atoms {
+        atom.x *= f;
+        atom.y *= f;
+        atom.z *= f;
+    }
+}
+
+/// Cartesian coordinates tagged with the [Unit] they are in, for coordinates
+/// read from or written to programs and files that don't use Ångström
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cartesian {
+    pub atoms: Vec<Atom>,
+    pub unit: Unit,
+}
+
+impl Cartesian {
+    pub fn new(atoms: Vec<Atom>, unit: Unit) -> Self {
+        Self { atoms, unit }
+    }
+
+    /// convert the coordinates to `unit`
+    pub fn into_unit(mut self, unit: Unit) -> Self {
+        convert(&mut self.atoms, self.unit, unit);
+        self.unit = unit;
+        self
+    }
+
+    /// the coordinates in Ångström, as stored in [Geom::Xyz]
+    pub fn into_angstrom(self) -> Vec<Atom> {
+        self.into_unit(Unit::Angstrom).atoms
+    }
+}
+
+/// A molecular geometry, either in Cartesian coordinates or as a Z-matrix.
+/// Cartesian coordinates are always stored in Ångström ([Unit::Angstrom]),
+/// and [Geom::to_unit] should be used to convert them for programs expecting
+/// other units. Z-matrix strings are passed through untouched, so any units
+/// they need must be specified in the template
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Geom {
     Xyz(Vec<Atom>),
@@ -85,6 +153,20 @@ impl Geom {
         }
     }
 
+    /// return `self` with its Cartesian coordinates converted from Ångström to
+    /// `unit`. this only allocates if a conversion is actually required. use
+    /// [Cartesian] to carry the unit along with the converted coordinates
+    pub fn to_unit(&self, unit: Unit) -> Cow<'_, Geom> {
+        match self {
+            Geom::Xyz(atoms) if unit != Unit::Angstrom => {
+                let mut atoms = atoms.clone();
+                convert(&mut atoms, Unit::Angstrom, unit);
+                Cow::Owned(Geom::Xyz(atoms))
+            }
+            _ => Cow::Borrowed(self),
+        }
+    }
+
     pub fn is_xyz(&self) -> bool {
         matches!(self, Geom::Xyz(_))
     }
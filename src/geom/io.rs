@@ -0,0 +1,550 @@
+//! Reading and writing [Geom]s in the file formats used by common
+//! visualization tools. Only Cartesian geometries ([Geom::Xyz]) can be
+//! represented in these formats, and all coordinates are in Ångström on the
+//! psqs side.
+
+use std::{fmt::Display, fmt::Write, path::Path, str::FromStr};
+
+use symm::Atom;
+
+use super::{Cartesian, Geom, Unit};
+
+/// The geometry file formats understood by [read] and [write]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Format {
+    /// plain XYZ: a count line, a comment line, and then `label x y z` lines
+    Xyz,
+
+    /// MDL MOL/SDF V2000. the bond block is neither read nor written
+    Sdf,
+
+    /// Protein Data Bank `ATOM`/`HETATM` records
+    Pdb,
+
+    /// the `[Atoms]` section of a Molden file
+    Molden,
+
+    /// Tripos MOL2. only the `@<TRIPOS>ATOM` section is read, and no bonds are
+    /// written
+    Mol2,
+}
+
+impl Format {
+    /// guess the format of `path` from its extension
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        path.as_ref().extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for Format {
+    type Err = GeomError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "xyz" => Ok(Self::Xyz),
+            "sdf" | "mol" => Ok(Self::Sdf),
+            "pdb" => Ok(Self::Pdb),
+            "molden" => Ok(Self::Molden),
+            "mol2" => Ok(Self::Mol2),
+            _ => Err(GeomError::UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GeomError {
+    /// the format name or file extension was not recognized
+    UnknownFormat(String),
+
+    /// the geometry was a Z-matrix, which cannot be written in any [Format]
+    NotCartesian,
+
+    /// a required section or header line was not found
+    SectionNotFound(String),
+
+    /// failed to parse the contained line
+    ParseError(String),
+
+    /// failed to read or write the contained file
+    FileError(String, std::io::ErrorKind),
+}
+
+impl Display for GeomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for GeomError {}
+
+/// parse `s` as a geometry in `format`
+pub fn read(s: &str, format: Format) -> Result<Geom, GeomError> {
+    let atoms = match format {
+        Format::Xyz => read_xyz(s),
+        Format::Sdf => read_sdf(s),
+        Format::Pdb => read_pdb(s),
+        Format::Molden => {
+            read_molden(s, Unit::Angstrom).map(Cartesian::into_angstrom)
+        }
+        Format::Mol2 => read_mol2(s),
+    }?;
+    Ok(Geom::Xyz(atoms))
+}
+
+/// render `geom` in `format`. returns [GeomError::NotCartesian] for
+/// [Geom::Zmat]
+pub fn write(geom: &Geom, format: Format) -> Result<String, GeomError> {
+    let Some(atoms) = geom.xyz() else {
+        return Err(GeomError::NotCartesian);
+    };
+    Ok(match format {
+        Format::Xyz => write_xyz(atoms, ""),
+        Format::Sdf => write_sdf(atoms, ""),
+        Format::Pdb => write_pdb(atoms),
+        Format::Molden => write_molden(atoms, Unit::Angstrom),
+        Format::Mol2 => write_mol2(atoms, ""),
+    })
+}
+
+/// read the geometry in `path`, guessing the [Format] from its extension
+pub fn read_file(path: impl AsRef<Path>) -> Result<Geom, GeomError> {
+    let path = path.as_ref();
+    let name = path.display().to_string();
+    let format = Format::from_path(path)
+        .ok_or(GeomError::UnknownFormat(name.clone()))?;
+    let s = std::fs::read_to_string(path)
+        .map_err(|e| GeomError::FileError(name, e.kind()))?;
+    read(&s, format)
+}
+
+/// write `geom` to `path`, guessing the [Format] from its extension
+pub fn write_file(
+    geom: &Geom,
+    path: impl AsRef<Path>,
+) -> Result<(), GeomError> {
+    let path = path.as_ref();
+    let name = path.display().to_string();
+    let format = Format::from_path(path)
+        .ok_or(GeomError::UnknownFormat(name.clone()))?;
+    std::fs::write(path, write(geom, format)?)
+        .map_err(|e| GeomError::FileError(name, e.kind()))
+}
+
+/// convert an element symbol in any case, like `CL` from a PDB file, into the
+/// capitalization expected by [Atom::new_from_label]
+fn normalize_label(label: &str) -> String {
+    let mut chars = label.chars();
+    match chars.next() {
+        Some(c) => c
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// parse the label and coordinates from the first four fields of `line`
+fn parse_atom<'a>(
+    label: &str,
+    mut coords: impl Iterator<Item = &'a str>,
+    line: &str,
+) -> Result<Atom, GeomError> {
+    let err = || GeomError::ParseError(line.to_owned());
+    let mut next = || -> Result<f64, GeomError> {
+        coords
+            .next()
+            .ok_or_else(err)?
+            .trim()
+            .parse()
+            .map_err(|_| err())
+    };
+    let (x, y, z) = (next()?, next()?, next()?);
+    Ok(Atom::new_from_label(&normalize_label(label), x, y, z))
+}
+
+pub fn read_xyz(s: &str) -> Result<Vec<Atom>, GeomError> {
+    let mut lines = s.lines().skip_while(|l| l.trim().is_empty());
+    let Some(count) = lines.next() else {
+        return Err(GeomError::SectionNotFound("atom count".to_owned()));
+    };
+    let count: usize = count
+        .trim()
+        .parse()
+        .map_err(|_| GeomError::ParseError(count.to_owned()))?;
+    // comment line
+    lines.next();
+    let mut atoms = Vec::with_capacity(count);
+    for line in lines.take(count) {
+        let mut sp = line.split_whitespace();
+        let label = sp.next().ok_or(GeomError::ParseError(line.to_owned()))?;
+        atoms.push(parse_atom(label, sp, line)?);
+    }
+    if atoms.len() != count {
+        return Err(GeomError::SectionNotFound("atoms".to_owned()));
+    }
+    Ok(atoms)
+}
+
+pub fn write_xyz(atoms: &[Atom], comment: &str) -> String {
+    let mut ret = String::with_capacity(50 * (atoms.len() + 2));
+    writeln!(ret, "{}", atoms.len()).unwrap();
+    writeln!(ret, "{comment}").unwrap();
+    for atom in atoms {
+        writeln!(
+            ret,
+            "{:5}{:15.10}{:15.10}{:15.10}",
+            atom.label(),
+            atom.x,
+            atom.y,
+            atom.z
+        )
+        .unwrap();
+    }
+    ret
+}
+
+/// read the first molecule in a MOL or SDF file
+pub fn read_sdf(s: &str) -> Result<Vec<Atom>, GeomError> {
+    // three header lines and then the counts line
+    let mut lines = s.lines().skip(3);
+    let Some(counts) = lines.next() else {
+        return Err(GeomError::SectionNotFound("counts line".to_owned()));
+    };
+    let count: usize = counts
+        .get(..3)
+        .unwrap_or(counts)
+        .trim()
+        .parse()
+        .map_err(|_| GeomError::ParseError(counts.to_owned()))?;
+    let mut atoms = Vec::with_capacity(count);
+    for line in lines.take(count) {
+        // x, y, z, and then the atom symbol
+        let sp: Vec<_> = line.split_whitespace().collect();
+        if sp.len() < 4 {
+            return Err(GeomError::ParseError(line.to_owned()));
+        }
+        atoms.push(parse_atom(sp[3], sp[..3].iter().copied(), line)?);
+    }
+    if atoms.len() != count {
+        return Err(GeomError::SectionNotFound("atom block".to_owned()));
+    }
+    Ok(atoms)
+}
+
+pub fn write_sdf(atoms: &[Atom], name: &str) -> String {
+    let mut ret = String::with_capacity(70 * (atoms.len() + 6));
+    writeln!(ret, "{name}").unwrap();
+    writeln!(ret, "  psqs").unwrap();
+    writeln!(ret).unwrap();
+    writeln!(
+        ret,
+        "{:3}{:3}  0  0  0  0  0  0  0  0999 V2000",
+        atoms.len(),
+        0
+    )
+    .unwrap();
+    for atom in atoms {
+        writeln!(
+            ret,
+            "{:10.4}{:10.4}{:10.4} {:<3} 0  0  0  0  0  0  0  0  0  0  0  0",
+            atom.x,
+            atom.y,
+            atom.z,
+            atom.label()
+        )
+        .unwrap();
+    }
+    writeln!(ret, "M  END").unwrap();
+    writeln!(ret, "$$$$").unwrap();
+    ret
+}
+
+/// read the `ATOM` and `HETATM` records of a PDB file. the element is taken
+/// from columns 77-78 if present and otherwise from the atom name with any
+/// digits removed
+pub fn read_pdb(s: &str) -> Result<Vec<Atom>, GeomError> {
+    let mut atoms = Vec::new();
+    for line in s.lines() {
+        if line.starts_with("END") {
+            break;
+        }
+        if !(line.starts_with("ATOM  ") || line.starts_with("HETATM")) {
+            continue;
+        }
+        let err = || GeomError::ParseError(line.to_owned());
+        let coords = [30..38, 38..46, 46..54]
+            .into_iter()
+            .map(|r| line.get(r).unwrap_or(""));
+        let element = match line.get(76..78).map(str::trim) {
+            Some(e) if !e.is_empty() => e.to_owned(),
+            _ => line
+                .get(12..16)
+                .ok_or_else(err)?
+                .trim()
+                .trim_matches(|c: char| c.is_ascii_digit())
+                .to_owned(),
+        };
+        atoms.push(parse_atom(&element, coords, line)?);
+    }
+    if atoms.is_empty() {
+        return Err(GeomError::SectionNotFound("ATOM records".to_owned()));
+    }
+    Ok(atoms)
+}
+
+pub fn write_pdb(atoms: &[Atom]) -> String {
+    let mut ret = String::with_capacity(81 * (atoms.len() + 1));
+    for (i, atom) in atoms.iter().enumerate() {
+        let name = format!("{}{}", atom.label(), i + 1);
+        writeln!(
+            ret,
+            "HETATM{:5} {:<4} MOL A   1    {:8.3}{:8.3}{:8.3}{:6.2}{:6.2}          {:>2}",
+            i + 1,
+            name,
+            atom.x,
+            atom.y,
+            atom.z,
+            1.0,
+            0.0,
+            atom.label().to_uppercase(),
+        )
+        .unwrap();
+    }
+    writeln!(ret, "END").unwrap();
+    ret
+}
+
+/// read the `@<TRIPOS>ATOM` section of a MOL2 file. the element is taken from
+/// the SYBYL atom type, such as `O.3`, by dropping everything from the first
+/// `.`. lines in the section look like
+///
+/// ```text
+///       1 O1          0.0000    0.0000   -0.0657 O.3       1 MOL       0.0000
+/// ```
+pub fn read_mol2(s: &str) -> Result<Vec<Atom>, GeomError> {
+    let mut lines = s.lines();
+    if !lines
+        .by_ref()
+        .any(|l| l.trim().eq_ignore_ascii_case("@<TRIPOS>ATOM"))
+    {
+        return Err(GeomError::SectionNotFound("@<TRIPOS>ATOM".to_owned()));
+    }
+    let mut atoms = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.starts_with('@') {
+            break;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // id, name, x, y, z, and then the atom type
+        let sp: Vec<_> = line.split_whitespace().collect();
+        if sp.len() < 6 {
+            return Err(GeomError::ParseError(line.to_owned()));
+        }
+        let element = sp[5].split('.').next().unwrap_or(sp[5]);
+        atoms.push(parse_atom(element, sp[2..5].iter().copied(), line)?);
+    }
+    if atoms.is_empty() {
+        return Err(GeomError::SectionNotFound("ATOM records".to_owned()));
+    }
+    Ok(atoms)
+}
+
+/// write `atoms` as the `@<TRIPOS>MOLECULE` and `@<TRIPOS>ATOM` sections of a
+/// MOL2 file, using the bare element symbol as the atom type
+pub fn write_mol2(atoms: &[Atom], name: &str) -> String {
+    let mut ret = String::with_capacity(80 * (atoms.len() + 7));
+    writeln!(ret, "@<TRIPOS>MOLECULE").unwrap();
+    writeln!(ret, "{name}").unwrap();
+    writeln!(ret, "{:5}{:6}{:6}{:6}{:6}", atoms.len(), 0, 1, 0, 0).unwrap();
+    writeln!(ret, "SMALL").unwrap();
+    writeln!(ret, "NO_CHARGES").unwrap();
+    writeln!(ret).unwrap();
+    writeln!(ret, "@<TRIPOS>ATOM").unwrap();
+    for (i, atom) in atoms.iter().enumerate() {
+        let label = atom.label();
+        writeln!(
+            ret,
+            "{:7} {:<8}{:12.6}{:12.6}{:12.6} {:<8}{:3} MOL{:14.4}",
+            i + 1,
+            format!("{label}{}", i + 1),
+            atom.x,
+            atom.y,
+            atom.z,
+            label,
+            1,
+            0.0,
+        )
+        .unwrap();
+    }
+    ret
+}
+
+/// read the `[Atoms]` section of a Molden file, in the units given in its
+/// header: Bohr if it is labeled `AU`, Ångström if it is labeled `Angs`, and
+/// `default` otherwise. lines in the section look like
+///
+/// ```text
+/// O        1    8       -0.0000000000        0.0000000000        0.1243564077
+/// ```
+pub fn read_molden(s: &str, default: Unit) -> Result<Cartesian, GeomError> {
+    let mut lines = s.lines();
+    let Some(header) = lines
+        .by_ref()
+        .find(|l| l.trim_start().to_lowercase().starts_with("[atoms]"))
+    else {
+        return Err(GeomError::SectionNotFound("[Atoms]".to_owned()));
+    };
+    let units = header.split_ascii_whitespace().nth(1).unwrap_or("");
+    let unit = if units.eq_ignore_ascii_case("au") {
+        Unit::Bohr
+    } else if units.eq_ignore_ascii_case("angs") {
+        Unit::Angstrom
+    } else {
+        default
+    };
+    let mut atoms = Vec::new();
+    // the section ends at the next header or the first blank line
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('[') {
+            break;
+        }
+        let sp: Vec<_> = line.split_ascii_whitespace().collect();
+        if sp.len() < 6 {
+            return Err(GeomError::ParseError(line.to_owned()));
+        }
+        let atomic_number = sp[2]
+            .parse()
+            .map_err(|_| GeomError::ParseError(line.to_owned()))?;
+        let mut coord = [0.0; 3];
+        for (c, s) in coord.iter_mut().zip(&sp[3..6]) {
+            *c = s
+                .parse::<f64>()
+                .map_err(|_| GeomError::ParseError(line.to_owned()))?;
+        }
+        atoms.push(Atom::new(atomic_number, coord[0], coord[1], coord[2]));
+    }
+    Ok(Cartesian::new(atoms, unit))
+}
+
+/// write `atoms`, whose coordinates are already in `unit`, as the `[Atoms]`
+/// section of a Molden file
+pub fn write_molden(atoms: &[Atom], unit: Unit) -> String {
+    let mut ret = String::with_capacity(70 * (atoms.len() + 2));
+    writeln!(ret, "[Molden Format]").unwrap();
+    match unit {
+        Unit::Angstrom => writeln!(ret, "[Atoms] Angs"),
+        Unit::Bohr => writeln!(ret, "[Atoms] AU"),
+    }
+    .unwrap();
+    for (i, atom) in atoms.iter().enumerate() {
+        writeln!(
+            ret,
+            "{:<4}{:5}{:5}{:20.10}{:20.10}{:20.10}",
+            atom.label(),
+            i + 1,
+            atom.atomic_number,
+            atom.x,
+            atom.y,
+            atom.z
+        )
+        .unwrap();
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn water() -> Vec<Atom> {
+        vec![
+            Atom::new(1, 0.0000000000, 0.7574590974, 0.5217905143),
+            Atom::new(8, 0.0000000000, 0.0000000000, -0.0657441568),
+            Atom::new(1, 0.0000000000, -0.7574590974, 0.5217905143),
+        ]
+    }
+
+    /// the MOL and PDB formats only carry a few decimal places, so compare
+    /// with a looser tolerance than [Atom]'s PartialEq
+    fn close(got: &[Atom], want: &[Atom], eps: f64) {
+        assert_eq!(got.len(), want.len());
+        for (g, w) in got.iter().zip(want) {
+            assert_eq!(g.atomic_number, w.atomic_number);
+            for (a, b) in [(g.x, w.x), (g.y, w.y), (g.z, w.z)] {
+                assert!((a - b).abs() < eps, "{g:?} != {w:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn xyz() {
+        let got = read_xyz(&write_xyz(&water(), "water")).unwrap();
+        assert_eq!(got, water());
+    }
+
+    #[test]
+    fn sdf() {
+        let s = write_sdf(&water(), "water");
+        close(&read_sdf(&s).unwrap(), &water(), 1e-4);
+    }
+
+    #[test]
+    fn pdb() {
+        let s = write_pdb(&water());
+        close(&read_pdb(&s).unwrap(), &water(), 1e-3);
+    }
+
+    #[test]
+    fn mol2() {
+        let s = write_mol2(&water(), "water");
+        close(&read_mol2(&s).unwrap(), &water(), 1e-6);
+        let got = read(&s, Format::Mol2).unwrap();
+        assert_eq!(write(&got, Format::Mol2).unwrap(), s.replace("water", ""));
+    }
+
+    #[test]
+    fn mol2_types() {
+        let s = std::fs::read_to_string("testfiles/mol2/water.mol2").unwrap();
+        close(&read_mol2(&s).unwrap(), &water(), 1e-4);
+    }
+
+    #[test]
+    fn molden() {
+        let s = write_molden(&water(), Unit::Angstrom);
+        let got = read_molden(&s, Unit::Bohr).unwrap();
+        assert_eq!(got, Cartesian::new(water(), Unit::Angstrom));
+    }
+
+    #[test]
+    fn molden_trailing_blank() {
+        let s =
+            std::fs::read_to_string("testfiles/molden/trailing_blank.molden")
+                .unwrap();
+        let got = read_molden(&s, Unit::Angstrom).unwrap();
+        assert_eq!(got.into_angstrom(), water());
+    }
+
+    #[test]
+    fn cfour_molden() {
+        let s = std::fs::read_to_string("testfiles/cfour/MOLDEN").unwrap();
+        let got = read_molden(&s, Unit::Angstrom).unwrap();
+        assert_eq!(got.unit, Unit::Bohr);
+        let got = got.into_angstrom();
+        let want = vec![
+            Atom::new(8, -0.0000000000, 0.0000000000, 0.06580655821884736),
+            Atom::new(1, 0.0000000000, -0.7531598119360652, -0.522198915512423),
+            Atom::new(1, 0.0000000000, 0.7531598119360652, -0.5221989155124239),
+        ];
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn zmat() {
+        let geom = Geom::Zmat("H\nO 1 OH\n\nOH = 1.0\n".to_owned());
+        assert_eq!(write(&geom, Format::Xyz), Err(GeomError::NotCartesian));
+    }
+}
@@ -0,0 +1,220 @@
+//! detection of displaced geometries that are related by a symmetry operation
+//! of a reference structure, and therefore have the same energy
+
+use std::{collections::HashMap, f64::consts::TAU};
+
+use symm::{atom::Atom, Axis, Molecule, Plane, PointGroup};
+
+use super::Geom;
+
+type Mat = [[f64; 3]; 3];
+
+const IDENTITY: Mat = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// a symmetry operation of a reference geometry, as an orthogonal matrix
+/// acting on Cartesian coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Op {
+    matrix: Mat,
+}
+
+impl Op {
+    /// the matrix of the operation, which is a proper rotation if its
+    /// determinant is 1 and an improper one if it is -1
+    pub fn matrix(&self) -> Mat {
+        self.matrix
+    }
+
+    pub fn is_identity(&self) -> bool {
+        close(&self.matrix, &IDENTITY)
+    }
+
+    pub fn apply(&self, atoms: &[Atom]) -> Vec<Atom> {
+        atoms
+            .iter()
+            .map(|a| {
+                let [x, y, z] = mul_vec(&self.matrix, [a.x, a.y, a.z]);
+                Atom::new(a.atomic_number, x, y, z)
+            })
+            .collect()
+    }
+}
+
+/// the symmetry of a reference geometry
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symmetry {
+    /// the point group as detected by [Molecule::point_group_approx]
+    pub point_group: PointGroup,
+
+    /// every operation of `point_group`, starting with the identity. the
+    /// order of the point group is the length of this
+    pub ops: Vec<Op>,
+}
+
+impl Symmetry {
+    /// determine the point group of `reference` with [symm], comparing
+    /// coordinates to within `tol`. like [symm], this only finds symmetry
+    /// elements along the Cartesian axes, so the reference should be in a
+    /// standard orientation with its center of mass at the origin. point
+    /// groups whose elements aren't known here are treated as C1
+    pub fn new(reference: &[Atom], tol: f64) -> Self {
+        let point_group =
+            Molecule::new(reference.to_vec()).point_group_approx(tol);
+        let mut ops = vec![IDENTITY];
+        for g in generators(&point_group) {
+            if !ops.iter().any(|o| close(o, &g)) {
+                ops.push(g);
+            }
+        }
+        // the generators don't include powers like C3^2 or products like the
+        // other vertical planes of C3v, so close the set under composition
+        let mut i = 0;
+        while i < ops.len() {
+            for j in 0..=i {
+                for m in [mul(&ops[i], &ops[j]), mul(&ops[j], &ops[i])] {
+                    if !ops.iter().any(|o| close(o, &m)) {
+                        ops.push(m);
+                    }
+                }
+            }
+            i += 1;
+        }
+        let ops = ops.into_iter().map(|matrix| Op { matrix }).collect();
+        Self { point_group, ops }
+    }
+
+    /// for each geometry in `geoms`, return the index of an earlier geometry
+    /// that it can be mapped onto by one of `self.ops`, or `None` if it is
+    /// unique. coordinates are compared by rounding them to multiples of
+    /// `tol`, so equivalent geometries very close to a rounding boundary may
+    /// occasionally be missed. Z-matrix geometries are always considered
+    /// unique
+    pub fn equivalent_geoms(
+        &self,
+        geoms: &[Geom],
+        tol: f64,
+    ) -> Vec<Option<usize>> {
+        let mut seen: HashMap<Vec<(usize, [i64; 3])>, usize> = HashMap::new();
+        let mut ret = Vec::with_capacity(geoms.len());
+        for (i, geom) in geoms.iter().enumerate() {
+            let Geom::Xyz(atoms) = geom else {
+                ret.push(None);
+                continue;
+            };
+            let found = self
+                .ops
+                .iter()
+                .find_map(|op| seen.get(&key(&op.apply(atoms), tol)).copied());
+            if found.is_none() {
+                seen.insert(key(atoms, tol), i);
+            }
+            ret.push(found);
+        }
+        ret
+    }
+}
+
+/// the symmetry elements of `pg` as matrices, which generate the whole group
+fn generators(pg: &PointGroup) -> Vec<Mat> {
+    match *pg {
+        PointGroup::C1 => Vec::new(),
+        PointGroup::C2 { axis } => vec![rotation(axis, 2)],
+        PointGroup::Cs { plane } => vec![reflection(plane)],
+        PointGroup::C2v { axis, planes } => {
+            let [p1, p2] = planes;
+            vec![rotation(axis, 2), reflection(p1), reflection(p2)]
+        }
+        PointGroup::C3v { axis, plane } => {
+            vec![rotation(axis, 3), reflection(plane)]
+        }
+        PointGroup::C2h { axis, plane } => {
+            vec![rotation(axis, 2), reflection(plane)]
+        }
+        PointGroup::D2h { axes, planes } => axes
+            .into_iter()
+            .map(|a| rotation(a, 2))
+            .chain(planes.into_iter().map(reflection))
+            .collect(),
+        PointGroup::D3h { c3, c2, sh, sv } => vec![
+            rotation(c3, 3),
+            rotation(c2, 2),
+            reflection(sh),
+            reflection(sv),
+        ],
+        PointGroup::D5h { c5, c2, sh, sv } => vec![
+            rotation(c5, 5),
+            rotation(c2, 2),
+            reflection(sh),
+            reflection(sv),
+        ],
+        #[allow(unreachable_patterns)]
+        ref pg => {
+            log::warn!("no symmetry elements known for {pg:?}, using C1");
+            Vec::new()
+        }
+    }
+}
+
+fn index(axis: Axis) -> usize {
+    match axis {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+    }
+}
+
+/// the rotation by a `n`th of a turn about `axis`
+fn rotation(axis: Axis, n: usize) -> Mat {
+    let k = index(axis);
+    let (i, j) = ((k + 1) % 3, (k + 2) % 3);
+    let (s, c) = (TAU / n as f64).sin_cos();
+    let mut ret = IDENTITY;
+    ret[i][i] = c;
+    ret[i][j] = -s;
+    ret[j][i] = s;
+    ret[j][j] = c;
+    ret
+}
+
+/// the reflection through `plane`, which negates the coordinate along the
+/// axis not in the plane
+fn reflection(plane: Plane) -> Mat {
+    let Plane(a, b) = plane;
+    let mut ret = IDENTITY;
+    let k = 3 - index(a) - index(b);
+    ret[k][k] = -1.0;
+    ret
+}
+
+/// a representation of `atoms` that is independent of the order of the atoms,
+/// with coordinates rounded to multiples of `tol`
+fn key(atoms: &[Atom], tol: f64) -> Vec<(usize, [i64; 3])> {
+    let round = |x: f64| (x / tol).round() as i64;
+    let mut ret: Vec<_> = atoms
+        .iter()
+        .map(|a| (a.atomic_number, [round(a.x), round(a.y), round(a.z)]))
+        .collect();
+    ret.sort_unstable();
+    ret
+}
+
+fn close(a: &Mat, b: &Mat) -> bool {
+    a.iter()
+        .flatten()
+        .zip(b.iter().flatten())
+        .all(|(x, y)| (x - y).abs() < 1e-8)
+}
+
+fn mul(a: &Mat, b: &Mat) -> Mat {
+    let mut ret = [[0.0; 3]; 3];
+    for (i, row) in ret.iter_mut().enumerate() {
+        for (j, r) in row.iter_mut().enumerate() {
+            *r = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    ret
+}
+
+fn mul_vec(a: &Mat, v: [f64; 3]) -> [f64; 3] {
+    a.map(|row| row.iter().zip(v).map(|(x, y)| x * y).sum())
+}
@@ -5,21 +5,117 @@ use std::{
 use serde::{Deserialize, Serialize};
 use symm::Atom;
 
-use crate::geom::Geom;
+use crate::geom::{symmetry::Symmetry, Geom, Unit};
 
 pub mod cfour;
 pub mod dftbplus;
+#[cfg(feature = "mock")]
+pub mod fake;
 pub mod molpro;
 pub mod mopac;
+#[cfg(feature = "replay")]
+pub mod replayed;
+
+/// Debye per atomic unit of dipole moment
+pub const AUDEBYE: f64 = 2.54174691;
+
+/// eV per hartree
+pub const HTEV: f64 = 27.2113834;
+
+/// An insertion-ordered map of labeled energies in hartrees, like `"HF"`,
+/// `"MP2"`, or `"CCSD(T)-F12b"`, parsed from a program's output
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Energies(Vec<(String, f64)>);
+
+impl Energies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// insert `energy` with `label`. if `label` is already present, its value
+    /// is replaced but it keeps its original position. this means that the
+    /// last occurrence of a label in an output file wins, as in a geometry
+    /// optimization
+    pub fn insert(&mut self, label: impl Into<String>, energy: f64) {
+        let label = label.into();
+        match self.0.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, e)) => *e = energy,
+            None => self.0.push((label, energy)),
+        }
+    }
+
+    /// return the energy associated with `label`, if present
+    pub fn get(&self, label: &str) -> Option<f64> {
+        self.0.iter().find(|(l, _)| l == label).map(|(_, e)| *e)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.0.iter().map(|(l, e)| (l.as_str(), *e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<S: Into<String>> FromIterator<(S, f64)> for Energies {
+    fn from_iter<T: IntoIterator<Item = (S, f64)>>(iter: T) -> Self {
+        let mut ret = Self::new();
+        for (label, energy) in iter {
+            ret.insert(label, energy);
+        }
+        ret
+    }
+}
+
+impl<S: Into<String>, const N: usize> From<[(S, f64); N]> for Energies {
+    fn from(value: [(S, f64); N]) -> Self {
+        value.into_iter().collect()
+    }
+}
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct ProgramResult {
+    /// the primary energy of the calculation, like the `PBQFF` variable in
+    /// Molpro or the final electronic energy in CFOUR, unless a drain selects
+    /// another one from `energies`
     pub energy: f64,
+
+    /// every labeled energy found in the output, in the order they were first
+    /// encountered
+    #[serde(default)]
+    pub energies: Energies,
+
+    /// the final Cartesian geometry, if the output contained one. this is
+    /// always in Ångström, regardless of the units used by the program itself
     pub cart_geom: Option<Vec<Atom>>,
+
     pub time: f64,
+
+    /// the dipole moment vector in atomic units (e a0), if the output
+    /// contained one
+    #[serde(default)]
+    pub dipole: Option<[f64; 3]>,
+
+    /// atomic partial charges in the order of the atoms in the output
+    #[serde(default)]
+    pub charges: Option<Vec<f64>>,
+
+    /// the energy of the highest occupied molecular orbital in hartrees
+    #[serde(default)]
+    pub homo: Option<f64>,
+
+    /// the energy of the lowest unoccupied molecular orbital in hartrees
+    #[serde(default)]
+    pub lumo: Option<f64>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ProgramError {
     FileNotFound(String),
     ErrorInOutput(String),
@@ -27,6 +123,24 @@ pub enum ProgramError {
     EnergyParseError(String),
     GeomNotFound(String),
     ReadFileError(String, std::io::ErrorKind),
+
+    /// the program exited with a nonzero status, according to the completion
+    /// marker written by the submit script
+    ExitStatus(String, i32),
+
+    /// the output is complete but doesn't contain the dipole moment requested
+    /// by a dipole drain
+    DipoleNotFound(String),
+
+    /// the output is complete but doesn't contain the energy selected by
+    /// [DrainOptions::energy_label]
+    ///
+    /// [DrainOptions::energy_label]: crate::queue::DrainOptions::energy_label
+    LabelNotFound(String),
+
+    /// a value other than the energy, like a charge, an orbital energy, or a
+    /// coordinate, was found in the output but couldn't be parsed
+    ParseError(String),
 }
 
 impl ProgramError {
@@ -37,6 +151,28 @@ impl ProgramError {
     pub fn is_error_in_output(&self) -> bool {
         matches!(self, Self::ErrorInOutput(..))
     }
+
+    /// Returns `true` if the program finished but its output lacks a result
+    /// requested by the drain, so that running it again won't help
+    pub fn is_missing_result(&self) -> bool {
+        matches!(self, Self::DipoleNotFound(_) | Self::LabelNotFound(_))
+    }
+
+    /// the name of the variant, without the file name or other details
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::FileNotFound(_) => "FileNotFound",
+            Self::ErrorInOutput(_) => "ErrorInOutput",
+            Self::EnergyNotFound(_) => "EnergyNotFound",
+            Self::EnergyParseError(_) => "EnergyParseError",
+            Self::GeomNotFound(_) => "GeomNotFound",
+            Self::ReadFileError(..) => "ReadFileError",
+            Self::ExitStatus(..) => "ExitStatus",
+            Self::DipoleNotFound(_) => "DipoleNotFound",
+            Self::LabelNotFound(_) => "LabelNotFound",
+            Self::ParseError(_) => "ParseError",
+        }
+    }
 }
 
 impl Display for ProgramError {
@@ -106,19 +242,71 @@ pub trait Program {
     /// the file extension for the input file
     fn extension(&self) -> String;
 
+    /// a short name for the program that stays the same across builds, used
+    /// to keep the results of different programs apart in caches
+    const KIND: &'static str;
+
     /// molecular charge
     fn charge(&self) -> isize;
 
+    /// the geometry written by `write_input`, if it can be changed in place.
+    /// this lets identical jobs be matched to within a tolerance
+    fn geom_mut(&mut self) -> Option<&mut Geom> {
+        None
+    }
+
+    /// the units in which `write_input` should write Cartesian coordinates.
+    /// [Geom::Xyz] coordinates are converted from Ångström to this unit
+    const INPUT_UNIT: Unit = Unit::Angstrom;
+
+    /// the units of Cartesian coordinates in the output files read by
+    /// `read_output`, or the ones assumed when an output file labels its own
+    /// units. these are converted to Ångström before being stored in
+    /// [ProgramResult::cart_geom]
+    const OUTPUT_UNIT: Unit = Unit::Angstrom;
+
     /// write the input file to the name returned by `filename`
     fn write_input(&mut self, proc: Procedure);
 
     /// read the output file `filename`
     fn read_output(filename: &str) -> Result<ProgramResult, ProgramError>;
 
+    /// read the output files `filenames` at once, returning the same results
+    /// as [Program::read_output] would for each of them. `seen` holds the
+    /// latest modified time of each job's output files when they were last
+    /// read, or `None` if they have to be read regardless. the default reads
+    /// each file separately in parallel, but programs with several output
+    /// files per job can override this to check which files exist in one
+    /// pass, returning `None` for outputs that haven't changed since `seen`
+    /// and updating `seen` for the others
+    fn read_outputs(
+        filenames: &[String],
+        seen: &mut [Option<SystemTime>],
+    ) -> Vec<Option<Result<ProgramResult, ProgramError>>> {
+        use rayon::prelude::*;
+        let _ = seen;
+        filenames
+            .par_iter()
+            .map(|f| Some(Self::read_output(f)))
+            .collect()
+    }
+
     /// Return all the filenames associated with the Program for deletion when
     /// it finishes
     fn associated_files(&self) -> Vec<String>;
 
+    /// the files written by `write_input`, for copying them to a remote
+    /// [crate::queue::transport::Transport]
+    fn input_files(&self) -> Vec<String> {
+        vec![self.infile()]
+    }
+
+    /// the files read by `read_output`, for copying them back from a remote
+    /// [crate::queue::transport::Transport]
+    fn output_files(&self) -> Vec<String> {
+        vec![self.outfile()]
+    }
+
     fn new(
         filename: String,
         template: Template,
@@ -158,12 +346,71 @@ pub trait Program {
         }
         jobs
     }
+
+    /// like [Program::build_jobs], but geometries in `moles` that are related
+    /// to an earlier geometry by one of the operations in `symmetry` are not
+    /// given their own job. instead, their index and `coeff` are attached to
+    /// the job for the equivalent geometry, and the drain copies its result.
+    /// this is only valid for results that are invariant to the symmetry
+    /// operations, so draining these jobs for anything other than single-point
+    /// energies returns [DrainError::SymmetryMerged]
+    ///
+    /// [DrainError::SymmetryMerged]: crate::queue::DrainError::SymmetryMerged
+    #[allow(clippy::too_many_arguments)]
+    fn build_jobs_symm(
+        moles: Vec<Geom>,
+        dir: impl AsRef<Path>,
+        start_index: usize,
+        coeff: f64,
+        job_num: usize,
+        charge: isize,
+        tmpl: Template,
+        symmetry: &Symmetry,
+        tol: f64,
+    ) -> Vec<Job<Self>>
+    where
+        Self: std::marker::Sized,
+    {
+        let equiv = symmetry.equivalent_geoms(&moles, tol);
+        let jobs = Self::build_jobs(
+            moles,
+            dir,
+            start_index,
+            coeff,
+            job_num,
+            charge,
+            tmpl,
+        );
+        // position in ret of the job for each geometry
+        let mut pos: Vec<usize> = Vec::with_capacity(jobs.len());
+        let mut ret: Vec<Job<Self>> = Vec::new();
+        for (job, eq) in jobs.into_iter().zip(equiv) {
+            match eq {
+                Some(j) => {
+                    let k = pos[j];
+                    ret[k].symm_dups.push((job.index, job.coeff));
+                    pos.push(k);
+                }
+                None => {
+                    pos.push(ret.len());
+                    ret.push(job);
+                }
+            }
+        }
+        ret
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Job<P: Program> {
     pub program: P,
+
+    /// the submit script containing this job, once it has been submitted
     pub pbs_file: String,
+
+    /// the queue's id for the submitted job, or empty if it has not been
+    /// submitted yet. this is kept in checkpoints so that a resumed drain can
+    /// reattach to jobs that are still running
     pub job_id: String,
 
     /// the index in the output array to store the result
@@ -174,6 +421,21 @@ pub struct Job<P: Program> {
 
     /// the last modified time of `program`'s output file
     pub(crate) modtime: SystemTime,
+
+    /// additional `(index, coeff)` pairs to store the result under, for
+    /// identical jobs merged into this one
+    #[serde(default)]
+    pub(crate) dups: Vec<(usize, f64)>,
+
+    /// like `dups`, but for jobs whose geometries are only related to this
+    /// one by a symmetry operation
+    #[serde(default)]
+    pub(crate) symm_dups: Vec<(usize, f64)>,
+
+    /// the latest modified time of the output files when they were last read,
+    /// as updated by [Program::read_outputs]
+    #[serde(skip)]
+    pub(crate) seen: Option<SystemTime>,
 }
 
 impl<P: Program> Job<P> {
@@ -185,6 +447,9 @@ impl<P: Program> Job<P> {
             index,
             coeff: 1.0,
             modtime: SystemTime::UNIX_EPOCH,
+            dups: Vec::new(),
+            symm_dups: Vec::new(),
+            seen: None,
         }
     }
 
@@ -200,6 +465,25 @@ impl<P: Program> Job<P> {
     }
 }
 
+/// return the HOMO and LUMO energies from a sequence of orbital `energies` and
+/// a parallel sequence of flags indicating whether each orbital is `occupied`.
+/// the orbitals do not need to be sorted by energy
+fn frontier_orbitals(
+    energies: impl IntoIterator<Item = f64>,
+    occupied: impl IntoIterator<Item = bool>,
+) -> (Option<f64>, Option<f64>) {
+    let mut homo: Option<f64> = None;
+    let mut lumo: Option<f64> = None;
+    for (e, occ) in energies.into_iter().zip(occupied) {
+        if occ {
+            homo = Some(homo.map_or(e, |h| h.max(e)));
+        } else {
+            lumo = Some(lumo.map_or(e, |l| l.min(e)));
+        }
+    }
+    (homo, lumo)
+}
+
 /// parses the `nth` field of `line` into a float and returns
 /// [ProgramError::EnergyParseError] containing `outname` if it fails. a string
 /// containing `outname` is allocated in the Err case
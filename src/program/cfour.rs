@@ -6,15 +6,15 @@ use std::{
 
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use symm::{Atom, ANGBOHR};
 
 use crate::{
-    geom::Geom,
+    geom::{io, Geom, Unit},
     queue::{local::Local, pbs::Pbs, slurm::Slurm, Queue, Submit},
 };
 
 use super::{
-    parse_energy, Procedure, Program, ProgramError, ProgramResult, Template,
+    parse_energy, Energies, Procedure, Program, ProgramError, ProgramResult,
+    Template,
 };
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -28,6 +28,8 @@ pub struct Cfour {
 }
 
 static CELL: OnceLock<[Regex; 4]> = OnceLock::new();
+static ENERGY_CELL: OnceLock<[Regex; 5]> = OnceLock::new();
+static PROPS_CELL: OnceLock<[Regex; 4]> = OnceLock::new();
 
 impl Program for Cfour {
     fn filename(&self) -> String {
@@ -35,7 +37,7 @@ impl Program for Cfour {
     }
 
     fn infile(&self) -> String {
-        todo!()
+        format!("{}/ZMAT", self.filename)
     }
 
     fn set_filename(&mut self, filename: &str) {
@@ -51,10 +53,23 @@ impl Program for Cfour {
         String::new()
     }
 
+    const KIND: &'static str = "cfour";
+
     fn charge(&self) -> isize {
         self.charge
     }
 
+    fn geom_mut(&mut self) -> Option<&mut Geom> {
+        Some(&mut self.geom)
+    }
+
+    /// CFOUR reads Cartesian coordinates in Ångström unless UNITS=BOHR is
+    /// given in the template
+    const INPUT_UNIT: Unit = Unit::Angstrom;
+
+    /// the MOLDEN file written by CFOUR labels its coordinates `AU`
+    const OUTPUT_UNIT: Unit = Unit::Bohr;
+
     /// Example [Template]:
     /// ```text
     /// comment line
@@ -70,7 +85,10 @@ impl Program for Cfour {
         // always just paste in the geometry, assume it's a zmat for
         // optimization and cartesian for single point
         body = body
-            .replace("{{.geom}}", &self.geom.to_string())
+            .replace(
+                "{{.geom}}",
+                &self.geom.to_unit(Self::INPUT_UNIT).to_string(),
+            )
             .replace("{{.charge}}", &format!("CHARGE={}", self.charge));
         match proc {
             Procedure::Opt => {
@@ -120,6 +138,27 @@ impl Program for Cfour {
                 Regex::new(r"The final electronic energy is").unwrap(),
             ]
         });
+        let [scf_re, mp2_re, cc_re, cc_total_re, triples_re] = ENERGY_CELL
+            .get_or_init(|| {
+                [
+                    Regex::new(r"^\s*E\(SCF\)\s*=\s*(\S+)").unwrap(),
+                    Regex::new(r"^\s*Total MP2 energy\s*=\s*(\S+)").unwrap(),
+                    Regex::new(r"^\s*(\S+) energy will be calculated").unwrap(),
+                    Regex::new(r"^\s*The total energy is\s+(\S+)").unwrap(),
+                    Regex::new(r"^\s*CCSD\(T\) energy\s+(\S+)").unwrap(),
+                ]
+            });
+
+        let [dipole_re, component_re, eig_re, pop_re] =
+            PROPS_CELL.get_or_init(|| {
+                [
+                    Regex::new(r"^\s*Total dipole moment").unwrap(),
+                    // z     -0.75840088     -1.92766311, in au and Debye
+                    Regex::new(r"^\s*([xyz])\s+(\S+)\s+\S+\s*$").unwrap(),
+                    Regex::new(r"ORBITAL EIGENVALUES \(ALPHA\)").unwrap(),
+                    Regex::new(r"Population analysis by atoms").unwrap(),
+                ]
+            });
 
         if panic_re.is_match(&contents) {
             panic!("panic requested in read_output");
@@ -129,10 +168,96 @@ impl Program for Cfour {
 
         // main output
         let mut energy = None;
+        let mut energies = Energies::new();
+        // the coupled cluster method announced by a line like "CCSD energy
+        // will be calculated", used to label the following total energy
+        let mut cc_label = String::from("CC");
+        let parse_labeled = |s: &str| {
+            s.parse::<f64>()
+                .map_err(|_| ProgramError::EnergyParseError(outname.clone()))
+        };
+        let parse = |s: &str| {
+            s.parse::<f64>()
+                .map_err(|_| ProgramError::ParseError(outname.clone()))
+        };
+        /// the multi-line property blocks
+        enum Section {
+            None,
+            Dipole,
+            /// `occupied` is false after the +++ line separating the occupied
+            /// and virtual orbitals
+            Eigenvalues {
+                occupied: bool,
+            },
+            Population,
+        }
+        let mut section = Section::None;
+        let mut dipole = None;
+        let mut dip = [0.0; 3];
+        let mut homo = None;
+        let mut lumo = None;
+        let mut orbital = None;
+        let mut populations = Vec::new();
         let mut time = None;
         let mut next_time = false;
         for line in contents.lines() {
-            if time_re.is_match(line) {
+            match section {
+                Section::None => {}
+                Section::Dipole => {
+                    // only nonzero components are printed
+                    if let Some(caps) = component_re.captures(line) {
+                        let i = (caps[1].as_bytes()[0] - b'x') as usize;
+                        dip[i] = parse(&caps[2])?;
+                    } else if line.contains("Conversion factor") {
+                        dipole = Some(dip);
+                        section = Section::None;
+                    }
+                    continue;
+                }
+                Section::Eigenvalues { occupied } => {
+                    // rows like
+                    //  5  24  -0.5047726695  -13.7355626397  B1  B1 (2)
+                    let sp: Vec<_> = line.split_ascii_whitespace().collect();
+                    if line.trim_start().starts_with("+++") {
+                        homo = orbital;
+                        section = Section::Eigenvalues { occupied: false };
+                    } else if sp.len() > 3 && sp[0].parse::<usize>().is_ok() {
+                        let e = parse(sp[2])?;
+                        if occupied {
+                            orbital = Some(e);
+                        } else {
+                            lumo = Some(e);
+                            section = Section::None;
+                        }
+                    }
+                    continue;
+                }
+                Section::Population => {
+                    // rows like "1      8.48039114" between dashed lines
+                    let sp: Vec<_> = line.split_ascii_whitespace().collect();
+                    if let [center, pop] = sp[..] {
+                        if center.parse::<usize>().is_ok() {
+                            populations.push(parse(pop)?);
+                        }
+                    } else if line.trim_start().starts_with("---")
+                        && !populations.is_empty()
+                    {
+                        section = Section::None;
+                    }
+                    continue;
+                }
+            }
+            if dipole_re.is_match(line) {
+                dip = [0.0; 3];
+                section = Section::Dipole;
+            } else if eig_re.is_match(line) {
+                orbital = None;
+                section = Section::Eigenvalues { occupied: true };
+            } else if pop_re.is_match(line) {
+                // keep only the last population analysis
+                populations.clear();
+                section = Section::Population;
+            } else if time_re.is_match(line) {
                 next_time = true;
             } else if next_time {
                 next_time = false;
@@ -148,30 +273,25 @@ impl Program for Cfour {
                 );
             } else if energy_re.is_match(line) {
                 energy = parse_energy(line, 5, &outname)?;
+            } else if let Some(caps) = scf_re.captures(line) {
+                energies.insert("SCF", parse_labeled(&caps[1])?);
+            } else if let Some(caps) = mp2_re.captures(line) {
+                energies.insert("MP2", parse_labeled(&caps[1])?);
+            } else if let Some(caps) = cc_re.captures(line) {
+                cc_label = caps[1].to_owned();
+            } else if let Some(caps) = cc_total_re.captures(line) {
+                energies.insert(cc_label.as_str(), parse_labeled(&caps[1])?);
+            } else if let Some(caps) = triples_re.captures(line) {
+                energies.insert("CCSD(T)", parse_labeled(&caps[1])?);
             }
         }
 
-        // read xyz. TODO we only need to do this if it's an optimization
+        // read xyz. TODO we only need to do this if it's an optimization.
         let geomfile = path.join("MOLDEN");
-        let cart_geom = if let Ok(s) = std::fs::read_to_string(geomfile) {
-            // skip [Molden Format] and [ATOMS] lines
-            let mut atoms = Vec::new();
-            for line in s.lines().skip(2) {
-                if line.starts_with("[Molden Format]") {
-                    break;
-                }
-                let mut sp = line.split_ascii_whitespace();
-                atoms.push(Atom::new(
-                    sp.nth(2).unwrap().parse().unwrap(),
-                    sp.next().unwrap().parse::<f64>().unwrap() * ANGBOHR,
-                    sp.next().unwrap().parse::<f64>().unwrap() * ANGBOHR,
-                    sp.next().unwrap().parse::<f64>().unwrap() * ANGBOHR,
-                ));
-            }
-            Some(atoms)
-        } else {
-            None
-        };
+        let cart_geom = std::fs::read_to_string(geomfile)
+            .ok()
+            .and_then(|s| io::read_molden(&s, Self::OUTPUT_UNIT).ok())
+            .map(|c| c.into_angstrom());
 
         let Some(energy) = energy else {
             return Err(ProgramError::EnergyNotFound(outname));
@@ -184,13 +304,35 @@ impl Program for Cfour {
             return Err(ProgramError::EnergyNotFound(outname));
         };
 
+        // CFOUR prints electron populations, so subtract them from the
+        // nuclear charges to get the partial charges
+        let charges = match &cart_geom {
+            Some(atoms) if atoms.len() == populations.len() => Some(
+                atoms
+                    .iter()
+                    .zip(&populations)
+                    .map(|(atom, pop)| atom.atomic_number as f64 - pop)
+                    .collect(),
+            ),
+            _ => None,
+        };
+
         Ok(ProgramResult {
             energy,
+            energies,
             cart_geom,
             time,
+            dipole,
+            charges,
+            homo,
+            lumo,
         })
     }
 
+    fn output_files(&self) -> Vec<String> {
+        vec![format!("{}/output.dat", self.filename)]
+    }
+
     fn associated_files(&self) -> Vec<String> {
         [
             "ECPDATA",
@@ -240,7 +382,7 @@ impl Queue<Cfour> for Pbs {
 #PBS -o {{.filename}}.out
 #PBS -W umask=022
 #PBS -l walltime=1000:00:00
-#PBS -l ncpus=1
+#PBS -l ncpus={{.cpus}}
 #PBS -l mem=8gb
 #PBS -q workq
 
@@ -289,7 +431,9 @@ impl Queue<Cfour> for Local {
 mod tests {
     use std::str::FromStr;
 
-    use crate::check;
+    use symm::Atom;
+
+    use crate::{check, geom::Cartesian};
 
     use super::*;
 
@@ -298,6 +442,11 @@ mod tests {
         let got = Cfour::read_output("testfiles/cfour").unwrap();
         let want = ProgramResult {
             energy: -76.33801063048065,
+            energies: Energies::from([
+                ("SCF", -76.057245862792),
+                ("MP2", -76.332251551900),
+                ("CCSD", -76.33801063048065),
+            ]),
             cart_geom: Some(vec![
                 Atom::new(8, -0.0000000000, 0.0000000000, 0.06580655821884736),
                 Atom::new(
@@ -314,10 +463,52 @@ mod tests {
                 ),
             ]),
             time: 55.263,
+            dipole: Some([0.0, 0.0, -0.75840088]),
+            charges: Some(vec![
+                8.0 - 8.45991326,
+                1.0 - 0.77004337,
+                1.0 - 0.77004337,
+            ]),
+            homo: Some(-0.5047726695),
+            lumo: Some(0.1423558880),
         };
         assert_eq!(got, want);
     }
 
+    /// a geometry written to MOLDEN in CFOUR's Bohr comes back in Ångström
+    #[test]
+    fn read_output_units() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::copy(
+            "testfiles/cfour/output.dat",
+            dir.path().join("output.dat"),
+        )
+        .unwrap();
+        let Geom::Xyz(atoms) = Geom::from_str(
+            "O 0.0 0.0 0.1173\nH 0.0 -0.7572 -0.4692\nH 0.0 0.7572 -0.4692",
+        )
+        .unwrap() else {
+            unreachable!()
+        };
+        let bohr = Cartesian::new(atoms.clone(), Unit::Angstrom)
+            .into_unit(Cfour::OUTPUT_UNIT);
+        assert_ne!(bohr.atoms, atoms);
+        std::fs::write(
+            dir.path().join("MOLDEN"),
+            io::write_molden(&bohr.atoms, bohr.unit),
+        )
+        .unwrap();
+        let got = Cfour::read_output(dir.path().to_str().unwrap()).unwrap();
+        let got = got.cart_geom.unwrap();
+        assert_eq!(got.len(), atoms.len());
+        for (g, w) in got.iter().zip(&atoms) {
+            assert_eq!(g.atomic_number, w.atomic_number);
+            for (a, b) in [(g.x, w.x), (g.y, w.y), (g.z, w.z)] {
+                assert!((a - b).abs() < 1e-9, "{a} != {b}");
+            }
+        }
+    }
+
     #[test]
     fn write_input() {
         let template = Template::from(
@@ -9,15 +9,21 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use symm::Atom;
 
-use crate::{geom::Geom, program::Procedure};
+use crate::{
+    geom::{Cartesian, Geom, Unit},
+    program::Procedure,
+};
 
-use super::{parse_energy, Program, ProgramError, ProgramResult, Template};
+use super::{
+    frontier_orbitals, parse_energy, Energies, Program, ProgramError,
+    ProgramResult, Template, HTEV,
+};
 
 #[cfg(test)]
 mod tests;
 
 static INPUT_CELL: OnceLock<[Regex; 3]> = OnceLock::new();
-static CELL: OnceLock<[Regex; 5]> = OnceLock::new();
+static CELL: OnceLock<[Regex; 6]> = OnceLock::new();
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct DFTBPlus {
@@ -35,7 +41,7 @@ impl Program for DFTBPlus {
     }
 
     fn infile(&self) -> String {
-        todo!()
+        format!("{}/dftb_in.hsd", self.filename)
     }
 
     fn set_filename(&mut self, filename: &str) {
@@ -52,10 +58,22 @@ impl Program for DFTBPlus {
         String::new()
     }
 
+    const KIND: &'static str = "dftb+";
+
     fn charge(&self) -> isize {
         self.charge
     }
 
+    fn geom_mut(&mut self) -> Option<&mut Geom> {
+        Some(&mut self.geom)
+    }
+
+    /// the xyz geometry in the HSD input is read in Ångström
+    const INPUT_UNIT: Unit = Unit::Angstrom;
+
+    /// geom.out.xyz is written in Ångström
+    const OUTPUT_UNIT: Unit = Unit::Angstrom;
+
     /// Example [Template]:
     /// ```text
     /// Geometry = xyzFormat {
@@ -158,7 +176,7 @@ impl Program for DFTBPlus {
                 }
             }
         }
-        let geom = match &self.geom {
+        let geom = match &*self.geom.to_unit(Self::INPUT_UNIT) {
             Geom::Zmat(_) => {
                 panic!("don't know how to handle a Z-matrix in dftb+");
             }
@@ -195,8 +213,8 @@ impl Program for DFTBPlus {
             }
         };
 
-        let [panic_re, error_re, time_re, energy_re, geom_warn] = CELL
-            .get_or_init(|| {
+        let [panic_re, error_re, time_re, energy_re, geom_warn, labeled_re] =
+            CELL.get_or_init(|| {
                 trace!("initializing dftb+ output regexes");
                 [
                     Regex::new("(?i)panic").unwrap(),
@@ -204,6 +222,9 @@ impl Program for DFTBPlus {
                     Regex::new(r"^Total\s+=\s+").unwrap(),
                     Regex::new(r"^Total Energy: ").unwrap(),
                     Regex::new(r"Geometry did NOT converge!").unwrap(),
+                    // lines like
+                    // Force related energy:  -3.9798793068 H   -108.2980 eV
+                    Regex::new(r"(?i)^([^:]*energy):\s+(\S+) H\b").unwrap(),
                 ]
             });
 
@@ -219,6 +240,7 @@ impl Program for DFTBPlus {
 
         // main output
         let mut energy = None;
+        let mut energies = Energies::new();
         let mut time = None;
         for line in contents.lines() {
             if time_re.is_match(line) {
@@ -232,6 +254,13 @@ impl Program for DFTBPlus {
             } else if energy_re.is_match(line) {
                 energy = parse_energy(line, 2, &outname)?;
             }
+            // the primary "Total Energy" is also stored as a labeled energy
+            if let Some(caps) = labeled_re.captures(line) {
+                let Ok(e) = caps[2].parse() else {
+                    return Err(ProgramError::EnergyParseError(outname));
+                };
+                energies.insert(&caps[1], e);
+            }
         }
 
         // read xyz. TODO we only need to do this if it's an optimization
@@ -251,7 +280,7 @@ impl Program for DFTBPlus {
                     sp.next().unwrap().parse().unwrap(),
                 ));
             }
-            Some(atoms)
+            Some(Cartesian::new(atoms, Self::OUTPUT_UNIT).into_angstrom())
         } else {
             None
         };
@@ -267,13 +296,27 @@ impl Program for DFTBPlus {
             return Err(ProgramError::EnergyNotFound(outname));
         };
 
+        let (dipole, charges) = read_detailed(&path.join("detailed.out"));
+        let (homo, lumo) = read_band(&path.join("band.out"));
+
         Ok(ProgramResult {
             energy,
+            energies,
             cart_geom,
             time,
+            dipole,
+            charges,
+            homo,
+            lumo,
         })
     }
 
+    fn output_files(&self) -> Vec<String> {
+        ["out", "geom.out.xyz", "detailed.out", "band.out"]
+            .map(|f| format!("{}/{f}", self.filename))
+            .into()
+    }
+
     fn associated_files(&self) -> Vec<String> {
         vec![
             "charges.bin".to_owned(),
@@ -300,3 +343,73 @@ impl Program for DFTBPlus {
         }
     }
 }
+
+/// read the dipole moment in atomic units and the atomic gross charges from
+/// DFTB+'s `detailed.out` file, if it exists. the relevant sections look like
+///
+/// ```text
+///  Atomic gross charges (e)
+///  Atom           Charge
+///     1      -0.59253010
+///     2       0.29626505
+///
+/// Dipole moment:    0.00000000   -0.59478843    0.00000000 au
+/// ```
+fn read_detailed(path: &Path) -> (Option<[f64; 3]>, Option<Vec<f64>>) {
+    let Ok(s) = read_to_string(path) else {
+        return (None, None);
+    };
+    let mut dipole = None;
+    let mut charges = Vec::new();
+    let mut lines = s.lines();
+    while let Some(line) = lines.next() {
+        if line.starts_with("Dipole moment:") && line.trim_end().ends_with("au")
+        {
+            let mut dip = [0.0; 3];
+            let vals = line.split_ascii_whitespace().skip(2);
+            for (d, v) in dip.iter_mut().zip(vals) {
+                let Ok(v) = v.parse() else {
+                    return (None, None);
+                };
+                *d = v;
+            }
+            dipole = Some(dip);
+        } else if line.trim_start().starts_with("Atomic gross charges") {
+            charges.clear();
+            // skip the Atom Charge header and stop at the blank line
+            for line in lines.by_ref().skip(1) {
+                let Some(q) = line.split_ascii_whitespace().nth(1) else {
+                    break;
+                };
+                let Ok(q) = q.parse() else {
+                    break;
+                };
+                charges.push(q);
+            }
+        }
+    }
+    (dipole, (!charges.is_empty()).then_some(charges))
+}
+
+/// read the HOMO and LUMO energies in hartrees from DFTB+'s `band.out` file,
+/// if it exists. each line gives an eigenvalue in eV and its occupation, and
+/// an orbital is considered occupied if its occupation is greater than zero
+fn read_band(path: &Path) -> (Option<f64>, Option<f64>) {
+    let Ok(s) = read_to_string(path) else {
+        return (None, None);
+    };
+    let mut energies = Vec::new();
+    let mut occupied = Vec::new();
+    for line in s.lines() {
+        let sp: Vec<_> = line.split_ascii_whitespace().collect();
+        // the last two fields are the energy and occupation, optionally
+        // preceded by the orbital index
+        if let [.., e, occ] = sp[..] {
+            if let (Ok(e), Ok(occ)) = (e.parse::<f64>(), occ.parse::<f64>()) {
+                energies.push(e / HTEV);
+                occupied.push(occ > 0.0);
+            }
+        }
+    }
+    frontier_orbitals(energies, occupied)
+}
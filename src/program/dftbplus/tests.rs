@@ -134,12 +134,21 @@ fn read_opt_output() {
     let got = DFTBPlus::read_output("testfiles/dftb+/opt").unwrap();
     let want = ProgramResult {
         energy: -4.0779379326,
+        energies: Energies::from([
+            ("Total Energy", -4.0779379326),
+            ("Total Mermin free energy", -4.0779379326),
+            ("Force related energy", -4.0779379326),
+        ]),
         cart_geom: Some(vec![
             Atom::new_from_label("O", 0.00000000, -0.71603315, 0.00000000),
             Atom::new_from_label("H", 0.00000000, -0.14200298, 0.77844804),
             Atom::new_from_label("H", -0.00000000, -0.14200298, -0.77844804),
         ]),
         time: 0.05,
+        dipole: None,
+        charges: None,
+        homo: None,
+        lumo: None,
     };
     assert_eq!(got, want);
 }
@@ -149,8 +158,17 @@ fn read_single_output() {
     let got = DFTBPlus::read_output("testfiles/dftb+/single").unwrap();
     let want = ProgramResult {
         energy: -3.9798793068,
+        energies: Energies::from([
+            ("Total Energy", -3.9798793068),
+            ("Total Mermin free energy", -3.9798793068),
+            ("Force related energy", -3.9798793068),
+        ]),
         cart_geom: None,
         time: 0.03,
+        dipole: Some([0.0, -0.59478843, 0.0]),
+        charges: Some(vec![-0.59253010, 0.29626505, 0.29626505]),
+        homo: Some(-8.013 / HTEV),
+        lumo: Some(3.654 / HTEV),
     };
     assert_eq!(got, want);
 }
@@ -0,0 +1,132 @@
+//! a stand-in for a quantum chemistry program, whose outputs are written by
+//! [crate::queue::mock::Mock] instead of by running anything. requires the
+//! `mock` feature
+
+use std::{fs::read_to_string, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::geom::Geom;
+
+use super::{Procedure, Program, ProgramError, ProgramResult, Template};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Fake {
+    filename: String,
+    template: Template,
+    charge: isize,
+    geom: Geom,
+}
+
+impl Fake {
+    /// the contents of a successful output file with `energy`, a run `time`
+    /// in seconds, and the final geometry `geom`
+    pub fn output(energy: f64, time: f64, geom: &Geom) -> String {
+        format!("energy = {energy}\ntime = {time}\ngeometry\n{geom}")
+    }
+
+    /// the contents of an output file reporting an error
+    pub fn error_output(msg: &str) -> String {
+        format!("ERROR: {msg}\n")
+    }
+}
+
+impl Program for Fake {
+    fn filename(&self) -> String {
+        self.filename.clone()
+    }
+
+    fn infile(&self) -> String {
+        format!("{}.{}", self.filename, self.extension())
+    }
+
+    fn set_filename(&mut self, filename: &str) {
+        self.filename = String::from(filename);
+    }
+
+    fn template(&self) -> &Template {
+        &self.template
+    }
+
+    fn extension(&self) -> String {
+        String::from("inp")
+    }
+
+    const KIND: &'static str = "fake";
+
+    fn charge(&self) -> isize {
+        self.charge
+    }
+
+    fn geom_mut(&mut self) -> Option<&mut Geom> {
+        Some(&mut self.geom)
+    }
+
+    /// write only the geometry, which is all that
+    /// [crate::queue::mock::Mock] needs to produce the output
+    fn write_input(&mut self, _proc: Procedure) {
+        let geom = self.geom.to_unit(Self::INPUT_UNIT);
+        if let Err(e) = std::fs::write(self.infile(), geom.to_string()) {
+            panic!("failed to write {} with {e}", self.infile());
+        }
+    }
+
+    fn read_output(filename: &str) -> Result<ProgramResult, ProgramError> {
+        let outfile = format!("{filename}.out");
+        let Ok(contents) = read_to_string(&outfile) else {
+            return Err(ProgramError::FileNotFound(outfile));
+        };
+        let mut energy = None;
+        let mut time = 0.0;
+        let mut lines = contents.lines();
+        let mut cart_geom = None;
+        while let Some(line) = lines.next() {
+            if line.starts_with("ERROR") {
+                return Err(ProgramError::ErrorInOutput(outfile));
+            } else if let Some(e) = line.strip_prefix("energy = ") {
+                energy = match e.parse() {
+                    Ok(e) => Some(e),
+                    Err(_) => {
+                        return Err(ProgramError::EnergyParseError(outfile))
+                    }
+                };
+            } else if let Some(t) = line.strip_prefix("time = ") {
+                time = t.parse().unwrap_or_default();
+            } else if line == "geometry" {
+                let rest: Vec<_> = lines.by_ref().collect();
+                cart_geom = Geom::from_str(&rest.join("\n"))
+                    .ok()
+                    .and_then(|g| g.xyz().cloned());
+            }
+        }
+        let Some(energy) = energy else {
+            return Err(ProgramError::EnergyNotFound(outfile));
+        };
+        Ok(ProgramResult {
+            energy,
+            energies: [("fake", energy)].into(),
+            cart_geom,
+            time,
+            ..Default::default()
+        })
+    }
+
+    fn associated_files(&self) -> Vec<String> {
+        let f = &self.filename;
+        vec![format!("{f}.inp"), format!("{f}.out")]
+    }
+
+    fn new(
+        filename: String,
+        template: Template,
+        charge: isize,
+        geom: Geom,
+    ) -> Self {
+        Self {
+            filename,
+            template,
+            charge,
+            geom,
+        }
+    }
+}
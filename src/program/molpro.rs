@@ -6,10 +6,11 @@ use std::{
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use crate::geom::{geom_string, Geom};
+use crate::geom::{geom_string, Cartesian, Geom, Unit};
 
 use super::{
-    parse_energy, Procedure, Program, ProgramError, ProgramResult, Template,
+    parse_energy, Energies, Procedure, Program, ProgramError, ProgramResult,
+    Template,
 };
 
 #[cfg(test)]
@@ -24,6 +25,8 @@ pub struct Molpro {
 }
 
 static CELL: OnceLock<[Regex; 6]> = OnceLock::new();
+static LABEL_CELL: OnceLock<Regex> = OnceLock::new();
+static PROPS_CELL: OnceLock<[Regex; 4]> = OnceLock::new();
 static INPUT_CELL: OnceLock<[Regex; 4]> = OnceLock::new();
 
 impl Program for Molpro {
@@ -57,10 +60,22 @@ impl Program for Molpro {
         String::from("inp")
     }
 
+    const KIND: &'static str = "molpro";
+
     fn charge(&self) -> isize {
         self.charge
     }
 
+    fn geom_mut(&mut self) -> Option<&mut Geom> {
+        Some(&mut self.geom)
+    }
+
+    /// Molpro reads XYZ-style geometry blocks in Ångström
+    const INPUT_UNIT: Unit = Unit::Angstrom;
+
+    /// the "Current geometry" block is printed in Ångström
+    const OUTPUT_UNIT: Unit = Unit::Angstrom;
+
     /// Example [Template]:
     /// ```text
     /// memory,1,g
@@ -144,7 +159,10 @@ impl Program for Molpro {
                 }
                 new_lines
             }
-            x @ Geom::Xyz(_) => format!("{geom}\n}}\n", geom = geom_string(x)),
+            x @ Geom::Xyz(_) => format!(
+                "{geom}\n}}\n",
+                geom = geom_string(&x.to_unit(Self::INPUT_UNIT))
+            ),
         };
         body = geom_re.replace(&body, geom).to_string();
         body = charge
@@ -182,6 +200,27 @@ impl Program for Molpro {
                     Regex::new(r"^ PBQFF\s+=").unwrap(),
                 ]
             });
+        // lines like
+        //  !RHF STATE  1.1 Energy               -76.065152050507
+        //  !CCSD(T)-F12b total energy           -76.369839620286
+        let labeled_re = LABEL_CELL.get_or_init(|| {
+            Regex::new(r"^\s*!(\S+)\s+(?:STATE.*Energy|total energy)\s+(\S+)")
+                .unwrap()
+        });
+
+        let [dipole_re, homo_re, lumo_re, pop_re] =
+            PROPS_CELL.get_or_init(|| {
+                [
+                    // !RHF STATE 1.1 Dipole moment  x  y  z, in atomic units
+                    Regex::new(r"^\s*!.*Dipole moment\s+(\S+)\s+(\S+)\s+(\S+)")
+                        .unwrap(),
+                    // HOMO      1.2    -0.510438 =     -13.8897eV
+                    Regex::new(r"^ HOMO\s+\S+\s+(\S+)").unwrap(),
+                    Regex::new(r"^ LUMO\s+\S+\s+(\S+)").unwrap(),
+                    // header of the Mulliken population analysis from `pop`
+                    Regex::new(r"^ Unique atom.*Charge").unwrap(),
+                ]
+            });
 
         if panic_re.is_match(&contents) {
             panic!("panic requested in read_output");
@@ -189,7 +228,21 @@ impl Program for Molpro {
             return Err(ProgramError::ErrorInOutput(outfile));
         }
 
+        let parse_labeled = |s: &str| {
+            s.parse::<f64>()
+                .map_err(|_| ProgramError::EnergyParseError(outfile.clone()))
+        };
+        let parse = |s: &str| {
+            s.parse::<f64>()
+                .map_err(|_| ProgramError::ParseError(outfile.clone()))
+        };
         let mut energy = None;
+        let mut energies = Energies::new();
+        let mut dipole = None;
+        let mut homo = None;
+        let mut lumo = None;
+        let mut charges = Vec::new();
+        let mut pop = false;
         let mut skip = 0;
         let mut geom = false;
         let mut atoms = Vec::new();
@@ -197,6 +250,18 @@ impl Program for Molpro {
         for line in contents.lines() {
             if skip > 0 {
                 skip -= 1;
+            } else if pop && blank_re.is_match(line) {
+                pop = false;
+            } else if pop {
+                // lines like
+                //   C       3.06722  3.14447  0.02213  6.23382  - 0.23382
+                // with the sign of the charge separated from its magnitude
+                let sp: Vec<_> = line.split_whitespace().collect();
+                let [.., sign, mag] = sp[..] else {
+                    return Err(ProgramError::ParseError(outfile.clone()));
+                };
+                let q = parse(mag)?;
+                charges.push(if sign == "-" { -q } else { q });
             } else if time_re.is_match(line) {
                 time = line
                     .split_ascii_whitespace()
@@ -206,6 +271,22 @@ impl Program for Molpro {
                     .unwrap_or_else(|e| panic!("{e:#?}"));
             } else if energy_re.is_match(line) {
                 energy = parse_energy(line, 2, &outfile)?;
+            } else if let Some(caps) = labeled_re.captures(line) {
+                energies.insert(&caps[1], parse_labeled(&caps[2])?);
+            } else if let Some(caps) = dipole_re.captures(line) {
+                dipole = Some([
+                    parse(&caps[1])?,
+                    parse(&caps[2])?,
+                    parse(&caps[3])?,
+                ]);
+            } else if let Some(caps) = homo_re.captures(line) {
+                homo = Some(parse(&caps[1])?);
+            } else if let Some(caps) = lumo_re.captures(line) {
+                lumo = Some(parse(&caps[1])?);
+            } else if pop_re.is_match(line) {
+                // only keep the last population analysis
+                charges.clear();
+                pop = true;
             } else if geom_re.is_match(line) {
                 skip = 3;
                 geom = true;
@@ -226,11 +307,18 @@ impl Program for Molpro {
             }
         }
 
+        let atoms = Cartesian::new(atoms, Self::OUTPUT_UNIT).into_angstrom();
+
         if let Some(energy) = energy {
             return Ok(ProgramResult {
                 energy,
+                energies,
                 cart_geom: if atoms.is_empty() { None } else { Some(atoms) },
                 time,
+                dipole,
+                charges: (!charges.is_empty()).then_some(charges),
+                homo,
+                lumo,
             });
         }
 
@@ -134,7 +134,7 @@ pub(crate) mod write_input {
 }
 
 mod read_output {
-    use crate::program::ProgramResult;
+    use crate::program::{Energies, ProgramError, ProgramResult};
     use symm::Atom;
 
     use super::*;
@@ -144,6 +144,11 @@ mod read_output {
         let got = Molpro::read_output("testfiles/molpro/opt").unwrap();
         let want = ProgramResult {
             energy: -76.369839620286,
+            energies: Energies::from([
+                ("RHF", -76.065152050507),
+                ("CCSD(T)-F12a", -76.374112665809),
+                ("CCSD(T)-F12b", -76.369839620286),
+            ]),
             cart_geom: Some(vec![
                 //
                 Atom::new_from_label(
@@ -166,19 +171,56 @@ mod read_output {
                 ),
             ]),
             time: 27.13,
+            dipole: Some([0.0, 0.0, 0.78505665]),
+            charges: None,
+            homo: Some(-0.510438),
+            lumo: Some(0.101828),
         };
 
         assert_eq!(got, want);
     }
 
+    /// the Mulliken charges from `pop`, with their signs printed separately
+    #[test]
+    fn charges() {
+        let got = Molpro::read_output("testfiles/molpro/charges").unwrap();
+        assert_eq!(got.charges, Some(vec![-0.58289, 0.29144, 0.29144]));
+        assert_eq!(got.energy, -76.369839620286);
+    }
+
+    #[test]
+    fn bad_charge() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("charges");
+        let base = base.to_str().unwrap();
+        let out = std::fs::read_to_string("testfiles/molpro/charges.out")
+            .unwrap()
+            .replace("- 0.58289", "- 0.5828g");
+        std::fs::write(format!("{base}.out"), out).unwrap();
+        assert_eq!(
+            Molpro::read_output(base),
+            Err(ProgramError::ParseError(format!("{base}.out")))
+        );
+    }
+
     #[test]
     fn dzccr() {
         let got = Molpro::read_output("testfiles/molpro/dzccr");
         let got = got.unwrap_or_else(|e| panic!("{e:#?}"));
         let want = ProgramResult {
             energy: -76.470698498340,
+            energies: Energies::from([
+                ("RHF", -76.077766546670),
+                ("CCSD(T)-F12a", -76.421138674507),
+                ("CCSD(T)-F12b", -76.414582576041),
+                ("CCSD(T)", -76.292725972499),
+            ]),
             cart_geom: None,
             time: 4.73,
+            dipole: Some([-0.00653291, 0.81065561, 0.0]),
+            charges: None,
+            homo: Some(-0.492412),
+            lumo: Some(0.184031),
         };
 
         assert_eq!(got, want);
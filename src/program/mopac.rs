@@ -1,15 +1,21 @@
-use crate::geom::{geom_string, Geom};
+use crate::geom::{geom_string, Cartesian, Geom, Unit};
 use crate::program::{Program, ProgramError};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use symm::Atom;
 
-use super::{Job, Procedure, ProgramResult, Template};
-use std::collections::hash_map::DefaultHasher;
+use super::{
+    frontier_orbitals, Energies, Job, Procedure, ProgramResult, Template,
+    AUDEBYE, HTEV,
+};
+use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::ffi::{OsStr, OsString};
 use std::fs::{read_to_string, File};
 use std::hash::{Hash, Hasher};
 use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
 use std::sync::OnceLock;
+use std::time::SystemTime;
 
 /// kcal/mol per hartree
 pub const KCALHT: f64 = 627.5091809;
@@ -88,6 +94,8 @@ impl Program for Mopac {
         String::from("mop")
     }
 
+    const KIND: &'static str = "mopac";
+
     /// Writes the parameters of self to a parameter file, then writes the MOPAC
     /// input file with external=paramfile. Also update self.paramfile to point
     /// to the generated name for the parameter file
@@ -119,7 +127,7 @@ impl Program for Mopac {
         if self.geom.is_xyz() {
             header.push_str(" XYZ");
         }
-        let geom = geom_string(&self.geom);
+        let geom = geom_string(&self.geom.to_unit(Self::INPUT_UNIT));
         let filename = format!("{}.mop", self.filename);
         let mut file = match File::create(&filename) {
             Ok(f) => f,
@@ -145,27 +153,62 @@ Comment line 2
         if res.is_ok() {
             return res;
         }
-        let outfile = format!("{}.out", &filename);
-        let contents = match read_to_string(&outfile) {
-            Ok(s) => s,
-            Err(_) => {
-                return Err(ProgramError::FileNotFound(outfile));
+        Self::check_out(filename, res)
+    }
+
+    /// scan each directory containing the jobs once, and only open the .aux
+    /// and .out files that exist, for jobs where one of them has changed
+    /// since `seen`
+    fn read_outputs(
+        filenames: &[String],
+        seen: &mut [Option<SystemTime>],
+    ) -> Vec<Option<Result<ProgramResult, ProgramError>>> {
+        use rayon::prelude::*;
+        let mut dirs = HashMap::new();
+        for f in filenames {
+            let dir = parent(f);
+            if !dirs.contains_key(dir) {
+                dirs.insert(dir, scan(dir));
             }
-        };
+        }
+        filenames
+            .par_iter()
+            .zip(seen)
+            .map(|(f, seen)| {
+                let files = &dirs[parent(f)];
+                let name = Path::new(f).file_name().unwrap_or_default();
+                let aux = files.get(&with_ext(name, "aux"));
+                let out = files.get(&with_ext(name, "out"));
+                let latest = [aux, out].into_iter().flatten().flatten().max();
+                if seen.is_some() && seen.as_ref() == latest {
+                    return None;
+                }
+                *seen = latest.copied();
+                let res = match aux {
+                    Some(Some(_)) => Self::read_aux(f),
+                    _ => Err(ProgramError::FileNotFound(format!("{f}.aux"))),
+                };
+                if res.is_ok() {
+                    return Some(res);
+                }
+                if out.is_none() {
+                    let out = format!("{f}.out");
+                    return Some(Err(ProgramError::FileNotFound(out)));
+                }
+                Some(Self::check_out(f, res))
+            })
+            .collect()
+    }
 
-        let [panic, error] = READ_OUT_CELL.get_or_init(|| {
-            [
-                Regex::new("(?i)panic").unwrap(),
-                Regex::new("(?i)error").unwrap(),
-            ]
-        });
+    fn input_files(&self) -> Vec<String> {
+        let mut ret = vec![self.infile()];
+        ret.extend(self.param_file.clone());
+        ret
+    }
 
-        if error.is_match(&contents) {
-            return Err(ProgramError::ErrorInOutput(filename.to_owned()));
-        } else if panic.is_match(&contents) {
-            panic!("panic requested in read_output");
-        }
-        res
+    fn output_files(&self) -> Vec<String> {
+        let fname = self.filename();
+        vec![format!("{fname}.out"), format!("{fname}.aux")]
     }
 
     fn associated_files(&self) -> Vec<String> {
@@ -186,15 +229,96 @@ Comment line 2
         self.charge
     }
 
+    fn geom_mut(&mut self) -> Option<&mut Geom> {
+        Some(&mut self.geom)
+    }
+
+    /// MOPAC reads Cartesian coordinates in Ångström
+    const INPUT_UNIT: Unit = Unit::Angstrom;
+
+    /// ATOM_X_OPT is labeled ANGSTROMS in the aux file
+    const OUTPUT_UNIT: Unit = Unit::Angstrom;
+
     fn infile(&self) -> String {
         self.filename() + ".mop"
     }
 }
 
+/// the directory containing `filename`
+fn parent(filename: &str) -> &Path {
+    match Path::new(filename).parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+fn with_ext(name: &OsStr, ext: &str) -> OsString {
+    let mut ret = name.to_owned();
+    ret.push(".");
+    ret.push(ext);
+    ret
+}
+
+/// the files in `dir`, along with the modified times of the non-empty .aux and
+/// .out files. a directory that can't be read is treated as empty
+fn scan(dir: &Path) -> HashMap<OsString, Option<SystemTime>> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let name = entry.file_name();
+            let ext = Path::new(&name).extension();
+            let modtime =
+                if ext == Some("aux".as_ref()) || ext == Some("out".as_ref()) {
+                    entry
+                        .metadata()
+                        .ok()
+                        .filter(|m| m.len() > 0)
+                        .and_then(|m| m.modified().ok())
+                } else {
+                    None
+                };
+            (name, modtime)
+        })
+        .collect()
+}
+
 static READ_OUT_CELL: OnceLock<[Regex; 2]> = OnceLock::new();
 static READ_AUX_CELL: OnceLock<[Regex; 6]> = OnceLock::new();
+static READ_PROPS_CELL: OnceLock<[Regex; 3]> = OnceLock::new();
 
 impl Mopac {
+    /// check the .out file for `filename` for errors after failing to read its
+    /// .aux file with `res`
+    fn check_out(
+        filename: &str,
+        res: Result<ProgramResult, ProgramError>,
+    ) -> Result<ProgramResult, ProgramError> {
+        let outfile = format!("{}.out", &filename);
+        let contents = match read_to_string(&outfile) {
+            Ok(s) => s,
+            Err(_) => {
+                return Err(ProgramError::FileNotFound(outfile));
+            }
+        };
+
+        let [panic, error] = READ_OUT_CELL.get_or_init(|| {
+            [
+                Regex::new("(?i)panic").unwrap(),
+                Regex::new("(?i)error").unwrap(),
+            ]
+        });
+
+        if error.is_match(&contents) {
+            return Err(ProgramError::ErrorInOutput(filename.to_owned()));
+        } else if panic.is_match(&contents) {
+            panic!("panic requested in read_output");
+        }
+        res
+    }
+
     pub fn new_full(
         filename: String,
         params: Option<Params>,
@@ -261,8 +385,9 @@ impl Mopac {
         write!(file, "{params}").expect("failed to write params file");
     }
 
-    /// return the heat of formation from a MOPAC aux file in Hartrees.
-    /// `filename` should not include the .aux extension
+    /// return the heat of formation from a MOPAC aux file in Hartrees, along
+    /// with the optimized geometry, dipole moment, atomic charges, and frontier
+    /// orbital energies. `filename` should not include the .aux extension
     pub fn read_aux(filename: &str) -> Result<ProgramResult, ProgramError> {
         let auxfile = format!("{}.aux", &filename);
         let Ok(f) = File::open(&auxfile) else {
@@ -281,11 +406,20 @@ impl Mopac {
                     Regex::new("^ CPU_TIME:SEC=").unwrap(),
                 ]
             });
+        let [dip_re, eig_re, occ_re] = READ_PROPS_CELL.get_or_init(|| {
+            [
+                Regex::new(r"^ DIP_VEC:DEBYE\[3\]=").unwrap(),
+                Regex::new("^ EIGENVALUES").unwrap(),
+                Regex::new("^ MOLECULAR_ORBITAL_OCCUPANCIES").unwrap(),
+            ]
+        });
         #[derive(PartialEq)]
         enum State {
             Geom,
             Labels,
-            Done,
+            Charges,
+            Eigenvalues,
+            Occupancies,
             None,
         }
         /// don't look for these after they've been found
@@ -294,6 +428,8 @@ impl Mopac {
             atom: bool,
             element: bool,
             time: bool,
+            geom: bool,
+            dipole: bool,
         }
         let mut state = State::None;
         let mut guard = Guard {
@@ -301,13 +437,61 @@ impl Mopac {
             atom: false,
             element: false,
             time: false,
+            geom: false,
+            dipole: false,
         };
         // atomic labels
         let mut labels = Vec::new();
         // coordinates
         let mut coords = Vec::new();
         let mut time = 0.0;
+        let mut dipole = None;
+        let mut charges = Vec::new();
+        let mut eigenvalues = Vec::new();
+        let mut occupancies = Vec::new();
+        // only the heat of formation is reported as an EnergyParseError
+        let parse_d = |s: &str| {
+            s.replace('D', "E")
+                .parse::<f64>()
+                .map_err(|_| ProgramError::ParseError(auxfile.clone()))
+        };
+        let parse_all = |line: &str| {
+            line.split_ascii_whitespace()
+                .map(|s| {
+                    s.parse::<f64>()
+                        .map_err(|_| ProgramError::ParseError(auxfile.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
         for line in BufReader::new(f).lines().map_while(Result::ok) {
+            // every keyword line starts with a single space and then a letter,
+            // while data lines start with more whitespace or a sign
+            let is_key = line
+                .as_bytes()
+                .get(1)
+                .is_some_and(|c| c.is_ascii_alphabetic());
+            match state {
+                State::Charges | State::Eigenvalues | State::Occupancies
+                    if !is_key =>
+                {
+                    let vals = parse_all(&line)?;
+                    match state {
+                        State::Charges => charges.extend(vals),
+                        State::Eigenvalues => eigenvalues.extend(vals),
+                        _ => occupancies.extend(vals),
+                    }
+                    continue;
+                }
+                // the occupancies are the last section needed, so there's no
+                // need to read the rest of the file
+                State::Occupancies => break,
+                State::Charges | State::Eigenvalues => {
+                    state = State::None;
+                }
+                // skip the large matrices after the geometry
+                State::None if !is_key => continue,
+                _ => {}
+            }
             if !guard.element && elt_re.is_match(&line) {
                 state = State::Labels;
                 guard.element = true;
@@ -319,53 +503,68 @@ impl Mopac {
             // line like HEAT_OF_FORMATION:KCAL/MOL=+0.97127947459164715838D+02
             } else if !guard.heat && heat_re.is_match(&line) {
                 let fields: Vec<&str> = line.trim().split('=').collect();
-                match fields[1].replace('D', "E").parse::<f64>() {
-                    Ok(f) => {
-                        energy = Some(f / KCALHT);
-                    }
-                    Err(_) => {
-                        return Err(ProgramError::EnergyParseError(auxfile));
-                    }
-                }
+                let Ok(heat) = parse_d(fields[1]) else {
+                    return Err(ProgramError::EnergyParseError(auxfile));
+                };
+                energy = Some(heat / KCALHT);
                 guard.heat = true;
             } else if !guard.time && time_re.is_match(&line) {
-                time = line
-                    .split('=')
-                    .nth(1)
-                    .unwrap()
-                    .replace('D', "E")
-                    .parse()
-                    .unwrap();
+                time = parse_d(line.split('=').nth(1).unwrap())?;
                 guard.time = true;
+            // line like DIP_VEC:DEBYE[3]= -0.23D-14 -0.44D-02 +0.46D+01
+            } else if !guard.dipole && dip_re.is_match(&line) {
+                let mut dip = [0.0; 3];
+                let vals = line.split('=').nth(1).unwrap().split_whitespace();
+                for (d, v) in dip.iter_mut().zip(vals) {
+                    *d = parse_d(v)? / AUDEBYE;
+                }
+                dipole = Some(dip);
+                guard.dipole = true;
             } else if !guard.atom && atom_re.is_match(&line) {
                 state = State::Geom;
                 guard.atom = true;
             } else if state == State::Geom && charge_re.is_match(&line) {
-                state = State::Done;
-                break;
+                state = State::Charges;
+                guard.geom = true;
             } else if state == State::Geom {
-                coords.extend(
-                    line.split_ascii_whitespace()
-                        .map(|s| s.parse::<f64>().unwrap()),
-                );
+                coords.extend(parse_all(&line)?);
+            } else if guard.geom && eig_re.is_match(&line) {
+                state = State::Eigenvalues;
+            } else if guard.geom && occ_re.is_match(&line) {
+                state = State::Occupancies;
             }
         }
-        if state != State::Done {
+        if !guard.geom {
             return Err(ProgramError::GeomNotFound(auxfile));
         }
         assert_eq!(coords.len() / 3, labels.len());
-        let ret = coords
+        let ret: Vec<_> = coords
             .chunks_exact(3)
             .zip(labels)
             .map(|(coord, l)| {
                 Atom::new_from_label(&l, coord[0], coord[1], coord[2])
             })
             .collect();
+        let ret =
+            Cartesian::new(ret, <Self as Program>::OUTPUT_UNIT).into_angstrom();
+        // EIGENVALUES are in eV, and an orbital is occupied if its occupancy
+        // is nonzero
+        let (homo, lumo) = frontier_orbitals(
+            eigenvalues.iter().map(|e| e / HTEV),
+            occupancies.iter().map(|&o| o > 0.0),
+        );
         if let Some(energy) = energy {
             Ok(ProgramResult {
                 energy,
+                // the aux file labels the heat of formation in kcal/mol, but
+                // it's stored in hartrees like every other labeled energy
+                energies: Energies::from([("HOF", energy)]),
                 cart_geom: Some(ret),
                 time,
+                dipole,
+                charges: (!charges.is_empty()).then_some(charges),
+                homo,
+                lumo,
             })
         } else {
             Err(ProgramError::EnergyNotFound(auxfile))
@@ -1,8 +1,6 @@
 use std::collections::HashSet;
 use std::fs;
 
-use crate::string;
-
 use crate::queue::{self, Queue, SubQueue, Submit};
 
 use super::*;
@@ -131,9 +129,31 @@ fn test_read_output() {
     let got = res.energy;
     let want = 9.712_794_745_916_472e1 / KCALHT;
     assert!((got - want).abs() < 1e-20);
+    assert_eq!(res.energies.get("HOF"), Some(res.energy));
 
     assert_eq!(res.time, 0.015625);
 
+    assert_eq!(
+        res.dipole,
+        Some([
+            -2.306_836_538_587_687_4e-15 / AUDEBYE,
+            -4.419_218_370_564_693e-3 / AUDEBYE,
+            4.648_006_068_598_753 / AUDEBYE,
+        ])
+    );
+    assert_eq!(
+        res.charges,
+        Some(vec![
+            -0.0011562687168602537,
+            -0.650_805_483_166_271_5,
+            -0.651_855_893_243_173_1,
+            0.651_746_858_275_689_3,
+            0.652_070_786_850_607_4,
+        ])
+    );
+    assert_eq!(res.homo, Some(-13.895_972_111_306_648 / HTEV));
+    assert_eq!(res.lumo, Some(-5.201_011_193_550_578 / HTEV));
+
     // opt success
     let got = Mopac::read_output("testfiles/opt").unwrap().cart_geom;
     let want = vec![
@@ -187,6 +207,51 @@ fn test_read_output() {
     assert!(got.unwrap().cart_geom.is_some());
 }
 
+#[test]
+fn read_outputs() {
+    let filenames: Vec<_> = ["job", "nojob", "noaux", "bad", "missing"]
+        .map(|f| format!("testfiles/{f}"))
+        .into();
+    let mut seen = vec![None; filenames.len()];
+    let got = Mopac::read_outputs(&filenames, &mut seen);
+    let want: Vec<_> = filenames
+        .iter()
+        .map(|f| Some(Mopac::read_output(f)))
+        .collect();
+    assert_eq!(got, want);
+    assert!(seen[0].is_some());
+    assert!(seen[4].is_none());
+
+    // outputs that haven't changed aren't read again, unless they never
+    // existed
+    let got = Mopac::read_outputs(&filenames, &mut seen);
+    assert!(got[..4].iter().all(Option::is_none));
+    assert_eq!(got[4], want[4]);
+
+    let mut seen = vec![Some(SystemTime::UNIX_EPOCH)];
+    let got = Mopac::read_outputs(&filenames[..1], &mut seen);
+    assert_eq!(got, want[..1]);
+}
+
+#[test]
+fn read_aux_parse_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("job");
+    let base = base.to_str().unwrap();
+    let aux = std::fs::read_to_string("testfiles/job.aux").unwrap();
+    let mut lines: Vec<_> = aux.lines().collect();
+    let i = lines
+        .iter()
+        .position(|l| l.starts_with(" ATOM_X_OPT"))
+        .unwrap();
+    lines[i + 1] = "    0.0  zero  0.0";
+    std::fs::write(format!("{base}.aux"), lines.join("\n")).unwrap();
+    assert_eq!(
+        Mopac::read_aux(base),
+        Err(ProgramError::ParseError(format!("{base}.aux")))
+    );
+}
+
 #[test]
 fn read_multi_el() {
     let got = Mopac::read_output("testfiles/mopac/multi_atom_el")
@@ -248,18 +313,23 @@ impl SubQueue<Mopac> for TestQueue {
     fn no_del(&self) -> bool {
         false
     }
+
+    fn cancel(&self, _job_ids: &[String]) {
+        todo!()
+    }
 }
 
 #[test]
 fn test_submit() {
     let tq = TestQueue;
-    tq.write_submit_script(
-        string!["input1.mop", "input2.mop", "input3.mop"],
-        "/tmp/main.pbs",
-    );
-    let got = tq.submit("/tmp/main.pbs");
-    let want = "input3.mop";
-    assert_eq!(got, want);
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    let inputs = ["input1.mop", "input2.mop", "input3.mop"]
+        .map(|f| format!("{dir}/{f}"));
+    let script = format!("{dir}/main.pbs");
+    tq.write_submit_script(inputs.clone(), &script);
+    let got = tq.submit(&script);
+    assert_eq!(got, inputs[2]);
 }
 
 #[test]
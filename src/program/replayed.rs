@@ -0,0 +1,183 @@
+//! the [Program] run by a [crate::queue::replay::Replay] queue, which returns
+//! the outcomes recorded for each job instead of reading real output files.
+//! requires the `replay` feature
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{geom::Geom, queue::marker};
+
+use super::{Procedure, Program, ProgramError, ProgramResult, Template};
+
+/// one recorded call to [Program::read_output], along with the side effects
+/// the drain looks at afterwards
+#[derive(Clone, Debug)]
+pub(crate) struct Outcome {
+    /// the number of reads of the job before this one. it is returned by every
+    /// read until the next outcome's
+    pub(crate) read: usize,
+
+    pub(crate) result: Result<ProgramResult, ProgramError>,
+
+    /// the modification time of the output file, if it existed
+    pub(crate) modified: Option<SystemTime>,
+
+    /// the contents of the completion marker, if it existed
+    pub(crate) done: Option<String>,
+}
+
+/// the outcomes still to be replayed for a job, and the number of times it has
+/// been read so far
+#[derive(Default)]
+struct Reads {
+    outcomes: VecDeque<Outcome>,
+    count: usize,
+}
+
+/// the outcomes still to be replayed, by job filename. [Program::read_output]
+/// can't see the queue, so this has to be shared through the whole process
+static OUTCOMES: LazyLock<Mutex<HashMap<String, Reads>>> =
+    LazyLock::new(Default::default);
+
+/// queue `outcomes` to be returned by successive reads of each job, replacing
+/// any left over from an earlier replay of the same jobs
+pub(crate) fn register(outcomes: HashMap<String, VecDeque<Outcome>>) {
+    OUTCOMES.lock().unwrap().extend(
+        outcomes
+            .into_iter()
+            .map(|(k, outcomes)| (k, Reads { outcomes, count: 0 })),
+    );
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Replayed {
+    filename: String,
+
+    /// the serialized form of the program that was recorded, which keeps
+    /// distinct jobs from being merged by the drain again
+    program: Value,
+}
+
+impl Replayed {
+    /// a job named `filename` standing in for the recorded `program`
+    pub(crate) fn from_value(filename: String, program: Value) -> Self {
+        Self { filename, program }
+    }
+}
+
+impl Program for Replayed {
+    fn filename(&self) -> String {
+        self.filename.clone()
+    }
+
+    fn infile(&self) -> String {
+        format!("{}.{}", self.filename, self.extension())
+    }
+
+    fn set_filename(&mut self, filename: &str) {
+        self.filename = String::from(filename);
+    }
+
+    /// replays never render a template, so this is always empty
+    fn template(&self) -> &Template {
+        static EMPTY: LazyLock<Template> = LazyLock::new(|| Template::from(""));
+        &EMPTY
+    }
+
+    fn extension(&self) -> String {
+        String::from("inp")
+    }
+
+    const KIND: &'static str = "replayed";
+
+    fn charge(&self) -> isize {
+        0
+    }
+
+    /// write the recorded program, which is only needed so that
+    /// resubmissions have an input file to copy
+    fn write_input(&mut self, _proc: Procedure) {
+        if let Err(e) = std::fs::write(self.infile(), self.program.to_string())
+        {
+            panic!("failed to write {} with {e}", self.infile());
+        }
+    }
+
+    /// return the outcome recorded for this read of `filename`, first
+    /// restoring the output file's modification time and the completion marker
+    /// as they were when it was recorded. a job that was never read in the
+    /// recording is never found
+    fn read_output(filename: &str) -> Result<ProgramResult, ProgramError> {
+        let outfile = format!("{filename}.out");
+        let next = OUTCOMES.lock().unwrap().get_mut(filename).and_then(|r| {
+            let count = r.count;
+            r.count += 1;
+            while r.outcomes.get(1).is_some_and(|o| o.read <= count) {
+                r.outcomes.pop_front();
+            }
+            r.outcomes.front().cloned()
+        });
+        let Some(Outcome {
+            read: _,
+            result,
+            modified,
+            done,
+        }) = next
+        else {
+            return Err(ProgramError::FileNotFound(outfile));
+        };
+        match modified {
+            Some(time) => {
+                let file = File::options()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(&outfile);
+                if let Err(e) = file.and_then(|f| f.set_modified(time)) {
+                    log::warn!("failed to restore {outfile} with {e}");
+                }
+            }
+            None => {
+                let _ = std::fs::remove_file(&outfile);
+            }
+        }
+        let marker = marker::done_file(filename);
+        match done {
+            Some(s) => {
+                if let Err(e) = std::fs::write(&marker, s) {
+                    log::warn!("failed to restore {marker} with {e}");
+                }
+            }
+            None => {
+                let _ = std::fs::remove_file(&marker);
+            }
+        }
+        result
+    }
+
+    fn associated_files(&self) -> Vec<String> {
+        let f = &self.filename;
+        vec![format!("{f}.inp"), format!("{f}.out")]
+    }
+
+    fn new(
+        filename: String,
+        template: Template,
+        charge: isize,
+        geom: Geom,
+    ) -> Self {
+        let program = serde_json::json!({
+            "template": template,
+            "charge": charge,
+            "geom": geom,
+        });
+        Self { filename, program }
+    }
+}
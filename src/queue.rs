@@ -17,13 +17,29 @@ use crate::{
 };
 
 pub mod local;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod pbs;
+pub mod pool;
+#[cfg(feature = "replay")]
+pub mod replay;
 pub mod slurm;
+pub mod transport;
 use drain::*;
 use serde::{Deserialize, Serialize};
+use transport::Transport;
 mod drain;
+pub(crate) mod marker;
+mod options;
 
-pub use drain::Check;
+pub use drain::{
+    CancelToken, Check, DrainError, DrainEvent, DrainObserver, JsonlLog,
+    StderrObserver, Timer,
+};
+
+#[cfg(feature = "async")]
+pub use drain::AsyncQueue;
+pub use options::DrainOptions;
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct Resubmit {
@@ -36,10 +52,22 @@ pub trait Submit<P>: SubQueue<P>
 where
     P: Program + Clone + Serialize + for<'a> Deserialize<'a>,
 {
+    /// the command that submits `filename` to the queue
+    fn submit_cmd(&self, filename: &str) -> Command {
+        let mut cmd = Command::new(self.submit_command());
+        cmd.arg(filename);
+        cmd
+    }
+
     /// submit `filename` to the queue and return the jobid
     fn submit(&self, filename: &str) -> String {
         loop {
-            match Command::new(self.submit_command()).arg(filename).output() {
+            match self
+                .options()
+                .transport()
+                .command(self.submit_cmd(filename))
+                .output()
+            {
                 Ok(s) => {
                     if s.status.success() {
                         let raw = str::from_utf8(&s.stdout)
@@ -91,6 +119,37 @@ where
 
     /// return `true` if all output files should be preserved
     fn no_del(&self) -> bool;
+
+    /// remove the jobs in `job_ids` from the queue. failures are only logged
+    /// since the jobs may have finished in the meantime
+    fn cancel(&self, job_ids: &[String]);
+
+    /// the options for draining this queue. the default has every option off
+    fn options(&self) -> &DrainOptions {
+        &options::DEFAULT
+    }
+}
+
+/// run `cmd` with `job_ids` as arguments to cancel them, for implementing
+/// [SubQueue::cancel]
+pub(crate) fn cancel_with(
+    transport: &dyn Transport,
+    cmd: &str,
+    job_ids: &[String],
+) {
+    if job_ids.is_empty() {
+        return;
+    }
+    let mut c = Command::new(cmd);
+    c.args(job_ids);
+    match transport.command(c).output() {
+        Ok(s) if s.status.success() => {}
+        Ok(s) => log::warn!(
+            "failed to cancel jobs with {cmd}: `{}`",
+            String::from_utf8_lossy(&s.stderr).trim()
+        ),
+        Err(e) => log::warn!("failed to run {cmd} with {e}"),
+    }
 }
 
 pub trait Queue<P>: SubQueue<P> + Submit<P>
@@ -103,6 +162,22 @@ where
 
     fn program_cmd(&self, filename: &str) -> String;
 
+    /// the command run after each `program_cmd` in a submit script, if
+    /// [DrainOptions::write_markers] is set, to record that the program
+    /// finished, along with its exit status. the drain treats this marker as
+    /// authoritative, so a nonzero status fails the job immediately, and a
+    /// marker without a complete output means the output was truncated and the
+    /// job is resubmitted
+    fn marker_cmd(&self, filename: &str) -> String {
+        marker::write_cmd(&self.script_path(filename))
+    }
+
+    /// the path of the job `filename` as seen from the directory the submit
+    /// script runs in, for writing its marker and log
+    fn script_path(&self, filename: &str) -> String {
+        filename.to_owned()
+    }
+
     fn write_submit_script(
         &self,
         infiles: impl IntoIterator<Item = String>,
@@ -111,14 +186,44 @@ where
         use std::fmt::Write;
         let path = Path::new(filename);
         let basename = path.file_name().unwrap();
+        let options = self.options();
         let mut body = self
             .template()
             .clone()
             .unwrap_or_else(|| <Self as Queue<P>>::default_submit_script(self))
             .replace("{{.basename}}", basename.to_str().unwrap())
-            .replace("{{.filename}}", filename);
-        for f in infiles {
-            writeln!(body, "{}", self.program_cmd(&f)).unwrap();
+            .replace("{{.filename}}", filename)
+            .replace("{{.cpus}}", &options.cpus().to_string());
+        let slots = options.parallel_jobs().max(1);
+        let markers =
+            options.write_markers() || options.transport().is_remote();
+        let infiles: Vec<_> = infiles.into_iter().collect();
+        if slots > 1 {
+            // take the background jobs down with the script when it's killed
+            writeln!(
+                body,
+                "trap 'kill $(jobs -p) 2>/dev/null; exit 143' INT TERM"
+            )
+            .unwrap();
+        }
+        for batch in infiles.chunks(slots) {
+            for f in batch {
+                let mut cmd = self.program_cmd(f);
+                if markers {
+                    cmd = format!("{cmd}\n{}", self.marker_cmd(f));
+                }
+                if slots == 1 {
+                    writeln!(body, "{cmd}").unwrap();
+                    continue;
+                }
+                // run each job in the background with its own log
+                let log = marker::log_file(&self.script_path(f));
+                writeln!(body, "(\n{cmd}\n) > {log} 2>&1 &").unwrap();
+            }
+            // plain sh can't wait for any one job, so wait for the whole batch
+            if slots > 1 {
+                writeln!(body, "wait").unwrap();
+            }
         }
         if std::fs::write(filename, body).is_err() {
             panic!("write_submit_script: failed to create {filename}");
@@ -173,18 +278,53 @@ where
         jobs: &mut [Job<P>],
         proc: Procedure,
     ) -> (HashMap<String, usize>, Duration, Duration, Duration) {
+        let (queue_file, slurm_jobs, input, script) =
+            self.write_chunk(dir, base, chunk_num, jobs, proc);
+        // run jobs
+        let job_id;
+        time!(e, {
+            job_id = self.submit(&queue_file);
+        });
+        #[cfg(feature = "replay")]
+        if let Some(r) = self.options().recorder() {
+            r.submit(&queue_file, &job_id);
+        }
+        for job in jobs {
+            job.job_id = job_id.clone();
+        }
+        (slurm_jobs, input, script, e)
+    }
+
+    /// write the input files for `jobs` and the submit script that runs them,
+    /// without submitting it. returns the name of the submit script along with
+    /// the remaining outputs of [Queue::build_chunk] apart from the submission
+    /// time
+    fn write_chunk(
+        &self,
+        dir: &str,
+        base: &str,
+        chunk_num: usize,
+        jobs: &mut [Job<P>],
+        proc: Procedure,
+    ) -> (String, HashMap<String, usize>, Duration, Duration) {
         let mut input = Duration::default();
         let mut script = Duration::default();
-        let mut submit = Duration::default();
         let queue_file =
             format!("{}/{base}{}.{}", dir, chunk_num, Self::SCRIPT_EXT);
         let jl = jobs.len();
         let mut slurm_jobs = HashMap::new();
+        let mut inputs = Vec::new();
+        let mut markers = Vec::new();
         let filenames = jobs.iter_mut().map(|job| {
             time!(e, {
                 job.program.write_input(proc);
             });
             input += e;
+            // clear any stale marker from an earlier run with the same name
+            let marker = marker::done_file(&job.program.filename());
+            let _ = std::fs::remove_file(&marker);
+            markers.push(marker);
+            inputs.extend(job.program.input_files());
             job.pbs_file = queue_file.to_string();
             job.program.filename()
         });
@@ -193,16 +333,16 @@ where
             self.write_submit_script(filenames, &queue_file);
         });
         script += e;
-        // run jobs
-        let job_id;
-        time!(e, {
-            job_id = self.submit(&queue_file);
-        });
-        submit += e;
-        for job in jobs {
-            job.job_id = job_id.clone();
+        let transport = self.options().transport();
+        if transport.is_remote() {
+            time!(e, {
+                inputs.push(queue_file.clone());
+                transport.put(&inputs);
+                transport.remove(&markers);
+            });
+            script += e;
         }
-        (slurm_jobs, input, script, submit)
+        (queue_file, slurm_jobs, input, script)
     }
 
     fn drain_err_case(
@@ -251,7 +391,8 @@ where
         }
     }
 
-    /// optimize is a copy of drain for optimizing jobs
+    /// optimize is a copy of drain for optimizing jobs. like [Queue::drain],
+    /// this panics if the drain is cancelled
     fn optimize(
         &self,
         dir: &str,
@@ -261,10 +402,44 @@ where
     where
         Self: Sync,
     {
-        Opt.drain(dir, self, jobs, dst, Check::None)
+        self.try_optimize(dir, jobs, dst, Check::None, &StderrObserver)
+            .map_err(DrainError::into_failed)
     }
 
-    /// resume draining from the checkpoint file in `checkpoint`
+    /// like [Queue::optimize], but with checkpoints, reporting progress to
+    /// `observer` instead of [StderrObserver], and returning a [DrainError]
+    fn try_optimize(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [Geom],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        Opt.drain(dir, self, jobs, dst, check, observer)
+    }
+
+    /// resume optimizing from the checkpoint file in `checkpoint`
+    fn resume_opt(
+        &self,
+        dir: &str,
+        checkpoint: &str,
+        dst: &mut [Geom],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        let jobs = Opt::resume_checkpoint(checkpoint, dst, observer);
+        self.try_optimize(dir, jobs, dst, check, observer)
+    }
+
+    /// resume draining from the checkpoint file in `checkpoint`. like
+    /// [Queue::drain], this panics if the drain is cancelled
     fn resume(
         &self,
         dir: &str,
@@ -275,16 +450,32 @@ where
     where
         Self: Sync,
     {
-        let jobs = Single::load_checkpoint(checkpoint, dst);
-        eprintln!(
-            "resuming from checkpoint in '{checkpoint}' with {} jobs remaining",
-            jobs.len()
-        );
-        self.drain(dir, jobs, dst, check)
+        self.try_resume(dir, checkpoint, dst, check, &StderrObserver)
+            .map_err(DrainError::into_failed)
+    }
+
+    /// like [Queue::resume], but reporting progress to `observer` instead of
+    /// [StderrObserver] and returning a [DrainError]
+    fn try_resume(
+        &self,
+        dir: &str,
+        checkpoint: &str,
+        dst: &mut [f64],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        let jobs = Single::resume_checkpoint(checkpoint, dst, observer);
+        self.try_drain(dir, jobs, dst, check, observer)
     }
 
     /// run the single-point energy calculations in `jobs`, storing the results
-    /// in `dst`. if `check_int` > 0, write checkpoint files at that interval
+    /// in `dst`. if `check_int` > 0, write checkpoint files at that interval.
+    /// on failure, return the indices of the failed jobs. this panics if the
+    /// drain is cancelled through [DrainOptions::cancel_token], so use
+    /// [Queue::try_drain] for drains that can be cancelled
     fn drain(
         &self,
         dir: &str,
@@ -295,9 +486,96 @@ where
     where
         Self: Sync,
     {
-        Single.drain(dir, self, jobs, dst, check)
+        self.try_drain(dir, jobs, dst, check, &StderrObserver)
+            .map_err(DrainError::into_failed)
+    }
+
+    /// like [Queue::drain], but reporting progress to `observer` instead of
+    /// [StderrObserver] and returning a [DrainError]
+    fn try_drain(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [f64],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        Single.drain(dir, self, jobs, dst, check, observer)
+    }
+
+    /// like [Queue::try_drain], but also store the dipole moment from each job
+    /// alongside its energy. jobs whose output doesn't contain a dipole moment
+    /// fail with [ProgramError::DipoleNotFound]
+    fn drain_with_dipoles(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [(f64, [f64; 3])],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        EnergyDipole.drain(dir, self, jobs, dst, check, observer)
+    }
+
+    /// resume draining energies and dipoles from the checkpoint file in
+    /// `checkpoint`
+    fn resume_with_dipoles(
+        &self,
+        dir: &str,
+        checkpoint: &str,
+        dst: &mut [(f64, [f64; 3])],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        let jobs = EnergyDipole::resume_checkpoint(checkpoint, dst, observer);
+        self.drain_with_dipoles(dir, jobs, dst, check, observer)
     }
 
+    /// run the single-point calculations in `jobs`, storing the
+    /// `coeff`-weighted dipole moments in `dst`. if `check_int` > 0, write
+    /// checkpoint files at that interval. jobs whose output doesn't contain a
+    /// dipole moment fail with [ProgramError::DipoleNotFound]
+    fn drain_dipoles(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [[f64; 3]],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        Dipole.drain(dir, self, jobs, dst, check, observer)
+    }
+
+    /// resume draining dipoles from the checkpoint file in `checkpoint`
+    fn resume_dipoles(
+        &self,
+        dir: &str,
+        checkpoint: &str,
+        dst: &mut [[f64; 3]],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        let jobs = Dipole::resume_checkpoint(checkpoint, dst, observer);
+        self.drain_dipoles(dir, jobs, dst, check, observer)
+    }
+
+    /// run the calculations in `jobs`, storing the full results in `dst`. like
+    /// [Queue::drain], this panics if the drain is cancelled
     fn energize(
         &self,
         dir: &str,
@@ -307,6 +585,40 @@ where
     where
         Self: Sync,
     {
-        Both.drain(dir, self, jobs, dst, Check::None)
+        self.try_energize(dir, jobs, dst, Check::None, &StderrObserver)
+            .map_err(DrainError::into_failed)
+    }
+
+    /// like [Queue::energize], but with checkpoints, reporting progress to
+    /// `observer` instead of [StderrObserver], and returning a [DrainError]
+    fn try_energize(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [ProgramResult],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        Both.drain(dir, self, jobs, dst, check, observer)
+    }
+
+    /// resume optimizing and collecting full results from the checkpoint file
+    /// in `checkpoint`
+    fn resume_energize(
+        &self,
+        dir: &str,
+        checkpoint: &str,
+        dst: &mut [ProgramResult],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        Self: Sync,
+    {
+        let jobs = Both::resume_checkpoint(checkpoint, dst, observer);
+        self.try_energize(dir, jobs, dst, check, observer)
     }
 }
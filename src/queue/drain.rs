@@ -1,20 +1,15 @@
-use core::time;
 use std::{
-    borrow::BorrowMut,
-    collections::{HashMap, HashSet},
-    iter::{Enumerate, Fuse, Peekable},
-    slice::ChunksMut,
+    collections::{BTreeSet, HashSet},
     sync::LazyLock,
-    thread,
 };
 
 use crate::{
     geom::Geom,
-    program::{Job, Procedure, Program, ProgramResult},
-    queue::drain::{dump::Dump, resub::ResubOutput},
+    program::{Job, Procedure, Program, ProgramError, ProgramResult},
+    queue::drain::dump::Dump,
 };
 
-use super::Queue;
+use super::{marker, marker::Done, transport::Transport, DrainOptions, Queue};
 
 /// time the duration of `$body` and store the resulting Duration in `$elapsed`
 #[macro_export]
@@ -26,25 +21,114 @@ macro_rules! time {
     };
 }
 
+mod cache;
+mod cancel;
+mod checkpoint;
+mod dedup;
+mod driver;
 mod dump;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod observer;
+mod poll;
 mod resub;
+mod running;
 mod timer;
+mod watch;
 
+pub use cancel::CancelToken;
+#[cfg(feature = "async")]
+pub use nonblocking::AsyncQueue;
+pub(crate) use observer::JsonlWriter;
+pub use observer::{DrainEvent, DrainObserver, JsonlLog, StderrObserver};
+pub use timer::Timer;
+
+#[cfg(test)]
+mod tests;
+
+use cache::Cache;
+use dedup::dedup;
+use driver::{block_on, Blocking};
 use libc::{timeval, RUSAGE_SELF};
-use resub::Resub;
+use running::Running;
 use serde::{Deserialize, Serialize};
 
 static NO_RESUB: LazyLock<bool> =
     LazyLock::new(|| std::env::var("NO_RESUB").is_ok());
 
 pub enum Check {
-    Some { check_int: usize, check_dir: String },
+    /// write a checkpoint to `check_dir` every `check_int` iterations, as
+    /// `chk.json` or as gzip-compressed `chk.json.gz` if the queue's
+    /// [DrainOptions::compress_checkpoints] is true
+    ///
+    /// [DrainOptions::compress_checkpoints]:
+    ///     crate::queue::DrainOptions::compress_checkpoints
+    Some {
+        check_int: usize,
+        check_dir: String,
+    },
     None,
 }
 
+/// the ways a drain can stop without finishing all of its jobs
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrainError {
+    /// the jobs with these indices failed with errors in their output files
+    Failed(Vec<usize>),
+
+    /// the drain was stopped by its [CancelToken]. `checkpoint` is the path
+    /// to the checkpoint written before returning, if checkpoints were
+    /// enabled
+    Cancelled { checkpoint: Option<String> },
+
+    /// some of the jobs were merged by [Program::build_jobs_symm], whose
+    /// results are only copied correctly by drains for single-point energies.
+    /// `drain` is the type of result that was requested instead
+    SymmetryMerged { drain: &'static str },
+}
+
+impl std::fmt::Display for DrainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DrainError::Failed(v) => write!(f, "{} jobs failed", v.len()),
+            DrainError::Cancelled { .. } => write!(f, "drain cancelled"),
+            DrainError::SymmetryMerged { drain } => write!(
+                f,
+                "jobs merged by symmetry can only be drained for energies, \
+                 not for {drain}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DrainError {}
+
+impl DrainError {
+    /// the indices of the failed jobs, as returned by the drains that predate
+    /// [DrainError]. those can't report any other error, so this panics for
+    /// the other variants, pointing to the checkpoint for a cancelled drain
+    pub(crate) fn into_failed(self) -> Vec<usize> {
+        match self {
+            DrainError::Failed(v) => v,
+            DrainError::Cancelled {
+                checkpoint: Some(chk),
+            } => panic!(
+                "drain cancelled, resume from {chk}. use the try_ methods to \
+                 handle cancellation"
+            ),
+            e => panic!("{e}. use the try_ methods to handle this error"),
+        }
+    }
+}
+
 pub(crate) trait Drain {
     type Item;
 
+    /// whether the results stored by this drain are unchanged by the symmetry
+    /// operations of the molecule, so that the result of one job can be copied
+    /// to the jobs merged into it by [Program::build_jobs_symm]
+    const INVARIANT: bool = false;
+
     fn procedure(&self) -> Procedure;
 
     fn set_result<P: Program>(
@@ -54,273 +138,463 @@ pub(crate) trait Drain {
         res: ProgramResult,
     );
 
+    /// check that `res` contains everything [Drain::set_result] needs before
+    /// it counts as finished. an error fails `job` like any other error in its
+    /// output
+    fn validate<P: Program>(
+        &self,
+        _job: &Job<P>,
+        res: ProgramResult,
+    ) -> Result<ProgramResult, ProgramError> {
+        Ok(res)
+    }
+
+    /// prepare `res` for [Drain::set_result] by making the energy selected by
+    /// [DrainOptions::energy_label] the primary one and then calling
+    /// [Drain::validate]
+    fn accept<P: Program>(
+        &self,
+        options: &DrainOptions,
+        job: &Job<P>,
+        mut res: ProgramResult,
+    ) -> Result<ProgramResult, ProgramError> {
+        if let Some(label) = options.energy_label() {
+            let Some(energy) = res.energies.get(label) else {
+                return Err(ProgramError::LabelNotFound(
+                    job.program.filename(),
+                ));
+            };
+            res.energy = energy;
+        }
+        self.validate(job, res)
+    }
+
+    /// call [Drain::set_result] for `job` and for each of the duplicate jobs
+    /// merged into it
+    fn set_results<P: Program>(
+        &self,
+        dst: &mut [Self::Item],
+        job: &mut Job<P>,
+        res: ProgramResult,
+    ) {
+        let (index, coeff) = (job.index, job.coeff);
+        let dups = job.dups.iter().chain(&job.symm_dups).copied();
+        for (i, c) in dups.collect::<Vec<_>>() {
+            job.index = i;
+            job.coeff = c;
+            self.set_result(dst, job, res.clone());
+        }
+        job.index = index;
+        job.coeff = coeff;
+        self.set_result(dst, job, res);
+    }
+
     /// on success, return the total job time, as returned by `P::read_output`.
-    /// on failure, return a vector of failed job indices
+    /// on failure, return the indices of the failed jobs, or
+    /// [DrainError::Cancelled] if the queue's [CancelToken] was cancelled.
+    /// progress is reported to `observer`
+    #[allow(clippy::too_many_arguments)]
     fn drain<P, Q>(
         &self,
         dir: &str,
         queue: &Q,
-        mut jobs: Vec<Job<P>>,
+        jobs: Vec<Job<P>>,
         dst: &mut [Self::Item],
         check: Check,
-    ) -> Result<f64, Vec<usize>>
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
     where
         Self: Sync,
         P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
         Q: Queue<P> + ?Sized + Sync,
         <Self as Drain>::Item: Clone + Serialize,
     {
-        // total time for the jobs to run as returned from Program::read_output
-        let mut job_time = 0.0;
-
-        let mut cur_jobs = Vec::new();
-        let mut slurm_jobs = HashMap::new();
-        let mut remaining = jobs.len();
-
-        let job_limit = queue.job_limit();
-
-        let mut out_of_jobs = false;
-
-        let dump = Dump::new(queue.no_del());
-        let mut time = timer::Timer::default();
-
-        let mut qstat = HashSet::<String>::new();
-        // this is a bit sad, but I need the original jobs for checkpoints and I
-        // can't get an immutable reference to them while chunks is holding a
-        // mutable reference. also can't use a Cow because the chunks_mut call
-        // would clone immediately anyway. I wanted something like Cow because I
-        // only need this clone if check_int is nonzero. I also can't figure out
-        // how to clone chunks itself when writing the checkpoint. that would
-        // really be the ideal solution, but it seems I can only consume the
-        // iterator. another option would be to consume the iterator and rebuild
-        // it when writing the checkpoints
-        let jobs_init = if let Check::Some { .. } = check {
-            jobs.clone()
-        } else {
-            Vec::new()
-        };
-        let total_jobs = jobs.len();
-        // for fast jobs, it may be necessary to stop and clean up even if
-        // finished != 0. this is used to signal that case
-        let mut cleanup_intervals =
-            (0..total_jobs).step_by(job_limit).peekable();
-
-        let mut chunks = jobs
-            .chunks_mut(queue.chunk_size())
-            .enumerate()
-            .fuse()
-            .peekable();
-        // the index of the last chunk consumed. used for writing remaining jobs
-        // to checkpoints. None initially and then Some(chunk_num)
-        let mut last_chunk = None;
-        let mut to_remove = Vec::new();
-        let mut resub = Resub::new(queue, dir, self.procedure());
-        let mut failed_jobs = HashSet::new();
-        let mut iter = 0;
-        const MAX_RETRIES: usize = 5;
-        let mut retries = HashMap::new();
-        loop {
-            let loop_time = std::time::Instant::now();
-            if chunks.peek().is_none() {
-                out_of_jobs = true;
-            }
-            if !out_of_jobs {
-                let n = self.receive_jobs(
-                    &mut chunks,
-                    job_limit,
-                    &mut cur_jobs,
-                    queue,
-                    dir,
-                    &mut slurm_jobs,
-                    &mut time,
-                    &mut qstat,
-                    &mut last_chunk,
-                );
-                log::trace!("received {n} chunks of jobs");
+        block_on(driver::run(
+            self, &Blocking, dir, queue, jobs, dst, check, observer,
+        ))
+    }
+
+    /// deduplicate `jobs` and remove the ones with cached results, storing
+    /// those results in `dst` directly. returns
+    /// [DrainError::SymmetryMerged] without touching any jobs if some of
+    /// `jobs` were merged by symmetry and this drain isn't [Drain::INVARIANT]
+    #[allow(clippy::too_many_arguments)]
+    fn prepare<P, Q>(
+        &self,
+        queue: &Q,
+        dir: &str,
+        jobs: &mut Vec<Job<P>>,
+        running: &mut Running<P>,
+        dst: &mut [Self::Item],
+        cache: Option<&Cache>,
+        dump: &Dump,
+        observer: &dyn DrainObserver,
+    ) -> Result<(), DrainError>
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+        Q: Queue<P> + ?Sized,
+    {
+        // the results of symmetry-merged jobs are copied without applying the
+        // symmetry operation, which is only correct for scalar results
+        if !Self::INVARIANT
+            && jobs
+                .iter()
+                .chain(&running.cur_jobs)
+                .any(|job| !job.symm_dups.is_empty())
+        {
+            return Err(DrainError::SymmetryMerged {
+                drain: std::any::type_name::<Self::Item>(),
+            });
+        }
+
+        if let Some(tol) = queue.options().dedup_tol() {
+            let merged;
+            (*jobs, merged) =
+                dedup(std::mem::take(jobs), tol, dir, self.procedure());
+            if merged > 0 {
+                observer.on_event(&DrainEvent::Deduplicated {
+                    merged,
+                    jobs: jobs.len(),
+                });
             }
+        }
 
-            // collect output
-            let mut finished = 0;
-            to_remove.clear();
-            let now = std::time::Instant::now();
-            let outfiles: Vec<_> =
-                cur_jobs.iter().map(|job| job.program.filename()).collect();
-            use rayon::prelude::*;
-            let results: Vec<_> =
-                outfiles.par_iter().map(|out| P::read_output(out)).collect();
-            time.reading += now.elapsed();
-            for (i, (job, res)) in cur_jobs.iter_mut().zip(results).enumerate()
-            {
-                match res {
-                    Ok(res) => {
-                        let name = job.program.filename();
-                        if failed_jobs.remove(&name) {
-                            log::info!("removed {name} from failed_jobs");
-                        }
-                        to_remove.push(i);
-                        job_time += res.time;
-                        self.set_result(dst, job, res);
-                        for f in job.program.associated_files() {
-                            dump.send(f);
+        // skip any jobs whose results are already cached. this requires
+        // writing the input files up front to compare them to the cache
+        if let Some(cache) = cache {
+            let program = P::KIND;
+            let before = jobs.len();
+            jobs.retain_mut(|job| {
+                job.program.write_input(self.procedure());
+                let Some(input) = cache::inputs(&job.program) else {
+                    return true;
+                };
+                let Some(res) = cache.get(program, &input) else {
+                    return true;
+                };
+                let Ok(res) = self.accept(queue.options(), job, res) else {
+                    return true;
+                };
+                self.set_results(dst, job, res);
+                for f in job.program.associated_files() {
+                    dump.send(f);
+                }
+                false
+            });
+            log::info!("found {} cached results", before - jobs.len());
+        }
+        running.remaining = jobs.len() + running.cur_jobs.len();
+        Ok(())
+    }
+
+    /// handle the output of each job in `running`, as read into `results`.
+    /// jobs whose output was not read this time have `None` in `results`.
+    /// returns the number of jobs that finished and the jobs that need to be
+    /// resubmitted, which have already been removed from `running`
+    #[allow(clippy::too_many_arguments)]
+    fn collect<P, Q>(
+        &self,
+        queue: &Q,
+        running: &mut Running<P>,
+        results: Vec<Option<Result<ProgramResult, ProgramError>>>,
+        dst: &mut [Self::Item],
+        cache: Option<&Cache>,
+        dump: &Dump,
+        time: &mut Timer,
+        observer: &dyn DrainObserver,
+    ) -> (usize, Vec<Job<P>>)
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+        Q: Queue<P> + ?Sized,
+    {
+        const MAX_RETRIES: usize = 5;
+        let program = P::KIND;
+        let mut finished = 0;
+        let mut to_remove = Vec::new();
+        let mut resubs = Vec::new();
+        for (i, (job, res)) in
+            running.cur_jobs.iter_mut().zip(results).enumerate()
+        {
+            let Some(res) = res else {
+                continue;
+            };
+            #[cfg(feature = "replay")]
+            if let Some(r) = queue.options().recorder() {
+                r.read(&job.program.filename(), &res);
+            }
+            match res.and_then(|res| self.accept(queue.options(), job, res)) {
+                Ok(res) => {
+                    let name = job.program.filename();
+                    if running.failed_jobs.remove(&name) {
+                        log::info!("removed {name} from failed_jobs");
+                    }
+                    to_remove.push(i);
+                    running.job_time += res.time;
+                    running.times.push(res.time);
+                    observer.on_event(&DrainEvent::JobFinished {
+                        index: job.index,
+                        filename: name,
+                        job_id: job.job_id.clone(),
+                        pbs_file: job.pbs_file.clone(),
+                        time: res.time,
+                    });
+                    if let Some(cache) = cache {
+                        if let Some(input) = cache::inputs(&job.program) {
+                            cache.insert(program, &input, &res);
                         }
-                        finished += 1;
-                        remaining -= 1;
-                        let job_name = job.pbs_file.as_str();
-                        let mut count = match slurm_jobs.get_mut(job_name) {
-                            Some(n) => *n,
-                            None => {
-                                eprintln!(
-                                    "failed to find {job_name} in slurm_jobs"
-                                );
-                                1
-                            }
-                        };
-                        count -= 1;
-                        if count == 0 {
-                            // delete the submit script and output file
-                            dump.send(job_name.to_string());
-                            dump.send(format!("{job_name}.out"));
+                    }
+                    self.set_results(dst, job, res);
+                    for f in job.program.associated_files() {
+                        dump.send(f);
+                    }
+                    let name = job.program.filename();
+                    dump.send(marker::done_file(&name));
+                    dump.send(marker::log_file(&name));
+                    finished += 1;
+                    running.remaining -= 1;
+                    let job_name = job.pbs_file.as_str();
+                    let mut count = match running.slurm_jobs.get_mut(job_name) {
+                        Some(n) => *n,
+                        None => {
+                            eprintln!(
+                                "failed to find {job_name} in slurm_jobs"
+                            );
+                            1
                         }
+                    };
+                    count -= 1;
+                    if count == 0 {
+                        // delete the submit script and output file
+                        dump.send(job_name.to_string());
+                        dump.send(format!("{job_name}.out"));
                     }
-                    Err(e) => {
-                        if e.is_error_in_output() {
-                            let filename = job.program.filename();
-                            if !failed_jobs.contains(&filename) {
-                                log::warn!("job failed with `{e}`");
-                                failed_jobs.insert(filename);
-                            }
-                        } else if !qstat.contains(&job.job_id) {
-                            // to avoid temporary file system issues, check a
-                            // few times before resubmitting. this should avoid
-                            // the case I've been seeing where I end up with 70+
-                            // .out_## files in Molpro. the jobs are obviously
-                            // running and finishing, but the output files
-                            // aren't appearing until after I've already
-                            // resubmitted
-                            let retry = retries
-                                .entry(job.program.filename())
-                                .or_insert(MAX_RETRIES);
-                            if *retry == 0 {
-                                // just overwrite the existing job with
-                                // the resubmitted version
-                                let time = job.modtime();
-                                if time > job.modtime {
-                                    // file has been updated since we last
-                                    // looked at it, so need to look again
-                                    job.modtime = time;
-                                } else {
-                                    // actual resubmission path
+                }
+                Err(e) => {
+                    // the marker written after the program exits is
+                    // authoritative: a nonzero status is a failure, and a
+                    // marker without a complete output means it was truncated.
+                    // markers are only written when enabled or for a remote
+                    // transport, so don't look for them otherwise
+                    let options = queue.options();
+                    let done = (options.write_markers()
+                        || options.transport().is_remote())
+                    .then(|| Done::read(&job.program.filename()))
+                    .flatten();
+                    let (e, truncated) = match done {
+                        _ if e.is_missing_result() => (e, false),
+                        Some(Done { status, .. }) if status != 0 => {
+                            let name = job.program.filename();
+                            (ProgramError::ExitStatus(name, status), false)
+                        }
+                        Some(Done { time, .. }) => {
+                            log::warn!(
+                                "{} finished at {time} without a complete \
+                                 output",
+                                job.program.filename()
+                            );
+                            (e, true)
+                        }
+                        None => (e, false),
+                    };
+                    if e.is_error_in_output()
+                        || e.is_missing_result()
+                        || matches!(e, ProgramError::ExitStatus(..))
+                    {
+                        let filename = job.program.filename();
+                        if !running.failed_jobs.contains(&filename) {
+                            observer.on_event(&DrainEvent::JobFailed {
+                                index: job.index,
+                                filename: filename.clone(),
+                                job_id: job.job_id.clone(),
+                                pbs_file: job.pbs_file.clone(),
+                                error: e.to_string(),
+                                kind: e.kind(),
+                            });
+                            running.failed_jobs.insert(filename);
+                        }
+                    } else if truncated || !running.qstat.contains(&job.job_id)
+                    {
+                        // to avoid temporary file system issues, check a few
+                        // times before resubmitting. this should avoid the
+                        // case I've been seeing where I end up with 70+
+                        // .out_## files in Molpro. the jobs are obviously
+                        // running and finishing, but the output files aren't
+                        // appearing until after I've already resubmitted
+                        let retry = running
+                            .retries
+                            .entry(job.program.filename())
+                            .or_insert(MAX_RETRIES);
+                        if truncated || *retry == 0 {
+                            // just overwrite the existing job with the
+                            // resubmitted version
+                            let time = job.modtime();
+                            if !truncated && time > job.modtime {
+                                // file has been updated since we last looked
+                                // at it, so need to look again
+                                job.modtime = time;
+                            } else {
+                                // actual resubmission path
+                                observer.on_event(&DrainEvent::Resubmitting {
+                                    index: job.index,
+                                    filename: job.program.filename(),
+                                    job_id: job.job_id.clone(),
+                                    pbs_file: job.pbs_file.clone(),
+                                    error: e.to_string(),
+                                    kind: e.kind(),
+                                });
+                                if *NO_RESUB {
                                     eprintln!(
-                                        "resubmitting {} (id={}) for {:?}",
-                                        job.program.filename(),
-                                        job.job_id,
-                                        e
+                                        "resubmission disabled by NO_RESUB \
+					 environment variable, exiting"
                                     );
-                                    if *NO_RESUB {
-                                        eprintln!(
-                                            "resubmission disabled by \
-					 NO_RESUB environment \
-					 variable, exiting"
-                                        );
-                                        std::process::exit(1);
-                                    }
-                                    failed_jobs.remove(&job.program.filename());
-                                    // copy the job into resub and plan to
-                                    // remove it from cur_jobs
-                                    resub.push(job.clone());
-                                    to_remove.push(i);
+                                    std::process::exit(1);
                                 }
-                            } else {
-                                *retry -= 1;
+                                running
+                                    .failed_jobs
+                                    .remove(&job.program.filename());
+                                running.orphans.insert(job.job_id.clone());
+                                // copy the job into resubs and plan to remove
+                                // it from cur_jobs
+                                resubs.push(job.clone());
+                                to_remove.push(i);
                             }
+                        } else {
+                            *retry -= 1;
                         }
                     }
                 }
             }
-            // have to remove the highest index first so sort and reverse
-            let r = std::time::Instant::now();
-            to_remove.sort();
-            to_remove.reverse();
-            for i in &to_remove {
-                cur_jobs.swap_remove(*i);
-            }
-            time.removing += r.elapsed();
-            // submit resubs
-            let works = resub.resubmit();
-            for ResubOutput {
-                jobs,
-                slurm_jobs: sj,
-                job_id,
-                writing_input: wi,
-                writing_script: ws,
-                submitting: ss,
-            } in works
-            {
-                slurm_jobs.extend(sj);
-                time.writing_input += wi;
-                time.writing_script += ws;
-                time.submitting_script += ss;
-                qstat.insert(job_id);
-                cur_jobs.extend(jobs);
-            }
-            log::debug!(
-                "finished {} jobs in {:.1} s",
-                finished,
-                loop_time.elapsed().as_millis() as f64 / 1000.0
-            );
-            if cur_jobs.len().saturating_sub(failed_jobs.len()) == 0
-                && out_of_jobs
-            {
-                dump.shutdown();
-                if !failed_jobs.is_empty() {
-                    if let Check::Some { check_dir, .. } = &check {
-                        Self::do_checkpoint(
-                            &cur_jobs,
-                            last_chunk,
-                            &jobs_init,
-                            queue.chunk_size(),
-                            check_dir,
-                            dst,
-                        );
-                    }
-                    let failed_indices: Vec<_> =
-                        cur_jobs.into_iter().map(|job| job.index).collect();
-                    return Err(failed_indices);
-                }
-                eprintln!("{time}");
-                return Ok(job_time);
-            }
-            if finished == 0 {
-                wait(queue, &mut time, iter, remaining);
-                qstat = queue.status();
-            } else if total_jobs - remaining
-                > *cleanup_intervals.peek().unwrap_or(&total_jobs)
-            {
-                wait(queue, &mut time, iter, remaining);
-                cleanup_intervals.next();
-            }
-            if let Check::Some {
-                check_int,
+        }
+        // have to remove the highest index first so sort and reverse
+        let r = std::time::Instant::now();
+        to_remove.sort();
+        to_remove.reverse();
+        for i in &to_remove {
+            running.cur_jobs.swap_remove(*i);
+        }
+        time.removing += r.elapsed();
+        (finished, resubs)
+    }
+
+    /// write a checkpoint if one is due on iteration `iter`
+    #[allow(clippy::too_many_arguments)]
+    fn interval_checkpoint<P, Q>(
+        &self,
+        queue: &Q,
+        running: &Running<P>,
+        check: &Check,
+        iter: usize,
+        last_chunk: Option<usize>,
+        jobs_init: &[Job<P>],
+        dst: &mut [Self::Item],
+        observer: &dyn DrainObserver,
+    ) where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+        Q: Queue<P> + ?Sized,
+        Self::Item: Serialize + Clone,
+    {
+        let Check::Some {
+            check_int,
+            check_dir,
+        } = check
+        else {
+            return;
+        };
+        if *check_int > 0 && iter % *check_int == 0 {
+            let path = Self::do_checkpoint(
+                &running.cur_jobs,
+                last_chunk,
+                jobs_init,
+                queue.chunk_size(),
                 check_dir,
-            } = &check
-            {
-                if *check_int > 0 && iter % check_int == 0 {
-                    Self::do_checkpoint(
-                        &cur_jobs,
-                        last_chunk,
-                        &jobs_init,
-                        queue.chunk_size(),
-                        check_dir,
-                        dst,
-                    );
-                }
+                queue.options().compress_checkpoints(),
+                dst,
+            );
+            observer.on_event(&DrainEvent::CheckpointWritten { path });
+        }
+    }
+
+    /// finish a drain in which every job has either finished or failed. if any
+    /// failed, write a checkpoint with the failed jobs and return their indices
+    #[allow(clippy::too_many_arguments)]
+    fn finish<P, Q>(
+        &self,
+        queue: &Q,
+        running: Running<P>,
+        check: &Check,
+        last_chunk: Option<usize>,
+        jobs_init: &[Job<P>],
+        dst: &mut [Self::Item],
+        time: Timer,
+        observer: &dyn DrainObserver,
+    ) -> Result<f64, DrainError>
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+        Q: Queue<P> + ?Sized,
+        Self::Item: Serialize + Clone,
+    {
+        if !running.failed_jobs.is_empty() {
+            if let Check::Some { check_dir, .. } = check {
+                let path = Self::do_checkpoint(
+                    &running.cur_jobs,
+                    last_chunk,
+                    jobs_init,
+                    queue.chunk_size(),
+                    check_dir,
+                    queue.options().compress_checkpoints(),
+                    dst,
+                );
+                observer.on_event(&DrainEvent::CheckpointWritten { path });
             }
-            iter += 1;
+            return Err(DrainError::Failed(running.indices()));
         }
+        observer.on_event(&DrainEvent::Finished { timer: time });
+        Ok(running.job_time)
+    }
+
+    /// stop a cancelled drain, writing a checkpoint of the remaining jobs if
+    /// checkpoints are enabled. `cancelled` holds the ids of any queue jobs
+    /// already cancelled
+    #[allow(clippy::too_many_arguments)]
+    fn cancelled<P, Q>(
+        &self,
+        queue: &Q,
+        running: &Running<P>,
+        cancelled: Vec<String>,
+        check: &Check,
+        last_chunk: Option<usize>,
+        jobs_init: &[Job<P>],
+        dst: &mut [Self::Item],
+        observer: &dyn DrainObserver,
+    ) -> DrainError
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+        Q: Queue<P> + ?Sized,
+        Self::Item: Serialize + Clone,
+    {
+        let checkpoint = if let Check::Some { check_dir, .. } = check {
+            Some(Self::do_checkpoint(
+                &running.cur_jobs,
+                last_chunk,
+                jobs_init,
+                queue.chunk_size(),
+                check_dir,
+                queue.options().compress_checkpoints(),
+                dst,
+            ))
+        } else {
+            None
+        };
+        observer.on_event(&DrainEvent::Cancelled {
+            checkpoint: checkpoint.clone(),
+            cancelled,
+        });
+        DrainError::Cancelled { checkpoint }
     }
 
     /// load a checkpoint from the `checkpoint` file, storing the energies in
-    /// `dst` and returning the list of remaining jobs
+    /// `dst` and returning the list of remaining jobs. both the current and
+    /// older checkpoint formats are accepted, compressed or not
     fn load_checkpoint<P>(
         checkpoint: &str,
         dst: &mut [Self::Item],
@@ -329,36 +603,55 @@ pub(crate) trait Drain {
         P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
         Self::Item: Clone + for<'a> Deserialize<'a>,
     {
-        let Ok(f) = std::fs::File::open(checkpoint) else {
-            panic!("failed to open {checkpoint}");
-        };
-        let Checkpoint { dst: d, jobs } = serde_json::from_reader(f).unwrap();
+        let (d, jobs) = checkpoint::load(checkpoint);
         dst.clone_from_slice(&d);
         jobs
     }
 
+    /// [Drain::load_checkpoint] for resuming a drain, reporting the number of
+    /// jobs left to `observer`
+    fn resume_checkpoint<P>(
+        checkpoint: &str,
+        dst: &mut [Self::Item],
+        observer: &dyn DrainObserver,
+    ) -> Vec<Job<P>>
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+        Self::Item: Clone + for<'a> Deserialize<'a>,
+    {
+        let jobs = Self::load_checkpoint(checkpoint, dst);
+        observer.on_event(&DrainEvent::Resumed {
+            checkpoint: checkpoint.to_owned(),
+            remaining: jobs.len(),
+        });
+        jobs
+    }
+
+    /// atomically write `dst` and `jobs` to the `checkpoint` file
     fn write_checkpoint<P>(
         checkpoint: &str,
         dst: Vec<Self::Item>,
         jobs: Vec<Job<P>>,
+        compress: bool,
     ) where
         P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
         Self::Item: Serialize,
     {
-        let c = Checkpoint { dst, jobs };
-        eprintln!("writing checkpoint to {checkpoint}");
-        let f = std::fs::File::create(checkpoint).unwrap();
-        serde_json::to_writer_pretty(f, &c).unwrap();
+        checkpoint::write(checkpoint, dst, jobs, compress);
     }
 
+    /// write a checkpoint of the remaining jobs to `check_dir` and return the
+    /// path to the file
     fn do_checkpoint<P>(
         cur_jobs: &[Job<P>],
         last_chunk: Option<usize>,
         jobs_init: &[Job<P>],
         chunk_size: usize,
         check_dir: &str,
+        compress: bool,
         dst: &mut [<Self as Drain>::Item],
-    ) where
+    ) -> String
+    where
         P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
         Job<P>: Clone,
         Self::Item: Serialize + Clone,
@@ -373,69 +666,10 @@ pub(crate) trait Drain {
         cur_jobs.extend(
             jobs_init[(cn * chunk_size).min(jobs_init.len())..].to_vec(),
         );
-        Self::write_checkpoint(
-            &format!("{check_dir}/chk.json"),
-            dst.to_vec(),
-            cur_jobs,
-        );
-    }
-
-    /// Returns the number of chunks received
-    #[allow(clippy::too_many_arguments)]
-    fn receive_jobs<P, Q>(
-        &self,
-        chunks: &mut Peekable<Fuse<Enumerate<ChunksMut<Job<P>>>>>,
-        job_limit: usize,
-        cur_jobs: &mut Vec<Job<P>>,
-        queue: &Q,
-        dir: &str,
-        slurm_jobs: &mut HashMap<String, usize>,
-        time: &mut timer::Timer,
-        qstat: &mut HashSet<String>,
-        last_chunk: &mut Option<usize>,
-    ) -> usize
-    where
-        Self: Sync,
-        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
-        Q: Queue<P> + ?Sized + Sync,
-        <Self as Drain>::Item: Clone + Serialize,
-    {
-        use rayon::prelude::*;
-        let works: Vec<_> = chunks
-            .borrow_mut()
-            .take((job_limit - cur_jobs.len()) / queue.chunk_size())
-            // NOTE par_bridge does NOT preserve order
-            .par_bridge()
-            .map(|(chunk_num, jobs)| {
-                let now = std::time::Instant::now();
-                let (slurm_jobs, wi, ws, ss) =
-                    queue.build_chunk(dir, jobs, chunk_num, self.procedure());
-                let job_id = jobs[0].job_id.clone();
-                let elapsed = now.elapsed();
-                log::debug!(
-                    "submitted chunk {} after {:.1} s",
-                    chunk_num,
-                    elapsed.as_millis() as f64 / 1000.0
-                );
-                (jobs.to_vec(), slurm_jobs, job_id, wi, ws, ss, chunk_num)
-            })
-            .collect();
-        let ret = works.len();
-        for (jobs, sj, job_id, wi, ws, ss, cn) in works {
-            slurm_jobs.extend(sj);
-            time.writing_input += wi;
-            time.writing_script += ws;
-            time.submitting_script += ss;
-            qstat.insert(job_id);
-            cur_jobs.extend(jobs);
-            // necessary because par_bridge may swap order
-            if let Some(n) = *last_chunk {
-                *last_chunk = Some(usize::max(n, cn))
-            } else {
-                *last_chunk = Some(cn);
-            }
-        }
-        ret
+        let ext = if compress { "json.gz" } else { "json" };
+        let path = format!("{check_dir}/chk.{ext}");
+        Self::write_checkpoint(&path, dst.to_vec(), cur_jobs, compress);
+        path
     }
 }
 
@@ -456,19 +690,88 @@ fn get_cpu_time() -> f64 {
     }
 }
 
-fn wait<P, Q>(queue: &Q, time: &mut timer::Timer, iter: usize, remaining: usize)
+/// build a [DrainEvent::Submitted] for `jobs`, which were all submitted
+/// together as `job_id`
+fn submitted<P: Program>(job_id: &str, jobs: &[Job<P>]) -> DrainEvent {
+    DrainEvent::Submitted {
+        pbs_file: jobs.first().map(|j| j.pbs_file.clone()).unwrap_or_default(),
+        job_id: job_id.to_owned(),
+        jobs: jobs
+            .iter()
+            .map(|j| (j.index, j.program.filename()))
+            .collect(),
+    }
+}
+
+/// list the directories of the jobs in `running` on a remote `transport`, and
+/// then copy the markers and output files of the jobs whose markers have
+/// arrived. the markers are written after the programs exit, so this never
+/// copies partial outputs
+fn fetch<P: Program>(transport: &dyn Transport, running: &Running<P>) {
+    if !transport.is_remote() {
+        return;
+    }
+    let markers: Vec<_> = running
+        .cur_jobs
+        .iter()
+        .map(|j| marker::done_file(&j.program.filename()))
+        .collect();
+    let dirs: BTreeSet<_> = markers
+        .iter()
+        .filter_map(|m| std::path::Path::new(m).parent()?.to_str())
+        .map(str::to_owned)
+        .collect();
+    let dirs: Vec<_> = dirs.into_iter().collect();
+    let remote = transport.list(&dirs);
+    let files: Vec<_> = running
+        .cur_jobs
+        .iter()
+        .zip(markers)
+        .filter(|(_, m)| remote.contains(m))
+        .flat_map(|(j, m)| {
+            let mut files = j.program.output_files();
+            files.push(m);
+            files
+        })
+        .collect();
+    transport.get(&files);
+}
+
+/// pass the `qstat` just returned by [SubQueue::status] to the queue's
+/// `Recorder`, if the `replay` feature is enabled and it has one
+///
+/// [SubQueue::status]: crate::queue::SubQueue::status
+#[cfg_attr(not(feature = "replay"), allow(unused_variables))]
+fn record_status<P, Q>(queue: &Q, qstat: HashSet<String>) -> HashSet<String>
 where
     P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
-    Q: Queue<P> + ?Sized + Sync,
+    Q: Queue<P> + ?Sized,
 {
-    let date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
-    eprintln!(
-        "[iter {iter} {date} {:.1} CPU s] {remaining} jobs remaining",
-        get_cpu_time()
-    );
-    let d = time::Duration::from_secs(queue.sleep_int() as u64);
-    time.sleeping += d;
-    thread::sleep(d);
+    #[cfg(feature = "replay")]
+    if let Some(r) = queue.options().recorder() {
+        r.status(&qstat);
+    }
+    qstat
+}
+
+/// read the selected `outfiles` with [Program::read_outputs], leaving `None`
+/// for the others and for those that haven't changed. the seen times in
+/// `outfiles` are updated in place
+fn read_outputs<P: Program>(
+    outfiles: &mut [Option<(String, Option<std::time::SystemTime>)>],
+) -> Vec<Option<Result<ProgramResult, ProgramError>>> {
+    let (names, mut seen): (Vec<_>, Vec<_>) =
+        outfiles.iter().flatten().cloned().unzip();
+    let mut results = P::read_outputs(&names, &mut seen).into_iter();
+    let mut seen = seen.into_iter();
+    outfiles
+        .iter_mut()
+        .map(|out| {
+            let (_, s) = out.as_mut()?;
+            *s = seen.next().unwrap();
+            results.next().unwrap()
+        })
+        .collect()
 }
 
 pub(crate) struct Opt;
@@ -490,20 +793,13 @@ impl Drain for Opt {
     }
 }
 
-#[derive(Deserialize, Serialize)]
-struct Checkpoint<P, T>
-where
-    P: Program + Clone,
-{
-    dst: Vec<T>,
-    jobs: Vec<Job<P>>,
-}
-
 pub(crate) struct Single;
 
 impl Drain for Single {
     type Item = f64;
 
+    const INVARIANT: bool = true;
+
     fn procedure(&self) -> Procedure {
         Procedure::SinglePt
     }
@@ -536,3 +832,80 @@ impl Drain for Both {
         dst[job.index] = res;
     }
 }
+
+/// [Drain::validate] for the dipole drains, failing jobs without a dipole
+fn require_dipole<P: Program>(
+    job: &Job<P>,
+    res: ProgramResult,
+) -> Result<ProgramResult, ProgramError> {
+    if res.dipole.is_none() {
+        return Err(ProgramError::DipoleNotFound(job.program.filename()));
+    }
+    Ok(res)
+}
+
+/// accumulate the `coeff`-weighted dipole moment for each job, for building
+/// dipole surfaces
+pub(crate) struct Dipole;
+
+impl Drain for Dipole {
+    type Item = [f64; 3];
+
+    fn procedure(&self) -> Procedure {
+        Procedure::SinglePt
+    }
+
+    fn validate<P: Program>(
+        &self,
+        job: &Job<P>,
+        res: ProgramResult,
+    ) -> Result<ProgramResult, ProgramError> {
+        require_dipole(job, res)
+    }
+
+    fn set_result<P: Program>(
+        &self,
+        dst: &mut [Self::Item],
+        job: &mut Job<P>,
+        res: ProgramResult,
+    ) {
+        let dipole = res.dipole.expect("checked by Drain::validate");
+        for (d, r) in dst[job.index].iter_mut().zip(dipole) {
+            *d += job.coeff * r;
+        }
+    }
+}
+
+/// like [Single] but also accumulates the `coeff`-weighted dipole moment for
+/// each job
+pub(crate) struct EnergyDipole;
+
+impl Drain for EnergyDipole {
+    type Item = (f64, [f64; 3]);
+
+    fn procedure(&self) -> Procedure {
+        Procedure::SinglePt
+    }
+
+    fn validate<P: Program>(
+        &self,
+        job: &Job<P>,
+        res: ProgramResult,
+    ) -> Result<ProgramResult, ProgramError> {
+        require_dipole(job, res)
+    }
+
+    fn set_result<P: Program>(
+        &self,
+        dst: &mut [Self::Item],
+        job: &mut Job<P>,
+        res: ProgramResult,
+    ) {
+        let dipole = res.dipole.expect("checked by Drain::validate");
+        let (energy, dip) = &mut dst[job.index];
+        *energy += job.coeff * res.energy;
+        for (d, r) in dip.iter_mut().zip(dipole) {
+            *d += job.coeff * r;
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! a persistent cache of [ProgramResult]s keyed by the rendered input files,
+//! so that repeated calculations can skip the queue entirely
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::program::{Program, ProgramResult};
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    /// the [Program::KIND] of the program that produced `result`
+    program: String,
+
+    /// the contents of all of the input files, for detecting hash collisions
+    input: String,
+
+    result: ProgramResult,
+}
+
+/// a directory of JSON files, one per cached result
+pub(crate) struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub(crate) fn new(dir: &str) -> Self {
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+            panic!("failed to create cache directory {dir} with {e}")
+        });
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, program: &str, input: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", fnv1a([program, input])))
+    }
+
+    /// return the cached result of running `input` through `program`, if any
+    pub(crate) fn get(
+        &self,
+        program: &str,
+        input: &str,
+    ) -> Option<ProgramResult> {
+        let f = File::open(self.path(program, input)).ok()?;
+        let entry: Entry = serde_json::from_reader(BufReader::new(f)).ok()?;
+        (entry.program == program && entry.input == input)
+            .then_some(entry.result)
+    }
+
+    /// store `result` as the output of running `input` through `program`.
+    /// failing to write the cache is only logged since the result itself is
+    /// not affected
+    pub(crate) fn insert(
+        &self,
+        program: &str,
+        input: &str,
+        result: &ProgramResult,
+    ) {
+        let path = self.path(program, input);
+        let tmp = path.with_extension("tmp");
+        let entry = Entry {
+            program: program.to_owned(),
+            input: input.to_owned(),
+            result: result.clone(),
+        };
+        let res = File::create(&tmp)
+            .map_err(|e| e.to_string())
+            .and_then(|f| {
+                serde_json::to_writer(BufWriter::new(f), &entry)
+                    .map_err(|e| e.to_string())
+            })
+            .and_then(|_| {
+                std::fs::rename(&tmp, &path).map_err(|e| e.to_string())
+            });
+        if let Err(e) = res {
+            log::warn!("failed to write {} with {e}", path.display());
+        }
+    }
+}
+
+/// the contents of every file in [Program::input_files] for `program`,
+/// separated by NUL bytes, for use as a cache key. the input file alone is not
+/// enough since it may only refer to other files by name, like the parameter
+/// files of [crate::program::mopac::Mopac]. returns `None` if any of the files
+/// can't be read
+pub(crate) fn inputs<P: Program>(program: &P) -> Option<String> {
+    let files = program
+        .input_files()
+        .iter()
+        .map(std::fs::read_to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    Some(files.join("\0"))
+}
+
+/// 64-bit FNV-1a hash of `parts`, separated by NUL bytes. unlike
+/// [std::hash::DefaultHasher], this is stable across Rust versions, which
+/// matters for keys that outlive the process
+fn fnv1a<'a>(parts: impl IntoIterator<Item = &'a str>) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = OFFSET;
+    for (i, part) in parts.into_iter().enumerate() {
+        let sep = if i > 0 { &[0][..] } else { &[] };
+        for b in sep.iter().chain(part.as_bytes()) {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
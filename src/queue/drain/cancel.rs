@@ -0,0 +1,143 @@
+//! cooperative cancellation of a running drain
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+/// the number of times SIGINT or SIGTERM has been received, counted by
+/// [handler]
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+/// the value of [SIGNALS] when the most recent token called
+/// [CancelToken::with_signals]
+static ARMED: AtomicUsize = AtomicUsize::new(0);
+
+/// the signals that cancel tokens armed with [CancelToken::with_signals]
+const HANDLED: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+/// the number of live [Handlers] and the dispositions of [HANDLED] from
+/// before the first of them installed [handler], or `None` if [handler] is not
+/// installed
+static INSTALLED: Mutex<Option<(usize, [libc::sigaction; 2])>> =
+    Mutex::new(None);
+
+/// a second signal since the last token was armed exits immediately, in case
+/// the drain is stuck somewhere that doesn't check for cancellation. only
+/// async-signal-safe calls are allowed here
+extern "C" fn handler(_: libc::c_int) {
+    if SIGNALS.fetch_add(1, Ordering::SeqCst) > ARMED.load(Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// keeps [handler] installed for as long as a token armed with
+/// [CancelToken::with_signals], or one of its clones, is alive. dropping the
+/// last one restores the handlers that were there before
+#[derive(Debug)]
+struct Handlers(());
+
+impl Handlers {
+    fn install() -> Self {
+        let mut installed = INSTALLED.lock().unwrap();
+        match installed.as_mut() {
+            Some((count, _)) => *count += 1,
+            None => {
+                let previous = HANDLED.map(|sig| unsafe {
+                    let mut new: libc::sigaction = std::mem::zeroed();
+                    new.sa_sigaction = handler as extern "C" fn(libc::c_int)
+                        as libc::sighandler_t;
+                    libc::sigemptyset(&mut new.sa_mask);
+                    let mut old = std::mem::zeroed();
+                    libc::sigaction(sig, &new, &mut old);
+                    old
+                });
+                *installed = Some((1, previous));
+            }
+        }
+        Self(())
+    }
+}
+
+impl Drop for Handlers {
+    fn drop(&mut self) {
+        let mut installed = INSTALLED.lock().unwrap();
+        let Some((count, previous)) = installed.as_mut() else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            for (sig, old) in HANDLED.iter().zip(previous.iter()) {
+                unsafe { libc::sigaction(*sig, old, std::ptr::null_mut()) };
+            }
+            *installed = None;
+        }
+    }
+}
+
+/// a handle for stopping a drain from another thread or from a signal. clones
+/// share the same state, so keep one and give a clone to the queue with
+/// [DrainOptions::with_cancel_token]. when a drain sees that its
+/// token has been cancelled, it writes a checkpoint if checkpoints are
+/// enabled, optionally cancels its outstanding jobs with [SubQueue::cancel],
+/// and returns [DrainError::Cancelled]
+///
+/// [SubQueue::cancel]: crate::queue::SubQueue::cancel
+/// [DrainError::Cancelled]: super::DrainError::Cancelled
+/// [DrainOptions::with_cancel_token]:
+///     crate::queue::DrainOptions::with_cancel_token
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    /// the value of [SIGNALS] when this token started listening for signals
+    signals: Option<usize>,
+    handlers: Option<Arc<Handlers>>,
+    cancel_jobs: bool,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// also treat SIGINT and SIGTERM received from now on as cancellation.
+    /// this replaces any existing handlers for these signals in the whole
+    /// process until this token and all of its clones are dropped, including
+    /// the one held by the queue's [DrainOptions], at which point the previous
+    /// handlers are restored. a second signal exits the process immediately
+    /// with status 130. signals received before this call, for example by an
+    /// earlier token whose drain was cancelled, don't cancel this one
+    ///
+    /// [DrainOptions]: crate::queue::DrainOptions
+    pub fn with_signals(mut self) -> Self {
+        self.handlers = Some(Arc::new(Handlers::install()));
+        let n = SIGNALS.load(Ordering::SeqCst);
+        ARMED.store(n, Ordering::SeqCst);
+        self.signals = Some(n);
+        self
+    }
+
+    /// cancel the jobs still in the queue when the drain is cancelled, instead
+    /// of leaving them running to be reattached by a later resume
+    pub fn with_cancel_jobs(mut self) -> Self {
+        self.cancel_jobs = true;
+        self
+    }
+
+    /// request cancellation of every drain using this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self
+                .signals
+                .is_some_and(|n| SIGNALS.load(Ordering::SeqCst) > n)
+    }
+
+    /// whether outstanding jobs should be cancelled along with the drain
+    pub fn cancel_jobs(&self) -> bool {
+        self.cancel_jobs
+    }
+}
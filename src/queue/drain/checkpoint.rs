@@ -0,0 +1,153 @@
+//! the on-disk checkpoint format used by [super::Drain]. checkpoints are JSON,
+//! optionally gzip-compressed, and are always written to a temporary file
+//! first and then renamed into place so that an interrupted write never leaves
+//! a truncated checkpoint behind
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::program::{Job, Program};
+
+/// the current checkpoint format version. files without a version field are
+/// version 0, the original format with complete copies of every job
+pub(crate) const VERSION: usize = 1;
+
+/// fields of `Job::program` that are usually shared by every job in a drain.
+/// these are stored once in [Checkpoint::shared] and replaced by their index
+/// there in each job
+const SHARED_FIELDS: [&str; 2] = ["template", "params"];
+
+/// the first two bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Deserialize, Serialize)]
+struct Checkpoint<T> {
+    #[serde(default)]
+    version: usize,
+
+    /// deduplicated values of [SHARED_FIELDS]
+    #[serde(default)]
+    shared: Vec<Value>,
+
+    dst: Vec<T>,
+
+    jobs: Vec<Value>,
+}
+
+/// write `dst` and `jobs` to `path`, compressing the output with gzip if
+/// `compress` is true
+pub(crate) fn write<P, T>(
+    path: &str,
+    dst: Vec<T>,
+    jobs: Vec<Job<P>>,
+    compress: bool,
+) where
+    P: Program + Clone + Serialize,
+    T: Serialize,
+{
+    let mut shared: Vec<Value> = Vec::new();
+    let jobs = jobs
+        .iter()
+        .map(|job| {
+            let mut job = serde_json::to_value(job).unwrap();
+            let Some(program) =
+                job.get_mut("program").and_then(Value::as_object_mut)
+            else {
+                return job;
+            };
+            for field in SHARED_FIELDS {
+                let Some(v) = program.get_mut(field) else {
+                    continue;
+                };
+                // leave unset Options and the like alone
+                if !v.is_object() {
+                    continue;
+                }
+                let idx = match shared.iter().position(|s| s == v) {
+                    Some(i) => i,
+                    None => {
+                        shared.push(v.take());
+                        shared.len() - 1
+                    }
+                };
+                *v = idx.into();
+            }
+            job
+        })
+        .collect();
+    let c = Checkpoint {
+        version: VERSION,
+        shared,
+        dst,
+        jobs,
+    };
+
+    let tmp = format!("{path}.tmp");
+    let f = File::create(&tmp)
+        .unwrap_or_else(|e| panic!("failed to create {tmp} with {e}"));
+    let mut w = BufWriter::new(f);
+    if compress {
+        let mut enc = GzEncoder::new(w, Compression::default());
+        serde_json::to_writer(&mut enc, &c).unwrap();
+        w = enc.finish().unwrap();
+    } else {
+        serde_json::to_writer(&mut w, &c).unwrap();
+    }
+    w.flush().unwrap();
+    w.get_ref().sync_all().unwrap();
+    std::fs::rename(&tmp, path).unwrap_or_else(|e| {
+        panic!("failed to rename {tmp} to {path} with {e}")
+    });
+}
+
+/// load the `dst` and remaining jobs from the checkpoint in `path`. accepts
+/// both compressed and uncompressed files of any version up to [VERSION]
+pub(crate) fn load<P, T>(path: &str) -> (Vec<T>, Vec<Job<P>>)
+where
+    P: Program + Clone + for<'a> Deserialize<'a>,
+    T: for<'a> Deserialize<'a>,
+{
+    let Ok(f) = File::open(path) else {
+        panic!("failed to open {path}");
+    };
+    let mut r = BufReader::new(f);
+    let gz = r.fill_buf().unwrap().starts_with(&GZIP_MAGIC);
+    let c: Checkpoint<T> = if gz {
+        serde_json::from_reader(BufReader::new(GzDecoder::new(r)))
+    } else {
+        serde_json::from_reader(r)
+    }
+    .unwrap_or_else(|e| panic!("failed to parse {path} with {e}"));
+    if c.version > VERSION {
+        panic!(
+            "checkpoint version {} in {path} is newer than the supported \
+             version {VERSION}",
+            c.version
+        );
+    }
+    let jobs = c
+        .jobs
+        .into_iter()
+        .map(|mut job| {
+            if let Some(program) =
+                job.get_mut("program").and_then(Value::as_object_mut)
+            {
+                for field in SHARED_FIELDS {
+                    if let Some(v) = program.get_mut(field) {
+                        if let Some(i) = v.as_u64() {
+                            *v = c.shared[i as usize].clone();
+                        }
+                    }
+                }
+            }
+            serde_json::from_value(job).unwrap()
+        })
+        .collect();
+    (c.dst, jobs)
+}
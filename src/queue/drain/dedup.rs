@@ -0,0 +1,82 @@
+//! merging of identical jobs so that each distinct calculation only runs once
+
+use std::collections::HashMap;
+
+use crate::{
+    geom::Geom,
+    program::{Job, Procedure, Program},
+};
+
+use super::cache;
+
+/// return a key that is equal for two programs if and only if they render the
+/// same input files, apart from their file names. the inputs are rendered under
+/// the fixed name `scratch` and removed again afterwards. if `tol` is positive,
+/// the Cartesian coordinates in the geometry are rounded to multiples of `tol`
+/// first. this means that coordinates within `tol` of each other are usually,
+/// but not always, considered equal. returns `None` if the rendered inputs
+/// can't be read back
+fn key<P: Program + Clone>(
+    program: &P,
+    tol: f64,
+    scratch: &str,
+    proc: Procedure,
+) -> Option<String> {
+    let mut program = program.clone();
+    program.set_filename(scratch);
+    if let Some(Geom::Xyz(atoms)) = program.geom_mut().filter(|_| tol > 0.0) {
+        let round = |x: f64| (x / tol).round() * tol;
+        for atom in atoms {
+            atom.x = round(atom.x);
+            atom.y = round(atom.y);
+            atom.z = round(atom.z);
+        }
+    }
+    program.write_input(proc);
+    let ret = cache::inputs(&program);
+    for f in program.input_files() {
+        let _ = std::fs::remove_file(f);
+    }
+    ret
+}
+
+/// merge jobs in `jobs` with identical inputs, moving the `index` and `coeff`
+/// of each duplicate into the `dups` of the first job with that input. jobs
+/// whose Cartesian coordinates agree to within `tol` are also merged, and a
+/// `tol` of zero only merges exact matches. the inputs are compared by
+/// rendering them for `proc` in `dir`. returns the remaining jobs and the
+/// number of jobs merged
+pub(crate) fn dedup<P>(
+    jobs: Vec<Job<P>>,
+    tol: f64,
+    dir: &str,
+    proc: Procedure,
+) -> (Vec<Job<P>>, usize)
+where
+    P: Program + Clone,
+{
+    let scratch = format!("{dir}/dedup");
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut ret: Vec<Job<P>> = Vec::with_capacity(jobs.len());
+    let mut merged = 0;
+    for job in jobs {
+        let Some(k) = key(&job.program, tol, &scratch, proc) else {
+            ret.push(job);
+            continue;
+        };
+        match seen.get(&k) {
+            Some(&i) => {
+                let rep = &mut ret[i];
+                rep.dups.push((job.index, job.coeff));
+                rep.dups.extend(job.dups);
+                rep.symm_dups.extend(job.symm_dups);
+                merged += 1;
+            }
+            None => {
+                seen.insert(k, ret.len());
+                ret.push(job);
+            }
+        }
+    }
+    (ret, merged)
+}
@@ -0,0 +1,353 @@
+//! the drain loop shared by [Drain::drain] and the async drain. the loop is
+//! written once as an async fn over a [Driver], which supplies the few
+//! operations that block the thread in one version and are awaited in the
+//! other. the futures returned by [Blocking] are always ready on their first
+//! poll, so [block_on] can run the loop without an executor
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::pin,
+    task::{Context, Waker},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    program::{Job, Procedure, Program, ProgramError, ProgramResult},
+    queue::Queue,
+};
+
+use super::{
+    cache::Cache, dump::Dump, fetch, poll::Poll, read_outputs, record_status,
+    resub::Resub, running::Running, submitted, watch::Watch, CancelToken,
+    Check, Drain, DrainError, DrainEvent, DrainObserver, Timer,
+};
+
+/// the output files selected for reading by [Watch::outfiles]
+pub(crate) type Outfiles = Vec<Option<(String, Option<std::time::SystemTime>)>>;
+
+/// the results of reading [Outfiles] with [read_outputs]
+pub(crate) type Results = Vec<Option<Result<ProgramResult, ProgramError>>>;
+
+/// the submit script counts and the times spent writing the input files,
+/// writing the submit script, and submitting it for one chunk, as returned by
+/// [Queue::build_chunk]
+pub(crate) type Chunk = (HashMap<String, usize>, Duration, Duration, Duration);
+
+/// the operations of the drain loop that wait on the queue or the file system
+pub(crate) trait Driver<P, Q>
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P> + ?Sized,
+{
+    /// the jobs in the queue, as in [SubQueue::status]
+    ///
+    /// [SubQueue::status]: crate::queue::SubQueue::status
+    fn status(&self, queue: &Q) -> impl Future<Output = HashSet<String>>;
+
+    /// write and submit `jobs` in chunks of [SubQueue::chunk_size], naming
+    /// the submit scripts after `base` and numbering them from `first`. the
+    /// queue id of each chunk is stored in its jobs
+    ///
+    /// [SubQueue::chunk_size]: crate::queue::SubQueue::chunk_size
+    fn submit(
+        &self,
+        queue: &Q,
+        dir: &str,
+        base: &str,
+        first: usize,
+        jobs: &mut [Job<P>],
+        proc: Procedure,
+    ) -> impl Future<Output = Vec<Chunk>>;
+
+    /// read the selected `outfiles` with [read_outputs], returning them with
+    /// their seen times updated along with the results
+    fn read_outputs(
+        &self,
+        outfiles: Outfiles,
+    ) -> impl Future<Output = (Outfiles, Results)>;
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()>;
+}
+
+/// the [Driver] for [Drain::drain], which blocks the thread for everything
+pub(crate) struct Blocking;
+
+impl<P, Q> Driver<P, Q> for Blocking
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P> + ?Sized + Sync,
+{
+    async fn status(&self, queue: &Q) -> HashSet<String> {
+        queue.status()
+    }
+
+    /// the chunks are written and submitted in parallel
+    async fn submit(
+        &self,
+        queue: &Q,
+        dir: &str,
+        base: &str,
+        first: usize,
+        jobs: &mut [Job<P>],
+        proc: Procedure,
+    ) -> Vec<Chunk> {
+        use rayon::prelude::*;
+        jobs.par_chunks_mut(queue.chunk_size())
+            .enumerate()
+            .map(|(i, jobs)| {
+                queue.build_chunk_inner(dir, base, first + i, jobs, proc)
+            })
+            .collect()
+    }
+
+    async fn read_outputs(
+        &self,
+        mut outfiles: Outfiles,
+    ) -> (Outfiles, Results) {
+        let results = read_outputs::<P>(&mut outfiles);
+        (outfiles, results)
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// run `fut` to completion on the current thread. this panics if `fut` is not
+/// ready on its first poll, so it can only be used with [Blocking]
+pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut cx = Context::from_waker(Waker::noop());
+    match pin!(fut).poll(&mut cx) {
+        std::task::Poll::Ready(ret) => ret,
+        std::task::Poll::Pending => panic!("blocking drain future was pending"),
+    }
+}
+
+/// drain `jobs` from `queue` into `dst` with `d`, waiting on the queue with
+/// `driver` and reporting progress to `observer`. this is the body of
+/// [Drain::drain] and of the async drain
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn run<D, T, P, Q>(
+    d: &D,
+    driver: &T,
+    dir: &str,
+    queue: &Q,
+    mut jobs: Vec<Job<P>>,
+    dst: &mut [D::Item],
+    check: Check,
+    observer: &dyn DrainObserver,
+) -> Result<f64, DrainError>
+where
+    D: Drain + Sync + ?Sized,
+    D::Item: Clone + Serialize,
+    T: Driver<P, Q>,
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P> + ?Sized + Sync,
+{
+    // jobs loaded from a checkpoint may have been submitted by a previous
+    // process. reattach to the ones still in the queue or whose output is
+    // already present instead of submitting them again
+    let qstat = if jobs.iter().any(|job| !job.job_id.is_empty()) {
+        record_status(queue, driver.status(queue).await)
+    } else {
+        HashSet::new()
+    };
+    let options = queue.options();
+    let mut running;
+    (jobs, running) = Running::new(jobs, qstat, options.transport());
+
+    let dump = Dump::new(queue.no_del(), options.remote_transport());
+    let cache = options.cache_dir().map(Cache::new);
+    d.prepare(
+        queue,
+        dir,
+        &mut jobs,
+        &mut running,
+        dst,
+        cache.as_ref(),
+        &dump,
+        observer,
+    )?;
+    #[cfg(feature = "replay")]
+    if let Some(r) = options.recorder() {
+        r.start(queue, dir, running.cur_jobs.iter().chain(&jobs));
+    }
+
+    let chunk_size = queue.chunk_size();
+    let job_limit = queue.job_limit();
+    let chunks = jobs.len().div_ceil(chunk_size);
+    let mut time = Timer::default();
+    let total_jobs = running.remaining;
+    // for fast jobs, it may be necessary to stop and clean up even if
+    // finished != 0. this is used to signal that case
+    let mut cleanup_intervals = (0..total_jobs).step_by(job_limit).peekable();
+    // `jobs` is only borrowed one batch of chunks at a time, so the jobs after
+    // `last_chunk` are still unsubmitted and can be used directly for
+    // checkpoints
+    let mut last_chunk: Option<usize> = None;
+    let mut resub = Resub::default();
+    let mut watch = Watch::new(options.watch_outputs());
+    let mut poll = Poll::new(queue.sleep_int(), options.poll_range());
+    let mut iter = 0;
+    loop {
+        let loop_time = Instant::now();
+        if let Some(token) = options.cancel_token().filter(|t| t.is_cancelled())
+        {
+            let mut cancelled = Vec::new();
+            if token.cancel_jobs() {
+                cancelled = running.take_job_ids();
+                queue.cancel(&cancelled);
+            }
+            dump.shutdown();
+            return Err(d.cancelled(
+                queue, &running, cancelled, &check, last_chunk, &jobs, dst,
+                observer,
+            ));
+        }
+
+        let next = last_chunk.map_or(0, |n| n + 1);
+        let free =
+            job_limit.saturating_sub(running.cur_jobs.len()) / chunk_size;
+        let end = chunks.min(next + free);
+        if end > next {
+            let batch_end = jobs.len().min(end * chunk_size);
+            let batch = &mut jobs[next * chunk_size..batch_end];
+            let built = driver
+                .submit(queue, dir, "main", next, batch, d.procedure())
+                .await;
+            add_chunks(queue, &mut running, &mut time, batch, built, observer);
+            last_chunk = Some(end - 1);
+            log::trace!("received {} chunks of jobs", end - next);
+        }
+        let out_of_jobs = next.max(end) >= chunks;
+
+        // collect output
+        let now = Instant::now();
+        fetch(options.transport(), &running);
+        let outfiles = watch.outfiles(&running, iter, &mut time);
+        let (outfiles, results) = driver.read_outputs(outfiles).await;
+        running.set_seen(outfiles);
+        time.reading += now.elapsed();
+        let (finished, resubs) = d.collect(
+            queue,
+            &mut running,
+            results,
+            dst,
+            cache.as_ref(),
+            &dump,
+            &mut time,
+            observer,
+        );
+
+        // submit resubs
+        for job in resubs {
+            resub.push(job);
+        }
+        let mut redo = resub.take();
+        if !redo.is_empty() {
+            let first = resub.next_chunks(redo.len().div_ceil(chunk_size));
+            let built = driver
+                .submit(queue, dir, "redo", first, &mut redo, d.procedure())
+                .await;
+            add_chunks(queue, &mut running, &mut time, &redo, built, observer);
+        }
+        log::debug!(
+            "finished {} jobs in {:.1} s",
+            finished,
+            loop_time.elapsed().as_millis() as f64 / 1000.0
+        );
+
+        if running.is_done(out_of_jobs) {
+            running.cancel_orphans(queue, observer);
+            dump.shutdown();
+            return d.finish(
+                queue, running, &check, last_chunk, &jobs, dst, time, observer,
+            );
+        }
+        if finished == 0 {
+            let interval = poll.next(&mut running);
+            let remaining = running.remaining;
+            wait(
+                driver, queue, &mut time, iter, remaining, interval, observer,
+            )
+            .await;
+            running.qstat = record_status(queue, driver.status(queue).await);
+            running.cancel_orphans(queue, observer);
+        } else if total_jobs - running.remaining
+            > *cleanup_intervals.peek().unwrap_or(&total_jobs)
+        {
+            let interval = poll.next(&mut running);
+            let remaining = running.remaining;
+            wait(
+                driver, queue, &mut time, iter, remaining, interval, observer,
+            )
+            .await;
+            cleanup_intervals.next();
+        }
+        d.interval_checkpoint(
+            queue, &running, &check, iter, last_chunk, &jobs, dst, observer,
+        );
+        iter += 1;
+    }
+}
+
+/// add the chunks of `jobs` just submitted by [Driver::submit] to `running`
+fn add_chunks<P, Q>(
+    queue: &Q,
+    running: &mut Running<P>,
+    time: &mut Timer,
+    jobs: &[Job<P>],
+    built: Vec<Chunk>,
+    observer: &dyn DrainObserver,
+) where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P> + ?Sized,
+{
+    for (jobs, (slurm_jobs, wi, ws, ss)) in
+        jobs.chunks(queue.chunk_size()).zip(built)
+    {
+        let job_id = jobs[0].job_id.clone();
+        observer.on_event(&submitted(&job_id, jobs));
+        time.writing_input += wi;
+        time.writing_script += ws;
+        time.submitting_script += ss;
+        running.add(job_id, jobs.to_vec(), slurm_jobs);
+    }
+}
+
+/// report the end of iteration `iter` to `observer` and then sleep for
+/// `interval`, or until the queue's [CancelToken] is cancelled
+async fn wait<T, P, Q>(
+    driver: &T,
+    queue: &Q,
+    time: &mut Timer,
+    iter: usize,
+    remaining: usize,
+    interval: Duration,
+    observer: &dyn DrainObserver,
+) where
+    T: Driver<P, Q>,
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P> + ?Sized,
+{
+    observer.on_event(&DrainEvent::iteration(iter, remaining, time, interval));
+    // sleep in short steps so that cancellation is noticed promptly
+    const STEP: Duration = Duration::from_millis(100);
+    let now = Instant::now();
+    let end = now + interval;
+    loop {
+        let left = end.saturating_duration_since(Instant::now());
+        let cancelled = queue
+            .options()
+            .cancel_token()
+            .is_some_and(CancelToken::is_cancelled);
+        if left.is_zero() || cancelled {
+            break;
+        }
+        driver.sleep(left.min(STEP)).await;
+    }
+    time.sleeping += now.elapsed();
+}
@@ -1,17 +1,20 @@
 use std::{
     sync::{
         mpsc::{self, Sender, SyncSender},
-        LazyLock,
+        Arc, LazyLock,
     },
     thread::{self, JoinHandle},
 };
 
+use crate::queue::Transport;
+
 static DUMP_DEBUG: LazyLock<bool> =
     LazyLock::new(|| std::env::var("DUMP_DEBUG").is_ok());
 
 /// a garbage heap that spawns another thread and sends filenames to be deleted.
 /// the `None` variant is used when no_del is enabled to turn every method into
-/// a no op
+/// a no op. with a remote [Transport], the files are also deleted from the
+/// remote machine, in batches of whatever has been sent so far
 pub(crate) enum Dump {
     Real {
         /// handle for spawned thread
@@ -38,7 +41,10 @@ fn debug_handler(file: &str, e: std::io::Result<()>) {
 }
 
 impl Dump {
-    pub(crate) fn new(no_del: bool) -> Self {
+    pub(crate) fn new(
+        no_del: bool,
+        transport: Option<Arc<dyn Transport>>,
+    ) -> Self {
         if no_del {
             return Self::None;
         }
@@ -53,11 +59,18 @@ impl Dump {
         };
 
         let handle = thread::spawn(move || {
-            for file in receiver {
+            while let Ok(file) = receiver.recv() {
                 if exit.try_recv().is_ok() {
                     return;
                 }
-                err_handler(&file, std::fs::remove_file(&file));
+                let mut batch = vec![file];
+                if let Some(transport) = &transport {
+                    batch.extend(receiver.try_iter());
+                    transport.remove(&batch);
+                }
+                for file in &batch {
+                    err_handler(file, std::fs::remove_file(file));
+                }
             }
         });
 
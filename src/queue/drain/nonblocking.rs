@@ -0,0 +1,208 @@
+//! an async counterpart of [Drain::drain] for running several drains
+//! concurrently on one tokio runtime. the scheduler commands are spawned with
+//! [tokio::process] and the waits between polls use [tokio::time], while
+//! output files are read on tokio's blocking thread pool. writing input files
+//! and submit scripts is still done directly on the runtime thread. the drain
+//! loop itself is shared with [Drain::drain] through [Driver]
+//!
+//! [Drain::drain]: super::Drain::drain
+
+use std::{collections::HashSet, future::Future, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geom::Geom,
+    program::{Job, Procedure, Program, ProgramResult},
+    queue::Queue,
+};
+
+use super::{
+    driver::{run, Chunk, Driver, Outfiles, Results},
+    read_outputs, Both, Check, DrainError, DrainObserver, Opt, Single,
+};
+
+/// a [Queue] that can also be drained without blocking a thread, so that
+/// several drains can be multiplexed on one tokio runtime. requires the
+/// `async` feature
+pub trait AsyncQueue<P>: Queue<P> + Sync
+where
+    P: Program
+        + Clone
+        + Send
+        + Sync
+        + Serialize
+        + for<'a> Deserialize<'a>
+        + 'static,
+{
+    /// like [SubQueue::status] but without blocking the thread
+    ///
+    /// [SubQueue::status]: crate::queue::SubQueue::status
+    fn status_async(&self) -> impl Future<Output = HashSet<String>> + Send;
+
+    /// like [Submit::submit] but without blocking the thread. the default
+    /// implementation runs [Submit::submit_cmd], retrying every second until
+    /// it succeeds
+    ///
+    /// [Submit::submit]: crate::queue::Submit::submit
+    /// [Submit::submit_cmd]: crate::queue::Submit::submit_cmd
+    fn submit_async(
+        &self,
+        filename: &str,
+    ) -> impl Future<Output = String> + Send {
+        async move {
+            loop {
+                let mut cmd = tokio::process::Command::from(
+                    self.options()
+                        .transport()
+                        .command(self.submit_cmd(filename)),
+                );
+                match cmd.output().await {
+                    Ok(s) if s.status.success() => {
+                        let raw = String::from_utf8_lossy(&s.stdout);
+                        return raw
+                            .split_whitespace()
+                            .last()
+                            .unwrap_or("")
+                            .to_string();
+                    }
+                    Ok(s) => {
+                        eprintln!(
+                            "failed to submit {filename} with `{}`",
+                            String::from_utf8_lossy(&s.stderr)
+                        );
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                    Err(e) => panic!("{e:?}"),
+                }
+            }
+        }
+    }
+
+    /// an async counterpart of [Queue::try_drain]
+    fn drain_async(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [f64],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> impl Future<Output = Result<f64, DrainError>> + Send {
+        run(&Single, &NonBlocking, dir, self, jobs, dst, check, observer)
+    }
+
+    /// an async counterpart of [Queue::try_optimize]
+    fn optimize_async(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [Geom],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> impl Future<Output = Result<f64, DrainError>> + Send {
+        run(&Opt, &NonBlocking, dir, self, jobs, dst, check, observer)
+    }
+
+    /// an async counterpart of [Queue::try_energize]
+    fn energize_async(
+        &self,
+        dir: &str,
+        jobs: Vec<Job<P>>,
+        dst: &mut [ProgramResult],
+        check: Check,
+        observer: &dyn DrainObserver,
+    ) -> impl Future<Output = Result<f64, DrainError>> + Send {
+        run(&Both, &NonBlocking, dir, self, jobs, dst, check, observer)
+    }
+}
+
+/// the [Driver] for the async drain, which awaits the scheduler commands and
+/// the waits between polls, and reads the output files on the blocking thread
+/// pool
+struct NonBlocking;
+
+impl<P, Q> Driver<P, Q> for NonBlocking
+where
+    P: Program
+        + Clone
+        + Send
+        + Sync
+        + Serialize
+        + for<'a> Deserialize<'a>
+        + 'static,
+    Q: AsyncQueue<P> + ?Sized,
+{
+    fn status(&self, queue: &Q) -> impl Future<Output = HashSet<String>> {
+        queue.status_async()
+    }
+
+    /// the chunks are submitted one at a time
+    async fn submit(
+        &self,
+        queue: &Q,
+        dir: &str,
+        base: &str,
+        first: usize,
+        jobs: &mut [Job<P>],
+        proc: Procedure,
+    ) -> Vec<Chunk> {
+        let mut ret = Vec::new();
+        for (i, jobs) in jobs.chunks_mut(queue.chunk_size()).enumerate() {
+            ret.push(
+                submit_chunk(queue, dir, base, first + i, jobs, proc).await,
+            );
+        }
+        ret
+    }
+
+    async fn read_outputs(
+        &self,
+        mut outfiles: Outfiles,
+    ) -> (Outfiles, Results) {
+        tokio::task::spawn_blocking(move || {
+            let results = read_outputs::<P>(&mut outfiles);
+            (outfiles, results)
+        })
+        .await
+        .expect("failed to read output files")
+    }
+
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> {
+        tokio::time::sleep(duration)
+    }
+}
+
+/// write the input files and submit script for `jobs` and submit it with
+/// [AsyncQueue::submit_async]
+async fn submit_chunk<P, Q>(
+    queue: &Q,
+    dir: &str,
+    base: &str,
+    chunk_num: usize,
+    jobs: &mut [Job<P>],
+    proc: Procedure,
+) -> Chunk
+where
+    P: Program
+        + Clone
+        + Send
+        + Sync
+        + Serialize
+        + for<'a> Deserialize<'a>
+        + 'static,
+    Q: AsyncQueue<P> + ?Sized,
+{
+    let (queue_file, slurm_jobs, wi, ws) =
+        queue.write_chunk(dir, base, chunk_num, jobs, proc);
+    let now = std::time::Instant::now();
+    let job_id = queue.submit_async(&queue_file).await;
+    let ss = now.elapsed();
+    #[cfg(feature = "replay")]
+    if let Some(r) = queue.options().recorder() {
+        r.submit(&queue_file, &job_id);
+    }
+    for job in jobs.iter_mut() {
+        job.job_id = job_id.clone();
+    }
+    (slurm_jobs, wi, ws, ss)
+}
@@ -0,0 +1,435 @@
+//! progress reporting for drains
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+
+use super::{get_cpu_time, timer::Timer};
+
+/// an event in the progress of a drain, passed to [DrainObserver::on_event]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum DrainEvent {
+    /// the drain is resuming from the checkpoint file `checkpoint`, with
+    /// `remaining` jobs left to run
+    Resumed {
+        checkpoint: String,
+        remaining: usize,
+    },
+
+    /// `merged` jobs were identical to other jobs and will not be run, leaving
+    /// `jobs` calculations
+    Deduplicated { merged: usize, jobs: usize },
+
+    /// the submit script `pbs_file` containing `jobs`, as pairs of job index
+    /// and filename, was submitted to the queue with id `job_id`. this covers
+    /// both new chunks and resubmissions
+    Submitted {
+        pbs_file: String,
+        job_id: String,
+        jobs: Vec<(usize, String)>,
+    },
+
+    /// the job stored at `index` finished successfully, taking `time` seconds
+    /// as reported by the program
+    JobFinished {
+        index: usize,
+        filename: String,
+        job_id: String,
+        pbs_file: String,
+        time: f64,
+    },
+
+    /// the job stored at `index` finished with an error in its output
+    JobFailed {
+        index: usize,
+        filename: String,
+        job_id: String,
+        pbs_file: String,
+        error: String,
+        kind: &'static str,
+    },
+
+    /// the job stored at `index` is no longer in the queue and its output
+    /// could not be read, so it is being resubmitted
+    Resubmitting {
+        index: usize,
+        filename: String,
+        job_id: String,
+        pbs_file: String,
+        error: String,
+        kind: &'static str,
+    },
+
+    /// the queue jobs in `job_ids` were cancelled because all of their jobs
+    /// had already been resubmitted
+    OrphansCancelled { job_ids: Vec<String> },
+
+    /// a checkpoint was written to `path`
+    CheckpointWritten { path: String },
+
+    /// the end of iteration `iter` of the drain loop, just before sleeping
+    Iteration {
+        iter: usize,
+        remaining: usize,
+        cpu_time: f64,
+        timer: Timer,
+
+        /// the number of seconds the drain is about to sleep for
+        interval: f64,
+    },
+
+    /// all of the jobs finished, with the total time spent in each part of the
+    /// drain in `timer`
+    Finished { timer: Timer },
+
+    /// the drain was cancelled. `checkpoint` is the checkpoint written before
+    /// stopping, if any, and `cancelled` holds the ids of the queue jobs that
+    /// were cancelled with it
+    Cancelled {
+        checkpoint: Option<String>,
+        cancelled: Vec<String>,
+    },
+}
+
+/// a type that is notified of each [DrainEvent] in a drain. any
+/// `Fn(&DrainEvent)` closure is also an observer, so a channel can be used
+/// with something like `move |e: &DrainEvent| tx.send(e.clone()).unwrap()`
+pub trait DrainObserver: Send + Sync {
+    fn on_event(&self, event: &DrainEvent);
+}
+
+impl<F> DrainObserver for F
+where
+    F: Fn(&DrainEvent) + Send + Sync,
+{
+    fn on_event(&self, event: &DrainEvent) {
+        self(event)
+    }
+}
+
+/// notify both observers in turn, for example to write a [JsonlLog] while
+/// still printing progress with [StderrObserver]
+impl<A, B> DrainObserver for (A, B)
+where
+    A: DrainObserver,
+    B: DrainObserver,
+{
+    fn on_event(&self, event: &DrainEvent) {
+        self.0.on_event(event);
+        self.1.on_event(event);
+    }
+}
+
+impl std::fmt::Debug for dyn DrainObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DrainObserver")
+    }
+}
+
+/// the default observer, which prints progress messages to stderr
+pub struct StderrObserver;
+
+impl DrainObserver for StderrObserver {
+    fn on_event(&self, event: &DrainEvent) {
+        match event {
+            DrainEvent::Resumed {
+                checkpoint,
+                remaining,
+            } => {
+                eprintln!(
+                    "resuming from checkpoint in '{checkpoint}' with \
+                     {remaining} jobs remaining"
+                );
+            }
+            DrainEvent::Deduplicated { merged, jobs } => {
+                eprintln!(
+                    "merged {merged} duplicate jobs, running {jobs} \
+                     calculations"
+                );
+            }
+            DrainEvent::Submitted { .. } | DrainEvent::JobFinished { .. } => {}
+            DrainEvent::JobFailed { error, .. } => {
+                log::warn!("job failed with `{error}`");
+            }
+            DrainEvent::Resubmitting {
+                filename,
+                job_id,
+                error,
+                ..
+            } => {
+                eprintln!("resubmitting {filename} (id={job_id}) for {error}");
+            }
+            DrainEvent::OrphansCancelled { job_ids } => {
+                eprintln!(
+                    "cancelled orphaned queue jobs {}",
+                    job_ids.join(", ")
+                );
+            }
+            DrainEvent::CheckpointWritten { path } => {
+                eprintln!("wrote checkpoint to {path}");
+            }
+            DrainEvent::Iteration {
+                iter,
+                remaining,
+                cpu_time,
+                interval,
+                ..
+            } => {
+                let date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+                eprintln!(
+                    "[iter {iter} {date} {cpu_time:.1} CPU s] {remaining} jobs \
+                     remaining, sleeping {interval:.1} s",
+                );
+            }
+            DrainEvent::Finished { timer } => eprintln!("{timer}"),
+            DrainEvent::Cancelled {
+                checkpoint,
+                cancelled,
+            } => {
+                eprintln!(
+                    "drain cancelled, cancelled {} queue jobs",
+                    cancelled.len()
+                );
+                if let Some(path) = checkpoint {
+                    eprintln!("resume from the checkpoint in {path}");
+                }
+            }
+        }
+    }
+}
+
+impl DrainEvent {
+    pub(crate) fn iteration(
+        iter: usize,
+        remaining: usize,
+        timer: &Timer,
+        interval: Duration,
+    ) -> Self {
+        Self::Iteration {
+            iter,
+            remaining,
+            cpu_time: get_cpu_time(),
+            timer: timer.clone(),
+            interval: interval.as_secs_f64(),
+        }
+    }
+}
+
+/// a JSON-lines file written one value per line, possibly from several
+/// threads
+#[derive(Debug)]
+pub(crate) struct JsonlWriter {
+    out: Mutex<LineWriter<File>>,
+
+    /// a description of the file for the warning logged when a write fails
+    what: &'static str,
+}
+
+impl JsonlWriter {
+    /// create a new file at `path`, truncating it if it already exists
+    pub(crate) fn create(
+        path: impl AsRef<Path>,
+        what: &'static str,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            out: Mutex::new(LineWriter::new(File::create(path)?)),
+            what,
+        })
+    }
+
+    /// append `value` as one line. failures are only logged, since losing a
+    /// line of a log shouldn't stop the drain
+    pub(crate) fn write(&self, value: &impl Serialize) {
+        let mut out = self.out.lock().unwrap();
+        let res = serde_json::to_writer(&mut *out, value)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(out));
+        if let Err(e) = res {
+            log::warn!("failed to write {} with {e}", self.what);
+        }
+    }
+}
+
+/// one line of a [JsonlLog]. every record has the same fields so that the log
+/// can be loaded directly into a table
+#[derive(Serialize)]
+struct Record<'a> {
+    /// one of `submitted`, `finished`, `failed`, or `resubmitted`
+    event: &'a str,
+    index: usize,
+    filename: &'a str,
+    job_id: &'a str,
+    pbs_file: &'a str,
+
+    /// which attempt at running the job this is, starting from 1 for its first
+    /// submission and counting each resubmission after that
+    attempt: usize,
+
+    /// when the job was submitted, in seconds since the Unix epoch
+    submit_time: Option<f64>,
+
+    /// when the job was found to be finished or failed, in seconds since the
+    /// Unix epoch
+    finish_time: Option<f64>,
+
+    /// the job time reported in [crate::program::ProgramResult::time]
+    program_time: Option<f64>,
+
+    /// the [crate::program::ProgramError::kind] of the error, if any
+    error: Option<&'a str>,
+}
+
+/// an observer that writes a JSON-lines log of every job submission,
+/// completion, failure, and resubmission in a drain
+pub struct JsonlLog {
+    out: JsonlWriter,
+
+    /// submission times by job filename
+    submitted: Mutex<HashMap<String, f64>>,
+
+    /// the current attempt at each job, by index
+    attempts: Mutex<HashMap<usize, usize>>,
+}
+
+impl JsonlLog {
+    /// create a new log at `path`, truncating it if it already exists
+    pub fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            out: JsonlWriter::create(path, "run log")?,
+            submitted: Mutex::new(HashMap::new()),
+            attempts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn write(&self, record: &Record) {
+        self.out.write(record);
+    }
+
+    /// start another attempt at the job with `index`, returning its number
+    fn next_attempt(&self, index: usize) -> usize {
+        let mut attempts = self.attempts.lock().unwrap();
+        let n = attempts.entry(index).or_default();
+        *n += 1;
+        *n
+    }
+
+    /// the current attempt at the job with `index`
+    fn attempt(&self, index: usize) -> usize {
+        self.attempts
+            .lock()
+            .unwrap()
+            .get(&index)
+            .copied()
+            .unwrap_or(1)
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
+impl DrainObserver for JsonlLog {
+    fn on_event(&self, event: &DrainEvent) {
+        let time = now();
+        let submit_time =
+            |f: &str| self.submitted.lock().unwrap().get(f).copied();
+        match event {
+            DrainEvent::Submitted {
+                pbs_file,
+                job_id,
+                jobs,
+            } => {
+                let mut submitted = self.submitted.lock().unwrap();
+                for (index, filename) in jobs {
+                    submitted.insert(filename.clone(), time);
+                    self.write(&Record {
+                        event: "submitted",
+                        index: *index,
+                        filename,
+                        job_id,
+                        pbs_file,
+                        attempt: self.next_attempt(*index),
+                        submit_time: Some(time),
+                        finish_time: None,
+                        program_time: None,
+                        error: None,
+                    });
+                }
+            }
+            DrainEvent::JobFinished {
+                index,
+                filename,
+                job_id,
+                pbs_file,
+                time: program_time,
+            } => self.write(&Record {
+                event: "finished",
+                index: *index,
+                filename,
+                job_id,
+                pbs_file,
+                attempt: self.attempt(*index),
+                submit_time: submit_time(filename),
+                finish_time: Some(time),
+                program_time: Some(*program_time),
+                error: None,
+            }),
+            DrainEvent::JobFailed {
+                index,
+                filename,
+                job_id,
+                pbs_file,
+                kind,
+                ..
+            } => self.write(&Record {
+                event: "failed",
+                index: *index,
+                filename,
+                job_id,
+                pbs_file,
+                attempt: self.attempt(*index),
+                submit_time: submit_time(filename),
+                finish_time: Some(time),
+                program_time: None,
+                error: Some(kind),
+            }),
+            DrainEvent::Resubmitting {
+                index,
+                filename,
+                job_id,
+                pbs_file,
+                kind,
+                ..
+            } => self.write(&Record {
+                event: "resubmitted",
+                index: *index,
+                filename,
+                job_id,
+                pbs_file,
+                attempt: self.next_attempt(*index),
+                submit_time: submit_time(filename),
+                finish_time: None,
+                program_time: None,
+                error: Some(kind),
+            }),
+            DrainEvent::Resumed { .. }
+            | DrainEvent::Deduplicated { .. }
+            | DrainEvent::OrphansCancelled { .. }
+            | DrainEvent::CheckpointWritten { .. }
+            | DrainEvent::Iteration { .. }
+            | DrainEvent::Finished { .. }
+            | DrainEvent::Cancelled { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! the interval to sleep between checks of the queue. by default this is
+//! always [Queue::sleep_int], but with [DrainOptions::poll_range] set, it
+//! adapts to the observed rate of completions and to the run times reported
+//! by the jobs, so that fast jobs are collected promptly and long jobs aren't
+//! checked on far more often than they finish
+//!
+//! [Queue::sleep_int]: crate::queue::Queue::sleep_int
+//! [DrainOptions::poll_range]: crate::queue::DrainOptions::poll_range
+
+use std::{collections::VecDeque, time::Duration, time::Instant};
+
+use crate::program::Program;
+
+use super::running::Running;
+
+/// the number of recent job times used for the median
+const WINDOW: usize = 256;
+
+/// never sleep longer than this fraction of the median job time, which bounds
+/// how late a typical job is collected relative to its run time
+const FRACTION: f64 = 0.25;
+
+/// the factor to back off by while no jobs have finished
+const BACKOFF: f64 = 1.5;
+
+pub(crate) struct Poll {
+    /// the minimum and maximum interval in seconds. `None` for a fixed interval
+    range: Option<(f64, f64)>,
+
+    /// the current interval in seconds
+    interval: f64,
+
+    start: Instant,
+
+    /// the number of jobs finished so far
+    finished: usize,
+
+    /// the run times of the most recently finished jobs
+    times: VecDeque<f64>,
+}
+
+impl Poll {
+    pub(crate) fn new(sleep_int: usize, range: Option<(usize, usize)>) -> Self {
+        let range = range.map(|(min, max)| (min as f64, max.max(min) as f64));
+        let mut interval = sleep_int as f64;
+        if let Some((min, max)) = range {
+            interval = interval.clamp(min, max);
+        }
+        Self {
+            range,
+            interval,
+            start: Instant::now(),
+            finished: 0,
+            times: VecDeque::new(),
+        }
+    }
+
+    /// take the times of the jobs finished since the last call from `running`
+    /// and return the interval to sleep for next
+    pub(crate) fn next<P: Program>(
+        &mut self,
+        running: &mut Running<P>,
+    ) -> Duration {
+        let times = std::mem::take(&mut running.times);
+        self.update(&times, self.start.elapsed().as_secs_f64());
+        Duration::from_secs_f64(self.interval)
+    }
+
+    /// record the newly finished job `times` and choose the next interval,
+    /// `elapsed` seconds after the start of the drain
+    pub(crate) fn update(&mut self, times: &[f64], elapsed: f64) -> f64 {
+        self.finished += times.len();
+        for &t in times {
+            if self.times.len() == WINDOW {
+                self.times.pop_front();
+            }
+            self.times.push_back(t);
+        }
+        let Some((min, max)) = self.range else {
+            return self.interval;
+        };
+        let interval = if self.finished == 0 || elapsed <= 0.0 {
+            self.interval * BACKOFF
+        } else {
+            // the mean time between completions so far
+            let per_job = elapsed / self.finished as f64;
+            match self.median() {
+                Some(m) if m > 0.0 => per_job.min(FRACTION * m),
+                _ => per_job,
+            }
+        };
+        self.interval = interval.clamp(min, max);
+        self.interval
+    }
+
+    /// the median of the recent job times, if any
+    fn median(&self) -> Option<f64> {
+        if self.times.is_empty() {
+            return None;
+        }
+        let mut times: Vec<_> = self.times.iter().copied().collect();
+        let mid = times.len() / 2;
+        let (_, m, _) = times.select_nth_unstable_by(mid, f64::total_cmp);
+        Some(*m)
+    }
+}
@@ -0,0 +1,205 @@
+//! bookkeeping for the jobs that have been submitted to the queue in a drain
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    program::{Job, Program},
+    queue::{transport::Transport, Queue},
+};
+
+use super::{DrainEvent, DrainObserver};
+
+pub(crate) struct Running<P: Program> {
+    /// the jobs that have been submitted but not yet collected
+    pub(crate) cur_jobs: Vec<Job<P>>,
+
+    /// the number of uncollected jobs in each submit script
+    pub(crate) slurm_jobs: HashMap<String, usize>,
+
+    /// the ids of the queue jobs that were running when the queue status was
+    /// last checked, plus those submitted since then
+    pub(crate) qstat: HashSet<String>,
+
+    /// filenames of the jobs with errors in their output
+    pub(crate) failed_jobs: HashSet<String>,
+
+    /// the number of times left to check for each job's output before
+    /// resubmitting it
+    pub(crate) retries: HashMap<String, usize>,
+
+    /// ids of queue jobs with resubmitted jobs. these may still be running
+    /// if the job only briefly disappeared from the queue status
+    pub(crate) orphans: HashSet<String>,
+
+    /// total time for the jobs to run as returned from Program::read_output
+    pub(crate) job_time: f64,
+
+    /// the times of the jobs collected since they were last taken by
+    /// [super::poll::Poll::next]
+    pub(crate) times: Vec<f64>,
+
+    /// the number of jobs not yet collected, including those not submitted
+    pub(crate) remaining: usize,
+}
+
+impl<P> Running<P>
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+{
+    /// split `jobs` into those that still need to be submitted and those that
+    /// were submitted by a previous process, according to their `job_id`s, and
+    /// are either still in `qstat` or have already written their output.
+    /// outputs are looked for through `transport` if it is remote, since they
+    /// won't have been copied back yet. returns the former along with a
+    /// [Running] holding the latter
+    pub(crate) fn new(
+        jobs: Vec<Job<P>>,
+        qstat: HashSet<String>,
+        transport: &dyn Transport,
+    ) -> (Vec<Job<P>>, Self) {
+        let remote = transport.is_remote().then(|| {
+            let dirs: HashSet<_> = jobs
+                .iter()
+                .filter(|job| !job.job_id.is_empty())
+                .filter_map(|job| {
+                    let outfile = job.program.outfile();
+                    let dir = Path::new(&outfile).parent()?;
+                    Some(dir.display().to_string())
+                })
+                .collect();
+            transport.list(&dirs.into_iter().collect::<Vec<_>>())
+        });
+        let has_output = |outfile: String| match &remote {
+            Some(files) => files.contains(&outfile),
+            None => Path::new(&outfile).exists(),
+        };
+        let (cur_jobs, jobs): (Vec<_>, Vec<_>) =
+            jobs.into_iter().partition(|job| {
+                !job.job_id.is_empty()
+                    && (qstat.contains(&job.job_id)
+                        || has_output(job.program.outfile()))
+            });
+        if !cur_jobs.is_empty() {
+            log::info!("reattached to {} running jobs", cur_jobs.len());
+        }
+        let mut slurm_jobs = HashMap::new();
+        for job in &cur_jobs {
+            *slurm_jobs.entry(job.pbs_file.clone()).or_insert(0) += 1;
+        }
+        let ret = Self {
+            remaining: jobs.len() + cur_jobs.len(),
+            cur_jobs,
+            slurm_jobs,
+            qstat,
+            failed_jobs: HashSet::new(),
+            retries: HashMap::new(),
+            orphans: HashSet::new(),
+            job_time: 0.0,
+            times: Vec::new(),
+        };
+        (jobs, ret)
+    }
+
+    /// record that `jobs` were submitted together as `job_id`
+    pub(crate) fn add(
+        &mut self,
+        job_id: String,
+        jobs: Vec<Job<P>>,
+        slurm_jobs: HashMap<String, usize>,
+    ) {
+        self.slurm_jobs.extend(slurm_jobs);
+        self.qstat.insert(job_id);
+        self.cur_jobs.extend(jobs);
+    }
+
+    /// whether every job has either finished or failed
+    pub(crate) fn is_done(&self, out_of_jobs: bool) -> bool {
+        out_of_jobs
+            && self.cur_jobs.len().saturating_sub(self.failed_jobs.len()) == 0
+    }
+
+    /// store the seen times updated by [super::read_outputs] in the jobs they
+    /// were read for
+    pub(crate) fn set_seen(
+        &mut self,
+        outfiles: Vec<Option<(String, Option<SystemTime>)>>,
+    ) {
+        for (job, out) in self.cur_jobs.iter_mut().zip(outfiles) {
+            if let Some((_, seen)) = out {
+                job.seen = seen;
+            }
+        }
+    }
+
+    /// the indices of the uncollected jobs, including any duplicates merged
+    /// into them
+    pub(crate) fn indices(self) -> Vec<usize> {
+        self.cur_jobs
+            .into_iter()
+            .flat_map(|job| {
+                let dups = job.dups.into_iter().chain(job.symm_dups);
+                let dups = dups.map(|(i, _)| i);
+                std::iter::once(job.index).chain(dups)
+            })
+            .collect()
+    }
+
+    /// return the sorted, distinct queue ids of the uncollected jobs and clear
+    /// them, along with their submit scripts, so that a later resume submits
+    /// these jobs again instead of trying to reattach to them
+    pub(crate) fn take_job_ids(&mut self) -> Vec<String> {
+        let mut ids: Vec<_> = self
+            .cur_jobs
+            .iter_mut()
+            .map(|job| {
+                job.pbs_file.clear();
+                std::mem::take(&mut job.job_id)
+            })
+            .filter(|id| !id.is_empty())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// cancel the queue jobs in `orphans` that are still listed in `qstat`
+    /// even though none of `cur_jobs` belong to them anymore. this happens
+    /// when a job is resubmitted after its original disappears from the queue
+    /// status only temporarily, and would otherwise leave the original running
+    /// for nothing. the cancelled jobs are reported to `observer`
+    pub(crate) fn cancel_orphans<Q>(
+        &mut self,
+        queue: &Q,
+        observer: &dyn DrainObserver,
+    ) where
+        Q: Queue<P> + ?Sized,
+    {
+        if self.orphans.is_empty() {
+            return;
+        }
+        let live: HashSet<_> =
+            self.cur_jobs.iter().map(|job| &job.job_id).collect();
+        let mut job_ids: Vec<_> = self
+            .orphans
+            .iter()
+            .filter(|id| self.qstat.contains(*id) && !live.contains(id))
+            .cloned()
+            .collect();
+        if job_ids.is_empty() {
+            return;
+        }
+        job_ids.sort();
+        queue.cancel(&job_ids);
+        for id in &job_ids {
+            self.orphans.remove(id);
+        }
+        observer.on_event(&DrainEvent::OrphansCancelled { job_ids });
+    }
+}
@@ -0,0 +1,1024 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    program::{molpro::Molpro, Template},
+    queue::{
+        transport::{Direct, Mirror},
+        DrainOptions, Queue, SubQueue, Submit,
+    },
+};
+
+use super::*;
+use super::{poll::Poll, watch::Watch};
+
+fn job(index: usize, coeff: f64) -> Job<Molpro> {
+    let mut job = Job::new(
+        Molpro::new(
+            format!("job{index}"),
+            Template::from(""),
+            0,
+            Geom::from_str("H 0.0 0.0 0.0").unwrap(),
+        ),
+        index,
+    );
+    job.coeff = coeff;
+    job
+}
+
+#[test]
+fn dipole_set_result() {
+    let mut dst = [[0.0; 3]; 2];
+    let res = ProgramResult {
+        dipole: Some([1.0, 2.0, 3.0]),
+        ..Default::default()
+    };
+    Dipole.set_result(&mut dst, &mut job(1, 0.5), res.clone());
+    Dipole.set_result(&mut dst, &mut job(1, -2.0), res);
+    assert_eq!(dst, [[0.0; 3], [-1.5, -3.0, -4.5]]);
+}
+
+#[test]
+fn dipole_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let chk = dir.path().join("chk.json");
+    let chk = chk.to_str().unwrap();
+    let want = vec![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+    Dipole::write_checkpoint(chk, want.clone(), vec![job(1, 1.0)], false);
+
+    let mut got = vec![[0.0; 3]; 2];
+    let jobs: Vec<Job<Molpro>> = Dipole::load_checkpoint(chk, &mut got);
+    assert_eq!(got, want);
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].index, 1);
+}
+
+/// a job whose output is missing its dipole fails instead of panicking
+#[test]
+fn dipole_not_found() {
+    let jobs: Vec<_> = (0..2)
+        .map(|i| {
+            let mut job = job(i, 1.0);
+            job.job_id = "1".into();
+            job
+        })
+        .collect();
+    let (_, mut running) =
+        Running::new(jobs, HashSet::from(["1".into()]), &Direct);
+    let results = vec![
+        Some(Ok(ProgramResult::default())),
+        Some(Ok(ProgramResult {
+            dipole: Some([1.0, 2.0, 3.0]),
+            ..Default::default()
+        })),
+    ];
+    let mut dst = [[0.0; 3]; 2];
+    let dump = Dump::new(true, None);
+    let (finished, resubs) = Dipole.collect(
+        &NoSubmit::default(),
+        &mut running,
+        results,
+        &mut dst,
+        None,
+        &dump,
+        &mut Timer::default(),
+        &StderrObserver,
+    );
+    dump.shutdown();
+    assert_eq!(finished, 1);
+    assert!(resubs.is_empty());
+    assert_eq!(dst, [[0.0; 3], [1.0, 2.0, 3.0]]);
+    assert_eq!(running.cur_jobs.len(), 1);
+    assert!(running
+        .failed_jobs
+        .contains(&running.cur_jobs[0].program.filename()));
+}
+
+/// the energy selected by label replaces the primary one, and jobs without it
+/// fail
+#[test]
+fn energy_label() {
+    let jobs: Vec<_> = (0..2)
+        .map(|i| {
+            let mut job = job(i, 1.0);
+            job.job_id = "1".into();
+            job
+        })
+        .collect();
+    let (_, mut running) =
+        Running::new(jobs, HashSet::from(["1".into()]), &Direct);
+    let results = vec![
+        Some(Ok(ProgramResult {
+            energy: -1.0,
+            energies: [("HF", -1.0), ("MP2", -2.0)].into(),
+            ..Default::default()
+        })),
+        Some(Ok(ProgramResult {
+            energy: -1.0,
+            energies: [("HF", -1.0)].into(),
+            ..Default::default()
+        })),
+    ];
+    let queue = NoSubmit {
+        options: DrainOptions::new().with_energy_label("MP2"),
+        ..Default::default()
+    };
+    let mut dst = [0.0; 2];
+    let dump = Dump::new(true, None);
+    let (finished, resubs) = Single.collect(
+        &queue,
+        &mut running,
+        results,
+        &mut dst,
+        None,
+        &dump,
+        &mut Timer::default(),
+        &StderrObserver,
+    );
+    dump.shutdown();
+    assert_eq!(finished, 1);
+    assert!(resubs.is_empty());
+    assert_eq!(dst, [-2.0, 0.0]);
+    assert!(running
+        .failed_jobs
+        .contains(&running.cur_jobs[0].program.filename()));
+}
+
+#[test]
+fn opt_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let chk = dir.path().join("chk.json");
+    let chk = chk.to_str().unwrap();
+    let want = vec![
+        Geom::from_str("H 0.0 0.0 0.0").unwrap(),
+        Geom::Zmat("H".into()),
+    ];
+    Opt::write_checkpoint(chk, want.clone(), vec![job(0, 1.0)], true);
+
+    let mut got = vec![Geom::Zmat(String::new()); 2];
+    let jobs: Vec<Job<Molpro>> = Opt::load_checkpoint(chk, &mut got);
+    assert_eq!(got, want);
+    assert_eq!(jobs.len(), 1);
+}
+
+#[test]
+fn checkpoint_dedup() {
+    let dir = tempfile::tempdir().unwrap();
+    let chk = dir.path().join("chk.json");
+    let chk = chk.to_str().unwrap();
+    let jobs: Vec<_> = (0..3).map(|i| job(i, 1.0)).collect();
+    Single::write_checkpoint(chk, vec![0.0; 3], jobs.clone(), false);
+
+    // no temporary file left behind
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    let v: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(chk).unwrap()).unwrap();
+    assert_eq!(v["version"], checkpoint::VERSION);
+    assert_eq!(v["shared"].as_array().unwrap().len(), 1);
+    assert_eq!(v["jobs"][2]["program"]["template"], 0);
+
+    let mut dst = vec![1.0; 3];
+    let got: Vec<Job<Molpro>> = Single::load_checkpoint(chk, &mut dst);
+    assert_eq!(dst, vec![0.0; 3]);
+    assert_eq!(
+        serde_json::to_value(got).unwrap(),
+        serde_json::to_value(jobs).unwrap()
+    );
+}
+
+/// checkpoints written before the format was versioned are just `dst` and
+/// the full `jobs`
+#[test]
+fn checkpoint_v0() {
+    let dir = tempfile::tempdir().unwrap();
+    let chk = dir.path().join("chk.json");
+    let jobs = vec![job(0, 1.0), job(1, -1.0)];
+    let old = serde_json::json!({
+        "dst": [1.0, 2.0],
+        "jobs": jobs,
+    });
+    std::fs::write(&chk, serde_json::to_string_pretty(&old).unwrap()).unwrap();
+
+    let mut dst = vec![0.0; 2];
+    let got: Vec<Job<Molpro>> =
+        Single::load_checkpoint(chk.to_str().unwrap(), &mut dst);
+    assert_eq!(dst, vec![1.0, 2.0]);
+    assert_eq!(got.len(), 2);
+    assert_eq!(got[1].coeff, -1.0);
+}
+
+/// queue that reports a fixed set of running jobs and refuses to submit
+/// anything
+#[derive(Default)]
+struct NoSubmit {
+    running: HashSet<String>,
+    options: DrainOptions,
+    cancelled: Mutex<Vec<String>>,
+}
+
+impl Submit<Molpro> for NoSubmit {
+    fn submit(&self, filename: &str) -> String {
+        panic!("unexpected submission of {filename}");
+    }
+}
+
+impl Queue<Molpro> for NoSubmit {
+    fn template(&self) -> &Option<String> {
+        &None
+    }
+
+    fn program_cmd(&self, filename: &str) -> String {
+        format!("echo {filename}")
+    }
+
+    fn default_submit_script(&self) -> String {
+        String::new()
+    }
+}
+
+impl SubQueue<Molpro> for NoSubmit {
+    fn submit_command(&self) -> &str {
+        "false"
+    }
+
+    fn chunk_size(&self) -> usize {
+        1
+    }
+
+    fn job_limit(&self) -> usize {
+        2
+    }
+
+    fn sleep_int(&self) -> usize {
+        0
+    }
+
+    const SCRIPT_EXT: &'static str = "pbs";
+
+    fn dir(&self) -> &str {
+        "/tmp"
+    }
+
+    fn stat_cmd(&self) -> String {
+        String::new()
+    }
+
+    fn status(&self) -> HashSet<String> {
+        self.running.clone()
+    }
+
+    fn no_del(&self) -> bool {
+        true
+    }
+
+    fn cancel(&self, job_ids: &[String]) {
+        self.cancelled.lock().unwrap().extend_from_slice(job_ids);
+    }
+
+    fn options(&self) -> &DrainOptions {
+        &self.options
+    }
+}
+
+#[test]
+fn resume_reattaches() {
+    let mut running = job(0, 1.0);
+    running.program.set_filename("testfiles/molpro/opt");
+    running.job_id = "1".into();
+    running.pbs_file = "main1.pbs".into();
+    let mut finished = job(1, 2.0);
+    finished.program.set_filename("testfiles/molpro/dzccr");
+    finished.job_id = "2".into();
+    finished.pbs_file = "main2.pbs".into();
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let e = events.clone();
+    let queue = NoSubmit {
+        running: HashSet::from(["1".to_owned()]),
+        ..Default::default()
+    };
+    let observer =
+        move |event: &DrainEvent| e.lock().unwrap().push(event.clone());
+    let mut dst = [0.0; 2];
+    Single
+        .drain(
+            "/tmp",
+            &queue,
+            vec![running, finished],
+            &mut dst,
+            Check::None,
+            &observer,
+        )
+        .unwrap();
+    assert_eq!(dst, [-76.369839620286, 2.0 * -76.470698498340]);
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[0],
+        DrainEvent::JobFinished {
+            index: 0,
+            filename: "testfiles/molpro/opt".to_owned(),
+            job_id: "1".to_owned(),
+            pbs_file: "main1.pbs".to_owned(),
+            time: 27.13,
+        }
+    );
+    assert!(matches!(
+        events[1],
+        DrainEvent::JobFinished { index: 1, .. }
+    ));
+    assert!(matches!(events[2], DrainEvent::Finished { .. }));
+}
+
+#[test]
+fn cancel() {
+    let dir = tempfile::tempdir().unwrap();
+    let check_dir = dir.path().to_str().unwrap().to_owned();
+    let mut running = job(0, 1.0);
+    running.program.set_filename("testfiles/molpro/missing");
+    running.job_id = "1".into();
+    running.pbs_file = "main1.pbs".into();
+
+    let token = CancelToken::new().with_cancel_jobs();
+    let queue = NoSubmit {
+        running: HashSet::from(["1".to_owned()]),
+        options: DrainOptions::new().with_cancel_token(token.clone()),
+        ..Default::default()
+    };
+    token.cancel();
+    let mut dst = [0.0; 2];
+    let got = Single.drain(
+        "/tmp",
+        &queue,
+        vec![running, job(1, 1.0)],
+        &mut dst,
+        Check::Some {
+            check_int: 0,
+            check_dir: check_dir.clone(),
+        },
+        &StderrObserver,
+    );
+    let path = format!("{check_dir}/chk.json");
+    assert_eq!(
+        got,
+        Err(DrainError::Cancelled {
+            checkpoint: Some(path.clone())
+        })
+    );
+    assert_eq!(*queue.cancelled.lock().unwrap(), ["1"]);
+
+    // the cancelled job has to be submitted again, along with the one that
+    // was never submitted
+    let jobs: Vec<Job<Molpro>> = Single::load_checkpoint(&path, &mut dst);
+    assert_eq!(jobs.len(), 2);
+    assert!(jobs.iter().all(|job| job.job_id.is_empty()));
+}
+
+/// the legacy drains can only report failed jobs, so they point to the
+/// checkpoint instead of returning an empty list
+#[test]
+#[should_panic(expected = "drain cancelled, resume from")]
+fn cancel_legacy() {
+    let dir = tempfile::tempdir().unwrap();
+    let token = CancelToken::new();
+    let queue = NoSubmit {
+        options: DrainOptions::new().with_cancel_token(token.clone()),
+        ..Default::default()
+    };
+    token.cancel();
+    let mut dst = [0.0; 2];
+    let _ = queue.drain(
+        "/tmp",
+        vec![job(0, 1.0), job(1, 1.0)],
+        &mut dst,
+        Check::Some {
+            check_int: 0,
+            check_dir: dir.path().to_str().unwrap().to_owned(),
+        },
+    );
+}
+
+#[test]
+fn signals() {
+    // the only signal raised in this process, since a second one exits it
+    let first = CancelToken::new().with_signals();
+    unsafe { libc::raise(libc::SIGTERM) };
+    assert!(first.is_cancelled());
+    // a later drain isn't cancelled by the signal that stopped the first one
+    let second = CancelToken::new().with_signals();
+    assert!(!second.is_cancelled());
+    assert!(!CancelToken::new().is_cancelled());
+
+    // the default disposition comes back once every armed token is gone
+    let disposition = || unsafe {
+        let mut old: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(libc::SIGTERM, std::ptr::null(), &mut old);
+        old.sa_sigaction
+    };
+    let clone = second.clone();
+    drop((first, second));
+    assert_ne!(disposition(), libc::SIG_DFL);
+    drop(clone);
+    assert_eq!(disposition(), libc::SIG_DFL);
+}
+
+#[test]
+fn orphans() {
+    let mut still_used = job(0, 1.0);
+    still_used.job_id = "2".into();
+    let queue = NoSubmit::default();
+    let qstat = HashSet::from(["1", "2"].map(String::from));
+    let (_, mut running) = Running::new(vec![still_used], qstat, &Direct);
+    running.orphans = HashSet::from(["1", "2", "3"].map(String::from));
+    running.cancel_orphans(&queue, &StderrObserver);
+    assert_eq!(*queue.cancelled.lock().unwrap(), ["1"]);
+    // 3 may still show up again later
+    assert_eq!(running.orphans, HashSet::from(["2", "3"].map(String::from)));
+}
+
+/// a job whose output is only on the remote side of a transport is reattached
+/// instead of being submitted again
+#[test]
+fn reattach_remote() {
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir(root.path().join("reattach")).unwrap();
+    std::fs::write(root.path().join("reattach/job0.out"), "").unwrap();
+    let jobs: Vec<_> = (0..2)
+        .map(|i| {
+            let mut job = job(i, 1.0);
+            job.program.set_filename(&format!("reattach/job{i}"));
+            job.job_id = "7".into();
+            job
+        })
+        .collect();
+    let (jobs, running) =
+        Running::new(jobs, HashSet::new(), &Mirror::new(root.path()));
+    assert_eq!(running.cur_jobs.len(), 1);
+    assert_eq!(running.cur_jobs[0].index, 0);
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].index, 1);
+}
+
+#[test]
+fn watch() {
+    let dir = tempfile::tempdir().unwrap();
+    let jobs: Vec<_> = (0..2)
+        .map(|i| {
+            let mut job = job(i, 1.0);
+            job.program.set_filename(
+                dir.path().join(format!("job{i}")).to_str().unwrap(),
+            );
+            job.job_id = "1".into();
+            job
+        })
+        .collect();
+    let qstat = HashSet::from(["1".to_owned()]);
+    let (_, running) = Running::new(jobs, qstat, &Direct);
+    let mut time = Timer::default();
+
+    let mut watch = Watch::new(None);
+    assert_eq!(
+        watch
+            .outfiles(&running, 1, &mut time)
+            .iter()
+            .flatten()
+            .count(),
+        2
+    );
+
+    // new jobs are always read
+    let mut watch = Watch::new(Some(10));
+    assert_eq!(
+        watch
+            .outfiles(&running, 1, &mut time)
+            .iter()
+            .flatten()
+            .count(),
+        2
+    );
+    assert_eq!((time.reads, time.skipped_reads), (4, 0));
+
+    #[cfg(feature = "notify")]
+    {
+        std::fs::write(dir.path().join("job0.out"), "").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let got = watch.outfiles(&running, 2, &mut time);
+        assert!(got[0].is_some());
+        assert!(got[1].is_none());
+        assert_eq!((time.reads, time.skipped_reads), (5, 1));
+
+        // full scan
+        let got = watch.outfiles(&running, 10, &mut time);
+        assert!(got.iter().all(Option::is_some));
+    }
+}
+
+#[test]
+fn markers() {
+    let dir = tempfile::tempdir().unwrap();
+    let jobs: Vec<_> = (0..2)
+        .map(|i| {
+            let mut job = job(i, 1.0);
+            let f = dir.path().join(format!("job{i}"));
+            job.program.set_filename(f.to_str().unwrap());
+            job.job_id = "1".into();
+            job
+        })
+        .collect();
+    // failed, and finished without writing any output
+    std::fs::write(dir.path().join("job0.done"), "1 1700000000\n").unwrap();
+    std::fs::write(dir.path().join("job1.done"), "0 1700000000\n").unwrap();
+
+    // both are still in the queue, so neither would be noticed without markers
+    let queue = NoSubmit {
+        running: HashSet::from(["1".to_owned()]),
+        options: DrainOptions::new().with_markers(),
+        ..Default::default()
+    };
+    let (_, mut running) = Running::new(jobs.clone(), queue.status(), &Direct);
+    let results = running
+        .cur_jobs
+        .iter()
+        .map(|job| Some(Molpro::read_output(&job.program.filename())))
+        .collect();
+    let dump = Dump::new(true, None);
+    let (finished, resubs) = Single.collect(
+        &queue,
+        &mut running,
+        results,
+        &mut [0.0; 2],
+        None,
+        &dump,
+        &mut Timer::default(),
+        &StderrObserver,
+    );
+    dump.shutdown();
+    assert_eq!(finished, 0);
+    assert_eq!(resubs.len(), 1);
+    assert_eq!(resubs[0].index, 1);
+    assert_eq!(running.cur_jobs.len(), 1);
+    assert!(running
+        .failed_jobs
+        .contains(&running.cur_jobs[0].program.filename()));
+
+    // stale markers are ignored when markers aren't being written
+    let queue = NoSubmit {
+        running: HashSet::from(["1".to_owned()]),
+        ..Default::default()
+    };
+    let (_, mut running) = Running::new(jobs, queue.status(), &Direct);
+    let results = running
+        .cur_jobs
+        .iter()
+        .map(|job| Some(Molpro::read_output(&job.program.filename())))
+        .collect();
+    let dump = Dump::new(true, None);
+    let (finished, resubs) = Single.collect(
+        &queue,
+        &mut running,
+        results,
+        &mut [0.0; 2],
+        None,
+        &dump,
+        &mut Timer::default(),
+        &StderrObserver,
+    );
+    dump.shutdown();
+    assert_eq!((finished, resubs.len()), (0, 0));
+    assert_eq!(running.cur_jobs.len(), 2);
+    assert!(running.failed_jobs.is_empty());
+}
+
+/// drain through a [Mirror] transport, so that the jobs run in a separate
+/// directory standing in for the remote machine and only the markers and
+/// outputs are copied back
+#[test]
+fn transport() {
+    use crate::queue::local::Local;
+
+    let template = format!(
+        "molpro() {{ cp {}/testfiles/molpro/opt.out ${{1%.inp}}.out; }}
+MOLPRO_CMD=molpro
+",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    // the transport maps relative paths, so the local side has to be one
+    let local = tempfile::tempdir_in(".").unwrap();
+    let remote = tempfile::tempdir().unwrap();
+    let dir = local.path().file_name().unwrap().to_str().unwrap();
+    let queue = Local {
+        chunk_size: 2,
+        template: Some(template),
+        ..Default::default()
+    }
+    .with_options(
+        DrainOptions::new().with_transport(Mirror::new(remote.path())),
+    );
+    let jobs: Vec<_> = (0..3)
+        .map(|i| {
+            let mut job = geom_job(i, 1.0, &format!("H 0.0 0.0 {i}.0"));
+            job.program.set_filename(&format!("{dir}/job{i}"));
+            job
+        })
+        .collect();
+    let mut dst = vec![0.0; 3];
+    let time =
+        Single.drain(dir, &queue, jobs, &mut dst, Check::None, &StderrObserver);
+    assert_eq!(time, Ok(3.0 * 27.13));
+    assert_eq!(dst, vec![-76.369839620286; 3]);
+}
+
+/// files sent to the dump are deleted through a remote transport too
+#[test]
+fn remote_dump() {
+    use crate::queue::transport::Mirror;
+
+    let local = tempfile::tempdir_in(".").unwrap();
+    let root = tempfile::tempdir().unwrap();
+    let dir = local.path().file_name().unwrap().to_str().unwrap();
+    let file = format!("{dir}/job0.out");
+    let remote = root.path().join(&file);
+    std::fs::create_dir(root.path().join(dir)).unwrap();
+    std::fs::write(&file, "").unwrap();
+    std::fs::write(&remote, "").unwrap();
+
+    let dump = Dump::new(false, Some(Arc::new(Mirror::new(root.path()))));
+    dump.send(file.clone());
+    // the local file is removed after the remote one
+    let start = std::time::Instant::now();
+    while Path::new(&file).exists() && start.elapsed().as_secs() < 5 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    dump.shutdown();
+    assert!(!Path::new(&file).exists());
+    assert!(!remote.exists());
+}
+
+#[test]
+fn adaptive_poll() {
+    // fixed interval
+    let mut poll = Poll::new(10, None);
+    assert_eq!(poll.update(&[1.0], 1.0), 10.0);
+
+    // back off until something finishes
+    let mut poll = Poll::new(10, Some((2, 60)));
+    assert_eq!(poll.update(&[], 10.0), 15.0);
+    assert_eq!(poll.update(&[], 20.0), 22.5);
+
+    // fast jobs finishing quickly go to the minimum
+    assert_eq!(poll.update(&[0.1; 100], 30.0), 2.0);
+
+    // a quarter of the median time bounds the time between completions
+    let mut poll = Poll::new(10, Some((2, 3600)));
+    assert_eq!(poll.update(&[400.0, 500.0, 10_000.0], 1500.0), 125.0);
+
+    // long jobs finishing rarely go to the maximum
+    let mut poll = Poll::new(10, Some((2, 600)));
+    assert_eq!(poll.update(&[10_800.0], 10_800.0), 600.0);
+}
+
+#[test]
+fn cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::new(dir.path().to_str().unwrap());
+    let res = ProgramResult {
+        energy: -1.0,
+        ..Default::default()
+    };
+    assert_eq!(cache.get("Molpro", "input"), None);
+    cache.insert("Molpro", "input", &res);
+    assert_eq!(cache.get("Molpro", "input"), Some(res));
+    assert_eq!(cache.get("Mopac", "input"), None);
+    assert_eq!(cache.get("Molpro", "other input"), None);
+}
+
+/// changing only the MOPAC parameters leaves the input file itself the same,
+/// but it still has to miss the cache
+#[test]
+fn cache_params() {
+    use crate::program::mopac::{Mopac, Params};
+
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    let cache = Cache::new(&format!("{dir}/cache"));
+    let mopac = |uss: f64| {
+        let params = Params::from(
+            vec!["USS".to_owned()],
+            vec!["H".to_owned()],
+            vec![uss],
+        );
+        let mut mopac = Mopac::new_full(
+            format!("{dir}/job"),
+            Some(params),
+            Geom::from_str("H 0.0 0.0 0.0").unwrap(),
+            0,
+            Template::from("PM6"),
+        );
+        mopac.param_dir = Some(dir.to_owned());
+        mopac.write_input(Procedure::SinglePt);
+        let input = std::fs::read_to_string(mopac.infile()).unwrap();
+        (cache::inputs(&mopac).unwrap(), input)
+    };
+    let res = ProgramResult {
+        energy: -1.0,
+        ..Default::default()
+    };
+    let (key, input) = mopac(-11.0);
+    cache.insert("Mopac", &key, &res);
+    assert_eq!(cache.get("Mopac", &mopac(-11.0).0), Some(res));
+
+    let (key, new_input) = mopac(-12.0);
+    assert_eq!(new_input, input);
+    assert_eq!(cache.get("Mopac", &key), None);
+}
+
+#[test]
+fn drain_from_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache").to_str().unwrap().to_owned();
+    let mut job = job(0, -2.0);
+    job.program
+        .set_filename(dir.path().join("job").to_str().unwrap());
+
+    // populate the cache with the input this job will write
+    job.program.write_input(Procedure::SinglePt);
+    let input = std::fs::read_to_string(job.program.infile()).unwrap();
+    let res = ProgramResult {
+        energy: -1.5,
+        ..Default::default()
+    };
+    Cache::new(&cache_dir).insert(Molpro::KIND, &input, &res);
+
+    let queue = NoSubmit {
+        options: DrainOptions::new()
+            .with_cache_dir(&cache_dir)
+            .with_dedup_tol(0.0),
+        ..Default::default()
+    };
+    let mut dst = [0.0];
+    Single
+        .drain(
+            "/tmp",
+            &queue,
+            vec![job],
+            &mut dst,
+            Check::None,
+            &StderrObserver,
+        )
+        .unwrap();
+    assert_eq!(dst, [3.0]);
+}
+
+fn geom_job(index: usize, coeff: f64, geom: &str) -> Job<Molpro> {
+    let mut job = job(index, coeff);
+    job.program = Molpro::new(
+        format!("job{index}"),
+        Template::from("geometry={{.geom}}"),
+        0,
+        Geom::from_str(geom).unwrap(),
+    );
+    job
+}
+
+#[test]
+fn dedup_jobs() {
+    let jobs = vec![
+        geom_job(0, 1.0, "H 0.0 0.0 0.0"),
+        geom_job(1, -1.0, "H 0.0 0.0 0.1"),
+        geom_job(2, 2.0, "H 0.0 0.0 0.0"),
+        geom_job(3, 1.0, "H 0.0 0.0 0.1000001"),
+    ];
+
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    let proc = Procedure::SinglePt;
+    let (got, merged) = dedup(jobs.clone(), 0.0, dir, proc);
+    assert_eq!(merged, 1);
+    assert_eq!(got.len(), 3);
+    assert_eq!(got[0].dups, vec![(2, 2.0)]);
+    assert!(got[1].dups.is_empty());
+    // the rendered inputs are cleaned up
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+
+    let (got, merged) = dedup(jobs, 1e-4, dir, proc);
+    assert_eq!(merged, 2);
+    assert_eq!(got.len(), 2);
+    assert_eq!(got[1].dups, vec![(3, 1.0)]);
+}
+
+/// MOPAC jobs only refer to their parameters by file name, but jobs with
+/// different parameters still have to run separately
+#[test]
+fn dedup_params() {
+    use crate::program::mopac::{Mopac, Params};
+
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().to_str().unwrap();
+    let jobs: Vec<_> = [-11.0, -12.0, -11.0]
+        .into_iter()
+        .enumerate()
+        .map(|(i, uss)| {
+            let params = Params::from(
+                vec!["USS".to_owned()],
+                vec!["H".to_owned()],
+                vec![uss],
+            );
+            let mut mopac = Mopac::new_full(
+                format!("{dir}/job{i}"),
+                Some(params),
+                Geom::from_str("H 0.0 0.0 0.0").unwrap(),
+                0,
+                Template::from("PM6"),
+            );
+            mopac.param_dir = Some(dir.to_owned());
+            Job::new(mopac, i)
+        })
+        .collect();
+    let (got, merged) = dedup(jobs, 0.0, dir, Procedure::SinglePt);
+    assert_eq!(merged, 1);
+    assert_eq!(got.len(), 2);
+    assert_eq!(got[0].dups, vec![(2, 1.0)]);
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+}
+
+#[test]
+fn drain_fans_out_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache").to_str().unwrap().to_owned();
+    let mut jobs = vec![
+        geom_job(0, 1.0, "H 0.0 0.0 0.0"),
+        geom_job(1, -2.0, "H 0.0 0.0 0.0"),
+    ];
+    for job in jobs.iter_mut() {
+        let name = dir.path().join(job.program.filename());
+        job.program.set_filename(name.to_str().unwrap());
+    }
+
+    let mut rep = jobs[0].clone();
+    rep.program.write_input(Procedure::SinglePt);
+    let input = std::fs::read_to_string(rep.program.infile()).unwrap();
+    let res = ProgramResult {
+        energy: -1.5,
+        ..Default::default()
+    };
+    Cache::new(&cache_dir).insert(Molpro::KIND, &input, &res);
+
+    let queue = NoSubmit {
+        options: DrainOptions::new().with_cache_dir(&cache_dir),
+        ..Default::default()
+    };
+    let mut dst = [0.0; 2];
+    Single
+        .drain("/tmp", &queue, jobs, &mut dst, Check::None, &StderrObserver)
+        .unwrap();
+    assert_eq!(dst, [-1.5, 3.0]);
+}
+
+/// dipoles of geometries related by symmetry differ by the symmetry operation,
+/// so they can't be copied from one job to the other
+#[test]
+fn dipoles_reject_symmetry() {
+    let mut rep = job(0, 1.0);
+    rep.symm_dups.push((1, 1.0));
+    let mut dst = [[0.0; 3]; 2];
+    let got = Dipole.drain(
+        "/tmp",
+        &NoSubmit::default(),
+        vec![rep],
+        &mut dst,
+        Check::None,
+        &StderrObserver,
+    );
+    assert!(matches!(got, Err(DrainError::SymmetryMerged { .. })));
+}
+
+#[test]
+fn jsonl_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run.jsonl");
+    let log = JsonlLog::new(&path).unwrap();
+    log.on_event(&DrainEvent::Submitted {
+        pbs_file: "main0.pbs".to_owned(),
+        job_id: "12".to_owned(),
+        jobs: vec![(0, "job.0".to_owned()), (1, "job.1".to_owned())],
+    });
+    log.on_event(&DrainEvent::JobFinished {
+        index: 0,
+        filename: "job.0".to_owned(),
+        job_id: "12".to_owned(),
+        pbs_file: "main0.pbs".to_owned(),
+        time: 1.5,
+    });
+    log.on_event(&DrainEvent::Finished {
+        timer: Timer::default(),
+    });
+    log.on_event(&DrainEvent::JobFailed {
+        index: 1,
+        filename: "job.1".to_owned(),
+        job_id: "12".to_owned(),
+        pbs_file: "main0.pbs".to_owned(),
+        error: "ErrorInOutput(\"job.1.out\")".to_owned(),
+        kind: "ErrorInOutput",
+    });
+
+    let got: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(got.len(), 4);
+    assert_eq!(got[0]["event"], "submitted");
+    assert_eq!(got[1]["index"], 1);
+    assert_eq!(got[2]["event"], "finished");
+    assert_eq!(got[2]["submit_time"], got[0]["submit_time"]);
+    assert_eq!(got[2]["program_time"], 1.5);
+    assert!(got[2]["finish_time"].as_f64().is_some());
+    assert_eq!(got[3]["event"], "failed");
+    assert_eq!(got[3]["error"], "ErrorInOutput");
+    assert_eq!(got[3]["pbs_file"], "main0.pbs");
+    assert_eq!(got[3]["attempt"], 1);
+}
+
+#[test]
+fn jsonl_log_attempts() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run.jsonl");
+    let log = JsonlLog::new(&path).unwrap();
+    log.on_event(&DrainEvent::Submitted {
+        pbs_file: "main0.pbs".to_owned(),
+        job_id: "12".to_owned(),
+        jobs: vec![(0, "job.0".to_owned())],
+    });
+    for _ in 0..2 {
+        log.on_event(&DrainEvent::Resubmitting {
+            index: 0,
+            filename: "job.0".to_owned(),
+            job_id: "12".to_owned(),
+            pbs_file: "main0.pbs".to_owned(),
+            error: "FileNotFound(\"job.0.out\")".to_owned(),
+            kind: "FileNotFound",
+        });
+    }
+    log.on_event(&DrainEvent::JobFinished {
+        index: 0,
+        filename: "job.0_redo".to_owned(),
+        job_id: "14".to_owned(),
+        pbs_file: "job.0_redo.pbs".to_owned(),
+        time: 1.5,
+    });
+
+    let got: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let attempts: Vec<_> = got.iter().map(|r| r["attempt"].clone()).collect();
+    assert_eq!(attempts, [1, 2, 3, 3]);
+}
+
+/// run two drains concurrently on a single-threaded runtime, using a local
+/// queue whose submit scripts copy an existing Molpro output file into place
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn drain_async() {
+    use crate::queue::{local::Local, AsyncQueue};
+
+    let template = "molpro() { cp testfiles/molpro/opt.out ${1%.inp}.out; }
+MOLPRO_CMD=molpro
+"
+    .to_owned();
+    let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+    let [a, b] = dirs.each_ref().map(|d| d.path().to_str().unwrap());
+    let queue = Local {
+        chunk_size: 2,
+        template: Some(template),
+        ..Default::default()
+    };
+    let jobs = |dir: &str, n: usize| -> Vec<Job<Molpro>> {
+        (0..n)
+            .map(|i| {
+                let geom = format!("H 0.0 0.0 {i}.0");
+                let program = Molpro::new(
+                    format!("{dir}/job{i}"),
+                    Template::from(""),
+                    0,
+                    Geom::from_str(&geom).unwrap(),
+                );
+                Job::new(program, i)
+            })
+            .collect()
+    };
+    let (mut da, mut db) = (vec![0.0; 3], vec![0.0; 4]);
+    let (ra, rb) = tokio::join!(
+        queue.drain_async(a, jobs(a, 3), &mut da, Check::None, &StderrObserver),
+        queue.drain_async(b, jobs(b, 4), &mut db, Check::None, &StderrObserver),
+    );
+    assert_eq!(ra, Ok(3.0 * 27.13));
+    assert_eq!(rb, Ok(4.0 * 27.13));
+    assert_eq!(da, vec![-76.369839620286; 3]);
+    assert_eq!(db, vec![-76.369839620286; 4]);
+}
@@ -1,14 +1,24 @@
 use std::fmt::Display;
 use std::time::Duration;
 
-#[derive(Default)]
-pub(crate) struct Timer {
-    pub(crate) writing_input: Duration,
-    pub(crate) writing_script: Duration,
-    pub(crate) submitting_script: Duration,
-    pub(crate) reading: Duration,
-    pub(crate) sleeping: Duration,
-    pub(crate) removing: Duration,
+use serde::Serialize;
+
+/// the total time spent in each part of a drain
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Timer {
+    pub writing_input: Duration,
+    pub writing_script: Duration,
+    pub submitting_script: Duration,
+    pub reading: Duration,
+    pub sleeping: Duration,
+    pub removing: Duration,
+
+    /// the number of output reads
+    pub reads: usize,
+
+    /// the number of output reads skipped because the files had not changed.
+    /// see [crate::queue::DrainOptions::watch_outputs]
+    pub skipped_reads: usize,
 }
 
 impl Display for Timer {
@@ -16,13 +26,16 @@ impl Display for Timer {
         write!(
             f,
             "{:.1} s reading ok, {:.1} s writing input, {:.1} s writing script, \
-	     {:.1} s submitting, {:.1} s sleeping, {:.1} s removing",
+	     {:.1} s submitting, {:.1} s sleeping, {:.1} s removing, \
+	     skipped {} of {} output reads",
             self.reading.as_millis() as f64 / 1000.0,
             self.writing_input.as_millis() as f64 / 1000.0,
             self.writing_script.as_millis() as f64 / 1000.0,
             self.submitting_script.as_millis() as f64 / 1000.0,
             self.sleeping.as_millis() as f64 / 1000.0,
             self.removing.as_millis() as f64 / 1000.0,
+            self.skipped_reads,
+            self.reads + self.skipped_reads,
         )
     }
 }
@@ -0,0 +1,251 @@
+//! selection of the output files to read in each iteration of a drain. by
+//! default every outstanding job is read every time, but with
+//! [DrainOptions::watch_outputs] set and the `notify` feature enabled, only the
+//! jobs whose files have changed according to filesystem notifications are
+//! read. notifications are not reliable on network filesystems like Lustre,
+//! where writes from other nodes may never be reported, so a job is also read
+//! if its queue job is no longer running, and every job is read on a regular
+//! full scan
+//!
+//! [DrainOptions::watch_outputs]: crate::queue::DrainOptions::watch_outputs
+
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::program::Program;
+
+use super::{running::Running, timer::Timer};
+
+pub(crate) struct Watch {
+    /// read every job every `full_scan` iterations. `None` disables watching
+    full_scan: Option<usize>,
+
+    #[cfg(feature = "notify")]
+    inner: Option<inner::Inner>,
+}
+
+impl Watch {
+    pub(crate) fn new(full_scan: Option<usize>) -> Self {
+        #[cfg(not(feature = "notify"))]
+        if full_scan.is_some() {
+            log::warn!(
+                "psqs was built without the notify feature, reading all \
+                 outputs in every iteration"
+            );
+        }
+        Self {
+            #[cfg(feature = "notify")]
+            inner: full_scan.and_then(|_| inner::Inner::new()),
+            full_scan,
+        }
+    }
+
+    /// the output filename and last seen time of each job in
+    /// `running.cur_jobs`, or `None` if it doesn't need to be read on
+    /// iteration `iter`. the seen time is dropped for jobs no longer in the
+    /// queue, so that their output is read at least once more
+    pub(crate) fn outfiles<P>(
+        &mut self,
+        running: &Running<P>,
+        iter: usize,
+        time: &mut Timer,
+    ) -> Vec<Option<(String, Option<SystemTime>)>>
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    {
+        let read = self.select(running, iter, time);
+        running
+            .cur_jobs
+            .iter()
+            .zip(read)
+            .map(|(job, read)| {
+                let in_queue = running.qstat.contains(&job.job_id);
+                let seen = job.seen.filter(|_| in_queue);
+                read.then(|| (job.program.filename(), seen))
+            })
+            .collect()
+    }
+
+    /// whether each job in `running.cur_jobs` should be read on iteration
+    /// `iter`. the numbers of reads and skipped reads are added to `time`
+    fn select<P>(
+        &mut self,
+        running: &Running<P>,
+        iter: usize,
+        time: &mut Timer,
+    ) -> Vec<bool>
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    {
+        let changed = self.changed(running);
+        let ret = match self.full_scan {
+            Some(n) if iter % n != 0 => changed,
+            _ => None,
+        }
+        .unwrap_or_else(|| vec![true; running.cur_jobs.len()]);
+        let reads = ret.iter().filter(|&&b| b).count();
+        time.reads += reads;
+        time.skipped_reads += ret.len() - reads;
+        ret
+    }
+
+    /// whether each job in `running.cur_jobs` has changed since the last call,
+    /// has not been seen before, or is no longer in the queue. `None` if the
+    /// changes are unknown
+    #[cfg(feature = "notify")]
+    fn changed<P>(&mut self, running: &Running<P>) -> Option<Vec<bool>>
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    {
+        let inner = self.inner.as_mut()?;
+        let changed = inner.take_changed();
+        if !inner.is_ok() {
+            log::warn!("file watching failed, reading all outputs");
+            self.inner = None;
+            return None;
+        }
+        let ret = running
+            .cur_jobs
+            .iter()
+            .map(|job| {
+                let Some(key) = inner.key(&job.program.filename()) else {
+                    // a new job, or one that can't be watched
+                    return true;
+                };
+                changed.contains(&key) || !running.qstat.contains(&job.job_id)
+            })
+            .collect();
+        Some(ret)
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn changed<P>(&mut self, _running: &Running<P>) -> Option<Vec<bool>>
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    {
+        None
+    }
+}
+
+#[cfg(feature = "notify")]
+mod inner {
+    use std::{
+        collections::{HashMap, HashSet},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use notify::{RecursiveMode, Watcher};
+
+    pub(super) struct Inner {
+        watcher: notify::RecommendedWatcher,
+
+        /// directories already being watched
+        dirs: HashSet<PathBuf>,
+
+        /// the canonical path of each watched job, by filename. this stands
+        /// for the files named after the job and, for programs that write into
+        /// a directory per job, the files in that directory
+        keys: HashMap<String, PathBuf>,
+
+        /// job keys with changes since the last call to `take_changed`
+        changed: Arc<Mutex<HashSet<PathBuf>>>,
+
+        /// set if the watcher reported an error, such as an overflowed event
+        /// queue, after which events may have been lost
+        failed: Arc<AtomicBool>,
+    }
+
+    impl Inner {
+        pub(super) fn new() -> Option<Self> {
+            let changed = Arc::new(Mutex::new(HashSet::new()));
+            let failed = Arc::new(AtomicBool::new(false));
+            let (c, f) = (changed.clone(), failed.clone());
+            let watcher = notify::recommended_watcher(
+                move |res: notify::Result<notify::Event>| match res {
+                    Ok(event) => {
+                        let mut c = c.lock().unwrap();
+                        for path in event.paths {
+                            // the key is either the file without its extension
+                            // or the job directory containing it
+                            if let Some(parent) = path.parent() {
+                                c.insert(parent.to_owned());
+                            }
+                            c.insert(path.with_extension(""));
+                        }
+                    }
+                    Err(_) => f.store(true, Ordering::SeqCst),
+                },
+            );
+            match watcher {
+                Ok(watcher) => Some(Self {
+                    watcher,
+                    dirs: HashSet::new(),
+                    keys: HashMap::new(),
+                    changed,
+                    failed,
+                }),
+                Err(e) => {
+                    log::warn!("failed to start file watcher with {e}");
+                    None
+                }
+            }
+        }
+
+        /// return the key of `filename` if it is already being watched.
+        /// otherwise, start watching it and return `None`
+        pub(super) fn key(&mut self, filename: &str) -> Option<PathBuf> {
+            if let Some(key) = self.keys.get(filename) {
+                return Some(key.clone());
+            }
+            let key = key(filename)?;
+            self.watch(&key, filename);
+            self.keys.insert(filename.to_owned(), key);
+            None
+        }
+
+        /// watch the directory containing `key`, as well as `filename` itself
+        /// if it is a directory
+        fn watch(&mut self, key: &Path, filename: &str) {
+            let dirs =
+                key.parent().map(Path::to_owned).into_iter().chain(
+                    Path::new(filename).is_dir().then(|| key.to_owned()),
+                );
+            for dir in dirs {
+                if self.dirs.contains(&dir) {
+                    continue;
+                }
+                if let Err(e) =
+                    self.watcher.watch(&dir, RecursiveMode::NonRecursive)
+                {
+                    log::warn!("failed to watch {} with {e}", dir.display());
+                    self.failed.store(true, Ordering::SeqCst);
+                }
+                self.dirs.insert(dir);
+            }
+        }
+
+        pub(super) fn take_changed(&self) -> HashSet<PathBuf> {
+            std::mem::take(&mut *self.changed.lock().unwrap())
+        }
+
+        pub(super) fn is_ok(&self) -> bool {
+            !self.failed.load(Ordering::SeqCst)
+        }
+    }
+
+    /// the canonical form of `filename`, which may not exist yet. `None` if
+    /// its directory does not exist either
+    fn key(filename: &str) -> Option<PathBuf> {
+        let path = Path::new(filename);
+        let dir = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        Some(dir.canonicalize().ok()?.join(path.file_name()?))
+    }
+}
@@ -6,7 +6,7 @@ use crate::program::molpro::Molpro;
 use crate::program::{mopac::Mopac, Program};
 use crate::queue::Queue;
 
-use super::{SubQueue, Submit};
+use super::{DrainOptions, SubQueue, Submit};
 
 /// Minimal implementation for testing MOPAC locally
 #[derive(Debug)]
@@ -14,6 +14,7 @@ pub struct Local {
     pub dir: String,
     pub chunk_size: usize,
     pub template: Option<String>,
+    pub options: DrainOptions,
 }
 
 impl Default for Local {
@@ -22,6 +23,7 @@ impl Default for Local {
             dir: ".".to_string(),
             chunk_size: 128,
             template: None,
+            options: DrainOptions::default(),
         }
     }
 }
@@ -39,8 +41,16 @@ impl Local {
             dir: dir.to_string(),
             chunk_size,
             template,
+            options: DrainOptions::default(),
         }
     }
+
+    /// drain with `options` instead of the defaults. see
+    /// [SubQueue::options]
+    pub fn with_options(mut self, options: DrainOptions) -> Self {
+        self.options = options;
+        self
+    }
 }
 
 impl Submit<Molpro> for Local {}
@@ -122,27 +132,45 @@ impl<P: Program + Clone + Serialize + for<'a> Deserialize<'a>> SubQueue<P>
         todo!()
     }
 
+    /// [Submit::submit] runs each script to completion, so there are never
+    /// any jobs left in the queue. jobs whose output is missing are therefore
+    /// resubmitted, including jobs reattached from a checkpoint
     fn status(&self) -> HashSet<String> {
-        for dir in ["opt", "pts", "freqs"] {
-            let Ok(d) = std::fs::read_dir(dir) else {
-                log::error!("{dir} not found for status");
-                continue;
-            };
-            for f in d {
-                eprintln!("contents of {:?}", f.as_ref().unwrap());
-                eprintln!(
-                    "{}",
-                    std::fs::read_to_string(f.unwrap().path()).unwrap()
-                );
-                eprintln!("================");
-            }
-        }
-        panic!("no status available for Local queue");
+        HashSet::new()
     }
 
     fn no_del(&self) -> bool {
         false
     }
+
+    /// jobs are run to completion by [Submit::submit] itself, so there is
+    /// never anything left to cancel. this includes jobs run in parallel
+    /// with [DrainOptions::with_parallel], since the script waits for each
+    /// batch before it exits and kills the batch if it is interrupted
+    fn cancel(&self, _job_ids: &[String]) {}
+
+    fn options(&self) -> &DrainOptions {
+        &self.options
+    }
+}
+
+#[cfg(feature = "async")]
+impl<P> super::AsyncQueue<P> for Local
+where
+    Local: Queue<P>,
+    P: Program
+        + Clone
+        + Send
+        + Sync
+        + Serialize
+        + for<'a> Deserialize<'a>
+        + 'static,
+{
+    /// as with [SubQueue::status], local jobs have already finished by the
+    /// time they are submitted, so this is always empty
+    async fn status_async(&self) -> HashSet<String> {
+        <Self as SubQueue<P>>::status(self)
+    }
 }
 
 #[cfg(test)]
@@ -158,6 +186,7 @@ mod tests {
             dir: String::new(),
             chunk_size: 0,
             template: None,
+            options: DrainOptions::default(),
         }
     }
 
@@ -182,6 +211,11 @@ mod tests {
         }
     }
 
+    #[test]
+    fn status() {
+        assert!(<Local as SubQueue<Mopac>>::status(&local()).is_empty());
+    }
+
     make_tests! {
         mopac_local, &local() =>  Mopac,
         molpro_local, &local() =>  Molpro,
@@ -0,0 +1,42 @@
+//! completion markers written by submit scripts. after running each job, the
+//! script records the exit status of the program and the time it finished in
+//! a `.done` file next to the job, which tells the drain for certain that the
+//! program is no longer running. jobs run in parallel also get their own log
+//! of standard output and error next to the marker
+
+/// the marker file for the job `filename`
+pub(crate) fn done_file(filename: &str) -> String {
+    format!("{filename}.done")
+}
+
+/// the log of the job `filename` when it runs in parallel with others. this
+/// can't be `.log`, which Molpro already uses
+pub(crate) fn log_file(filename: &str) -> String {
+    format!("{filename}.stdout")
+}
+
+/// the shell command that writes the marker for `filename`. this must directly
+/// follow the program command so that `$?` is its exit status
+pub(crate) fn write_cmd(filename: &str) -> String {
+    format!("echo \"$? $(date +%s)\" > {}", done_file(filename))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Done {
+    /// the exit status of the program
+    pub(crate) status: i32,
+
+    /// when the program finished, in seconds since the Unix epoch
+    pub(crate) time: u64,
+}
+
+impl Done {
+    /// read the marker for `filename`, if it has been written
+    pub(crate) fn read(filename: &str) -> Option<Self> {
+        let s = std::fs::read_to_string(done_file(filename)).ok()?;
+        let mut fields = s.split_whitespace();
+        let status = fields.next()?.parse().ok()?;
+        let time = fields.next()?.parse().ok()?;
+        Some(Self { status, time })
+    }
+}
@@ -0,0 +1,362 @@
+//! a scripted stand-in for a cluster, for testing drains end to end without
+//! one. [Mock] runs [Fake] jobs by writing their outputs itself, with energies
+//! computed from their geometries, and a [Scenario] decides when each job
+//! finishes and what goes wrong along the way. time only passes when the drain
+//! checks [SubQueue::status], so a scenario plays out the same way every time.
+//! requires the `mock` feature
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{geom::Geom, program::fake::Fake};
+
+use super::{marker, DrainOptions, Queue, SubQueue, Submit};
+
+/// what happens to one attempt at running a job
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// the job finishes normally
+    Finish,
+
+    /// the job finishes normally, but only after this many extra polls
+    Delay(usize),
+
+    /// the job finishes and writes its marker, but its output goes missing
+    LoseOutput,
+
+    /// the job reports an error in its output and exits with status 1
+    Fail,
+
+    /// the whole scheduler job containing this job disappears from the queue
+    /// before anything in it writes output, as if its node died
+    Die,
+}
+
+/// the script followed by a [Mock] queue
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    /// the number of polls a scheduler job stays in the queue
+    delay: usize,
+
+    /// the outcomes of successive attempts at each job, by name
+    jobs: HashMap<String, Vec<Outcome>>,
+
+    /// the poll on which to cancel the queue's [DrainOptions::cancel_token]
+    interrupt: Option<usize>,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            delay: 1,
+            jobs: HashMap::new(),
+            interrupt: None,
+        }
+    }
+}
+
+impl Scenario {
+    /// a scenario where every job finishes normally on the first poll after
+    /// it was submitted
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// keep each scheduler job in the queue for `polls` polls
+    pub fn with_delay(mut self, polls: usize) -> Self {
+        self.delay = polls;
+        self
+    }
+
+    /// script the outcomes of the successive attempts at the job `name`, which
+    /// is its file name without the directory or any `_redo` suffixes added by
+    /// resubmission. attempts beyond the end of `outcomes` finish normally
+    pub fn job(
+        mut self,
+        name: &str,
+        outcomes: impl IntoIterator<Item = Outcome>,
+    ) -> Self {
+        self.jobs
+            .insert(name.to_owned(), outcomes.into_iter().collect());
+        self
+    }
+
+    /// cancel the [DrainOptions::cancel_token] of the queue on poll number
+    /// `poll`, as if the drain had been interrupted there
+    pub fn interrupt_at(mut self, poll: usize) -> Self {
+        self.interrupt = Some(poll);
+        self
+    }
+
+    fn outcome(&self, name: &str, attempt: usize) -> Outcome {
+        self.jobs
+            .get(name)
+            .and_then(|v| v.get(attempt))
+            .copied()
+            .unwrap_or(Outcome::Finish)
+    }
+}
+
+/// a scheduler job submitted to a [Mock] queue
+struct Queued {
+    id: String,
+
+    /// the filenames of the jobs in the submit script with their outcomes
+    jobs: Vec<(String, Outcome)>,
+
+    /// the poll on which the job leaves the queue
+    done_at: usize,
+}
+
+/// the state of the simulated cluster, which is shared by clones of a [Mock]
+#[derive(Default)]
+struct Cluster {
+    polls: usize,
+    next_id: usize,
+    queued: Vec<Queued>,
+
+    /// the number of attempts made so far at each job, by name
+    attempts: HashMap<String, usize>,
+
+    submitted: Vec<String>,
+    cancelled: Vec<String>,
+}
+
+/// a [Queue] for [Fake] jobs that follows a [Scenario] instead of running
+/// anything. clones share the same simulated cluster, so a clone with
+/// different [DrainOptions] can resume a drain interrupted by
+/// [Scenario::interrupt_at] and reattach to the jobs it left running
+#[derive(Clone)]
+pub struct Mock {
+    dir: String,
+    chunk_size: usize,
+    job_limit: usize,
+    template: Option<String>,
+    scenario: Arc<Scenario>,
+    energy: Arc<dyn Fn(&Geom) -> f64 + Send + Sync>,
+    cluster: Arc<Mutex<Cluster>>,
+    options: DrainOptions,
+}
+
+impl Mock {
+    /// a queue following `scenario` in which each job finishes with the
+    /// energy returned by `energy` for its geometry
+    pub fn new(
+        dir: &str,
+        scenario: Scenario,
+        energy: impl Fn(&Geom) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            chunk_size: 1,
+            job_limit: 1600,
+            template: None,
+            scenario: Arc::new(scenario),
+            energy: Arc::new(energy),
+            cluster: Arc::default(),
+            options: DrainOptions::default(),
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn with_job_limit(mut self, job_limit: usize) -> Self {
+        self.job_limit = job_limit;
+        self
+    }
+
+    /// drain with `options` instead of the defaults. the
+    /// [DrainOptions::cancel_token] is the one cancelled by
+    /// [Scenario::interrupt_at]
+    pub fn with_options(mut self, options: DrainOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// the submit scripts submitted so far, in order
+    pub fn submitted(&self) -> Vec<String> {
+        self.cluster.lock().unwrap().submitted.clone()
+    }
+
+    /// the ids of the jobs cancelled so far, in order
+    pub fn cancelled(&self) -> Vec<String> {
+        self.cluster.lock().unwrap().cancelled.clone()
+    }
+
+    /// the number of times the queue has been polled
+    pub fn polls(&self) -> usize {
+        self.cluster.lock().unwrap().polls
+    }
+
+    /// write the outputs and markers of the jobs in `queued`, which just left
+    /// the queue
+    fn finish(&self, queued: Queued) {
+        if queued.jobs.iter().any(|(_, o)| *o == Outcome::Die) {
+            return;
+        }
+        for (filename, outcome) in queued.jobs {
+            let outfile = format!("{filename}.out");
+            let status = match outcome {
+                Outcome::Finish | Outcome::Delay(_) => {
+                    let infile = format!("{filename}.inp");
+                    let input = std::fs::read_to_string(&infile)
+                        .unwrap_or_else(|e| {
+                            panic!("failed to read {infile} with {e}")
+                        });
+                    let geom = Geom::from_str(&input).unwrap();
+                    let energy = (self.energy)(&geom);
+                    write(&outfile, &Fake::output(energy, 1.0, &geom));
+                    0
+                }
+                Outcome::LoseOutput => 0,
+                Outcome::Fail => {
+                    write(&outfile, &Fake::error_output("scripted failure"));
+                    1
+                }
+                Outcome::Die => unreachable!(),
+            };
+            let time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            write(&marker::done_file(&filename), &format!("{status} {time}\n"));
+        }
+    }
+}
+
+fn write(path: &str, contents: &str) {
+    if let Err(e) = std::fs::write(path, contents) {
+        panic!("failed to write {path} with {e}");
+    }
+}
+
+/// the name of the job `filename` used by [Scenario::job]
+fn job_name(filename: &str) -> &str {
+    let mut name = Path::new(filename)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(filename);
+    while let Some(n) = name.strip_suffix("_redo") {
+        name = n;
+    }
+    name
+}
+
+impl Submit<Fake> for Mock {
+    /// queue the jobs found in the submit script `filename`
+    fn submit(&self, filename: &str) -> String {
+        let script = std::fs::read_to_string(filename).unwrap_or_else(|e| {
+            panic!("failed to read submit script {filename} with {e}")
+        });
+        let mut cluster = self.cluster.lock().unwrap();
+        let mut jobs = Vec::new();
+        let mut extra = 0;
+        for f in script.lines().filter_map(|l| l.strip_prefix("fake ")) {
+            let name = job_name(f);
+            let attempt = cluster.attempts.entry(name.to_owned()).or_default();
+            let outcome = self.scenario.outcome(name, *attempt);
+            *attempt += 1;
+            if let Outcome::Delay(n) = outcome {
+                extra = extra.max(n);
+            }
+            jobs.push((f.to_owned(), outcome));
+        }
+        cluster.next_id += 1;
+        let id = cluster.next_id.to_string();
+        let done_at = cluster.polls + self.scenario.delay + extra;
+        cluster.queued.push(Queued {
+            id: id.clone(),
+            jobs,
+            done_at,
+        });
+        cluster.submitted.push(filename.to_owned());
+        id
+    }
+}
+
+impl Queue<Fake> for Mock {
+    fn template(&self) -> &Option<String> {
+        &self.template
+    }
+
+    fn program_cmd(&self, filename: &str) -> String {
+        format!("fake {filename}")
+    }
+
+    fn default_submit_script(&self) -> String {
+        String::new()
+    }
+}
+
+impl SubQueue<Fake> for Mock {
+    const SCRIPT_EXT: &'static str = "mock";
+
+    fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    fn submit_command(&self) -> &str {
+        "mock"
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn job_limit(&self) -> usize {
+        self.job_limit
+    }
+
+    fn sleep_int(&self) -> usize {
+        0
+    }
+
+    fn stat_cmd(&self) -> String {
+        String::new()
+    }
+
+    /// advance the simulated cluster by one poll, finishing any jobs that are
+    /// due, and return the ids of those still in the queue
+    fn status(&self) -> HashSet<String> {
+        let mut cluster = self.cluster.lock().unwrap();
+        cluster.polls += 1;
+        if self.scenario.interrupt == Some(cluster.polls) {
+            if let Some(token) = self.options.cancel_token() {
+                token.cancel();
+            }
+        }
+        let polls = cluster.polls;
+        let (done, queued) = std::mem::take(&mut cluster.queued)
+            .into_iter()
+            .partition(|q| q.done_at <= polls);
+        cluster.queued = queued;
+        let ret = cluster.queued.iter().map(|q| q.id.clone()).collect();
+        drop(cluster);
+        for q in done {
+            self.finish(q);
+        }
+        ret
+    }
+
+    fn no_del(&self) -> bool {
+        false
+    }
+
+    fn cancel(&self, job_ids: &[String]) {
+        let mut cluster = self.cluster.lock().unwrap();
+        cluster.queued.retain(|q| !job_ids.contains(&q.id));
+        cluster.cancelled.extend_from_slice(job_ids);
+    }
+
+    fn options(&self) -> &DrainOptions {
+        &self.options
+    }
+}
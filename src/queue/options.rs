@@ -0,0 +1,248 @@
+//! the optional drain behavior shared by every kind of [Queue]
+//!
+//! [Queue]: super::Queue
+
+use std::sync::{Arc, LazyLock};
+
+#[cfg(feature = "replay")]
+use super::replay::Recorder;
+use super::{transport::Direct, CancelToken, Transport};
+
+/// the options returned by the default [SubQueue::options], for queues that
+/// don't hold their own
+///
+/// [SubQueue::options]: super::SubQueue::options
+pub(crate) static DEFAULT: LazyLock<DrainOptions> =
+    LazyLock::new(DrainOptions::default);
+
+/// the options for draining a queue, returned by [SubQueue::options]. every
+/// option is off by default
+///
+/// [SubQueue::options]: super::SubQueue::options
+#[derive(Clone, Debug)]
+pub struct DrainOptions {
+    cache_dir: Option<String>,
+    dedup_tol: Option<f64>,
+    cancel_token: Option<CancelToken>,
+    watch: Option<usize>,
+    poll: Option<(usize, usize)>,
+    parallel: usize,
+    cpus: usize,
+    transport: Option<Arc<dyn Transport>>,
+    #[cfg(feature = "replay")]
+    recorder: Option<Arc<Recorder>>,
+    markers: bool,
+    compress: bool,
+    energy_label: Option<String>,
+}
+
+impl Default for DrainOptions {
+    fn default() -> Self {
+        Self {
+            cache_dir: None,
+            dedup_tol: None,
+            cancel_token: None,
+            watch: None,
+            poll: None,
+            parallel: 1,
+            cpus: 1,
+            transport: None,
+            #[cfg(feature = "replay")]
+            recorder: None,
+            markers: false,
+            compress: false,
+            energy_label: None,
+        }
+    }
+}
+
+impl DrainOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// use `dir` as a persistent cache of results. see
+    /// [DrainOptions::cache_dir]
+    pub fn with_cache_dir(mut self, dir: &str) -> Self {
+        self.cache_dir = Some(dir.to_owned());
+        self
+    }
+
+    /// run identical jobs only once, treating jobs with coordinates within
+    /// `tol` of each other as identical. see [DrainOptions::dedup_tol]
+    pub fn with_dedup_tol(mut self, tol: f64) -> Self {
+        self.dedup_tol = Some(tol);
+        self
+    }
+
+    /// stop draining when `token` is cancelled. see [CancelToken]
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// only read the outputs that changed, reading all of them every
+    /// `full_scan` iterations. see [DrainOptions::watch_outputs]
+    pub fn with_watch(mut self, full_scan: usize) -> Self {
+        self.watch = Some(full_scan.max(1));
+        self
+    }
+
+    /// adapt the sleep between checks of the queue to the jobs, keeping it
+    /// between `min` and `max` seconds. see [DrainOptions::poll_range]
+    pub fn with_adaptive_poll(mut self, min: usize, max: usize) -> Self {
+        self.poll = Some((min, max));
+        self
+    }
+
+    /// run the jobs in each chunk in parallel, as many at a time as fit in
+    /// `cpus` with `cpus_per_job` each, and request enough cpus for all of
+    /// them. see [DrainOptions::parallel_jobs]
+    pub fn with_parallel(mut self, cpus: usize, cpus_per_job: usize) -> Self {
+        let cpus_per_job = cpus_per_job.max(1);
+        self.parallel = (cpus / cpus_per_job).max(1);
+        self.cpus = self.parallel * cpus_per_job;
+        self
+    }
+
+    /// run the scheduler commands and copy files through `transport`, for
+    /// example [super::transport::Ssh]. see [DrainOptions::transport]
+    pub fn with_transport(
+        mut self,
+        transport: impl Transport + 'static,
+    ) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// record each drain to `recorder` for replaying it later. see
+    /// [DrainOptions::recorder]
+    #[cfg(feature = "replay")]
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// write a completion marker after each job in the submit scripts. see
+    /// [DrainOptions::write_markers]
+    pub fn with_markers(mut self) -> Self {
+        self.markers = true;
+        self
+    }
+
+    /// gzip the checkpoint files. see [DrainOptions::compress_checkpoints]
+    pub fn with_compressed_checkpoints(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    /// use the energy labeled `label` as the primary energy of each job. see
+    /// [DrainOptions::energy_label]
+    pub fn with_energy_label(mut self, label: &str) -> Self {
+        self.energy_label = Some(label.to_owned());
+        self
+    }
+
+    /// the directory of a persistent cache of results keyed by the contents of
+    /// [Program::input_files]. if this is set, jobs whose inputs match a
+    /// cached result are not submitted
+    ///
+    /// [Program::input_files]: crate::program::Program::input_files
+    pub fn cache_dir(&self) -> Option<&str> {
+        self.cache_dir.as_deref()
+    }
+
+    /// if this returns a tolerance, jobs in a drain that render the same input
+    /// files are only run once, with the result shared by all of them. jobs
+    /// whose Cartesian coordinates agree to within a positive tolerance are
+    /// also considered identical. comparing the inputs means writing them out
+    /// ahead of time, so this is off by default
+    pub fn dedup_tol(&self) -> Option<f64> {
+        self.dedup_tol
+    }
+
+    /// the token to check for cancellation while draining, if any
+    pub fn cancel_token(&self) -> Option<&CancelToken> {
+        self.cancel_token.as_ref()
+    }
+
+    /// if this returns `Some(n)` and the `notify` feature is enabled, only the
+    /// output files reported as changed by filesystem notifications are read
+    /// while draining, along with those of jobs no longer in the queue. every
+    /// output is still read on every `n`th iteration to catch changes that
+    /// were not reported, such as writes from other nodes on a network
+    /// filesystem
+    pub fn watch_outputs(&self) -> Option<usize> {
+        self.watch
+    }
+
+    /// if this returns `Some((min, max))`, the drain sleeps for between `min`
+    /// and `max` seconds between checks of the queue, adapting to the rate at
+    /// which jobs finish and to their run times, instead of always sleeping
+    /// for [SubQueue::sleep_int]
+    ///
+    /// [SubQueue::sleep_int]: super::SubQueue::sleep_int
+    pub fn poll_range(&self) -> Option<(usize, usize)> {
+        self.poll
+    }
+
+    /// the number of jobs in each chunk to run at once inside a single
+    /// allocation. when this is more than one, the submit script runs the jobs
+    /// in the background in batches of this size, each job with its own log
+    pub fn parallel_jobs(&self) -> usize {
+        self.parallel
+    }
+
+    /// the number of cpus to request for each submit script, which replaces
+    /// `{{.cpus}}` in the template. this covers all of the
+    /// [DrainOptions::parallel_jobs] at once
+    pub fn cpus(&self) -> usize {
+        self.cpus
+    }
+
+    /// how to reach the machine where the jobs run. the default, [Direct],
+    /// runs everything locally
+    pub fn transport(&self) -> &dyn Transport {
+        self.transport.as_deref().unwrap_or(&Direct)
+    }
+
+    /// a shared handle to [DrainOptions::transport] if it's remote, for
+    /// deleting files on the remote machine from another thread
+    pub(crate) fn remote_transport(&self) -> Option<Arc<dyn Transport>> {
+        self.transport.clone().filter(|t| t.is_remote())
+    }
+
+    /// where to record the submissions, status snapshots, and output reads of
+    /// each drain, for replaying it later with [super::replay::Replay]
+    #[cfg(feature = "replay")]
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_deref()
+    }
+
+    /// whether submit scripts run [Queue::marker_cmd] after each job. this is
+    /// off by default, but markers are always written for a remote
+    /// [DrainOptions::transport], which only copies back the outputs of jobs
+    /// whose markers have arrived
+    ///
+    /// [Queue::marker_cmd]: super::Queue::marker_cmd
+    pub fn write_markers(&self) -> bool {
+        self.markers
+    }
+
+    /// whether checkpoints are written gzip-compressed, as `chk.json.gz`
+    /// instead of `chk.json`
+    pub fn compress_checkpoints(&self) -> bool {
+        self.compress
+    }
+
+    /// if this returns a label, the energy with that label in
+    /// [ProgramResult::energies] replaces [ProgramResult::energy] for every
+    /// job, and jobs whose output doesn't contain it fail. otherwise each
+    /// program's own primary energy is used
+    ///
+    /// [ProgramResult::energies]: crate::program::ProgramResult::energies
+    /// [ProgramResult::energy]: crate::program::ProgramResult::energy
+    pub fn energy_label(&self) -> Option<&str> {
+        self.energy_label.as_deref()
+    }
+}
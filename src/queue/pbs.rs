@@ -10,7 +10,7 @@ use crate::program::mopac::Mopac;
 use crate::program::Program;
 use crate::queue::Queue;
 
-use super::{SubQueue, Submit};
+use super::{cancel_with, DrainOptions, SubQueue, Submit};
 
 /// Pbs is a type for holding the information for submitting a pbs job.
 /// `filename` is the name of the Pbs submission script
@@ -22,6 +22,7 @@ pub struct Pbs {
     pub dir: &'static str,
     pub no_del: bool,
     pub template: Option<String>,
+    pub options: DrainOptions,
 }
 
 impl Pbs {
@@ -40,8 +41,16 @@ impl Pbs {
             dir,
             no_del,
             template,
+            options: DrainOptions::default(),
         }
     }
+
+    /// drain with `options` instead of the defaults. see
+    /// [SubQueue::options]
+    pub fn with_options(mut self, options: DrainOptions) -> Self {
+        self.options = options;
+        self
+    }
 }
 
 impl Submit<Mopac> for Pbs
@@ -49,11 +58,17 @@ where
     Mopac: Serialize + for<'a> Deserialize<'a>,
 {
     /// submit `filename` to the queue and return the jobid
-    fn submit(&self, filename: &str) -> String {
+    fn submit_cmd(&self, filename: &str) -> Command {
         let mut cmd =
             Command::new(<Self as SubQueue<Mopac>>::submit_command(self));
-        let cmd = cmd.arg("-f").arg(filename);
-        submit_inner(cmd, self.sleep_int).unwrap()
+        cmd.arg("-f").arg(filename);
+        cmd
+    }
+
+    fn submit(&self, filename: &str) -> String {
+        let cmd = <Self as Submit<Mopac>>::submit_cmd(self, filename);
+        let mut cmd = self.options.transport().command(cmd);
+        submit_inner(&mut cmd, self.sleep_int).unwrap()
     }
 }
 
@@ -63,14 +78,20 @@ impl Submit<Molpro> for Pbs
 where
     Molpro: Serialize + for<'a> Deserialize<'a>,
 {
-    fn submit(&self, filename: &str) -> String {
+    fn submit_cmd(&self, filename: &str) -> Command {
         let path = Path::new(filename);
         let dir = path.parent().unwrap();
         let base = path.file_name().unwrap();
         let mut cmd =
             Command::new(<Self as SubQueue<Molpro>>::submit_command(self));
-        let cmd = cmd.arg(base).current_dir(dir);
-        submit_inner(cmd, self.sleep_int).unwrap()
+        cmd.arg(base).current_dir(dir);
+        cmd
+    }
+
+    fn submit(&self, filename: &str) -> String {
+        let cmd = <Self as Submit<Molpro>>::submit_cmd(self, filename);
+        let mut cmd = self.options.transport().command(cmd);
+        submit_inner(&mut cmd, self.sleep_int).unwrap()
     }
 }
 
@@ -128,6 +149,12 @@ where
         format!("$MOLPRO_CMD {basename:?}.inp")
     }
 
+    /// the Molpro script runs in the directory of the submit script
+    fn script_path(&self, filename: &str) -> String {
+        let basename = Path::new(&filename).file_name().unwrap();
+        basename.to_str().unwrap().to_owned()
+    }
+
     fn default_submit_script(&self) -> String {
         "#!/bin/sh
 #PBS -N {{.basename}}
@@ -136,7 +163,7 @@ where
 #PBS -o {{.basename}}.out
 #PBS -W umask=022
 #PBS -l walltime=1000:00:00
-#PBS -l ncpus=1
+#PBS -l ncpus={{.cpus}}
 #PBS -l mem=8gb
 #PBS -q workq
 
@@ -171,7 +198,7 @@ impl Queue<Mopac> for Pbs {
 #PBS -o {{.filename}}.out
 #PBS -W umask=022
 #PBS -l walltime=1000:00:00
-#PBS -l ncpus=1
+#PBS -l ncpus={{.cpus}}
 #PBS -l mem=1gb
 #PBS -q workq
 
@@ -204,7 +231,7 @@ impl Queue<DFTBPlus> for Pbs {
 #PBS -o {{.filename}}.out
 #PBS -W umask=022
 #PBS -l walltime=1000:00:00
-#PBS -l ncpus=1
+#PBS -l ncpus={{.cpus}}
 #PBS -l mem=8gb
 #PBS -q workq
 
@@ -220,11 +247,17 @@ export DFTB_CMD=/ddnlus/r2518/.conda/envs/dftb/bin/dftb+
 }
 
 impl Submit<DFTBPlus> for Pbs {
-    fn submit(&self, filename: &str) -> String {
+    fn submit_cmd(&self, filename: &str) -> Command {
         let mut cmd =
             Command::new(<Self as SubQueue<DFTBPlus>>::submit_command(self));
-        let cmd = cmd.arg("-f").arg(filename);
-        submit_inner(cmd, self.sleep_int).unwrap()
+        cmd.arg("-f").arg(filename);
+        cmd
+    }
+
+    fn submit(&self, filename: &str) -> String {
+        let cmd = <Self as Submit<DFTBPlus>>::submit_cmd(self, filename);
+        let mut cmd = self.options.transport().command(cmd);
+        submit_inner(&mut cmd, self.sleep_int).unwrap()
     }
 }
 
@@ -254,7 +287,7 @@ where
         self.dir
     }
 
-    /// run `qstat -u $USER`. form of the output is:
+    /// run `qstat -u` for the [super::Transport::user]. form of the output is:
     ///
     /// maple:
     ///                                                     Req'd  Req'd   Elap
@@ -262,30 +295,82 @@ where
     /// ------- -------- -------- ---------- ------ --- --- ------ ----- - -----
     /// 819446  user     queue    C6HNpts      5085   1   1    8gb 26784 R 00:00
     fn stat_cmd(&self) -> String {
-        let user = std::env::var("USER").expect("couldn't find $USER env var");
-        let status = match Command::new("qstat").args(["-u", &user]).output() {
+        let transport = self.options.transport();
+        let mut cmd = transport.command(qstat(&transport.user()));
+        let status = match cmd.output() {
             Ok(status) => status,
-            Err(e) => panic!("failed to run `qstat -u {user}` with {e}"),
+            Err(e) => panic!("failed to run qstat with {e}"),
         };
         String::from_utf8(status.stdout).expect("failed to parse qstat output")
     }
 
     fn status(&self) -> HashSet<String> {
-        let mut ret = HashSet::new();
-        let lines = <Pbs as SubQueue<P>>::stat_cmd(self);
-        // skip to end of header
-        let lines = lines.lines().skip_while(|l| !l.contains("-----------"));
-        for line in lines {
-            let fields: Vec<_> = line.split_whitespace().collect();
-            assert!(fields.len() == 11);
-            ret.insert(fields[0].to_string());
-        }
-        ret
+        parse_qstat(&<Pbs as SubQueue<P>>::stat_cmd(self))
     }
 
     fn no_del(&self) -> bool {
         self.no_del
     }
+
+    /// run `qdel` on `job_ids`
+    fn cancel(&self, job_ids: &[String]) {
+        cancel_with(self.options.transport(), "qdel", job_ids);
+    }
+
+    fn options(&self) -> &DrainOptions {
+        &self.options
+    }
+}
+
+#[cfg(feature = "async")]
+impl<P> super::AsyncQueue<P> for Pbs
+where
+    Pbs: Queue<P>,
+    P: Program
+        + Clone
+        + Send
+        + Sync
+        + Serialize
+        + for<'a> Deserialize<'a>
+        + 'static,
+{
+    async fn status_async(&self) -> HashSet<String> {
+        let transport = self.options.transport();
+        let status = match tokio::process::Command::from(
+            transport.command(qstat(&transport.user())),
+        )
+        .output()
+        .await
+        {
+            Ok(status) => status,
+            Err(e) => panic!("failed to run qstat with {e}"),
+        };
+        parse_qstat(
+            &String::from_utf8(status.stdout)
+                .expect("failed to parse qstat output"),
+        )
+    }
+}
+
+/// the command run by [SubQueue::stat_cmd], listing the jobs of `user`
+pub(crate) fn qstat(user: &str) -> Command {
+    let mut cmd = Command::new("qstat");
+    cmd.args(["-u", user]);
+    cmd
+}
+
+/// return the ids of the jobs in the output of [qstat]
+pub(crate) fn parse_qstat(lines: &str) -> HashSet<String> {
+    // skip to end of header
+    lines
+        .lines()
+        .skip_while(|l| !l.contains("-----------"))
+        .map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            assert!(fields.len() == 11);
+            fields[0].to_string()
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -304,6 +389,7 @@ mod tests {
             dir: "/tmp",
             no_del: false,
             template: None,
+            options: DrainOptions::default(),
         }
     }
 
@@ -335,5 +421,8 @@ mod tests {
         molpro_pbs, &pbs() =>  Molpro,
         cfour_pbs, &pbs() => Cfour,
         dftb_pbs, &pbs() => DFTBPlus,
+        mopac_parallel_pbs,
+            &pbs().with_options(DrainOptions::new().with_parallel(4, 2))
+            => Mopac,
     }
 }
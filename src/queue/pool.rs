@@ -0,0 +1,476 @@
+//! a pilot-job [Queue] that runs the submit scripts of another queue on a
+//! fixed number of long-lived worker jobs. instead of submitting each chunk
+//! to the scheduler, [Pool] publishes it to a shared directory, where the
+//! workers claim chunks by atomically renaming them until none are left. this
+//! keeps the number of scheduler jobs small for very large drains
+//!
+//! the pool directory holds:
+//! - `todo/`: published chunks waiting for a worker
+//! - `claimed/`: chunks being run, with the name of the worker appended.
+//!   chunks left here by a worker whose heartbeat has gone stale are moved
+//!   back to `todo/`
+//! - `workers/`: a heartbeat file touched by each running worker, and an
+//!   `.exit` file written when a worker runs out of work
+//!
+//! the pool directory is only ever accessed from this process's filesystem, so
+//! the inner queue can't use a remote [Transport]
+//!
+//! [Transport]: super::transport::Transport
+
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::program::Program;
+
+use super::{DrainOptions, Queue, SubQueue, Submit};
+
+/// a running worker that hasn't touched its heartbeat for this long is
+/// assumed to have been killed
+const STALE: Duration = Duration::from_secs(60);
+
+/// a [Queue] that runs the chunks written for `Q` on a pool of worker jobs
+/// submitted to `Q`. with a blocking queue like [super::local::Local], each
+/// worker runs to completion when it is submitted
+pub struct Pool<Q> {
+    inner: Q,
+
+    /// the absolute path of the pool directory
+    dir: PathBuf,
+
+    /// the number of workers to keep running while there is work
+    workers: usize,
+
+    /// the number of seconds a worker waits for more work before exiting
+    idle: usize,
+
+    /// how long a submitted worker may wait to start before it is assumed to
+    /// have been lost
+    start: Duration,
+
+    /// workers submitted by this process that haven't started yet, along with
+    /// when they were submitted
+    pending: Mutex<Vec<(String, Instant)>>,
+
+    /// counters for naming workers and chunks uniquely
+    launched: AtomicUsize,
+    published: AtomicUsize,
+}
+
+impl<Q> Pool<Q> {
+    /// run the jobs on up to `workers` jobs submitted to `inner`, sharing work
+    /// through the directory `dir`, which must be visible to all of the nodes.
+    /// returns an error if the pool directory can't be created
+    pub fn new(inner: Q, dir: &str, workers: usize) -> io::Result<Self> {
+        for sub in ["todo", "claimed", "workers"] {
+            fs::create_dir_all(Path::new(dir).join(sub))?;
+        }
+        Ok(Self {
+            inner,
+            dir: fs::canonicalize(dir)?,
+            workers: workers.max(1),
+            idle: 60,
+            start: Duration::from_secs(3600),
+            pending: Mutex::new(Vec::new()),
+            launched: AtomicUsize::new(0),
+            published: AtomicUsize::new(0),
+        })
+    }
+
+    /// the number of seconds a worker waits for more work before exiting.
+    /// defaults to 60
+    pub fn with_idle(mut self, idle: usize) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// the number of seconds a submitted worker may wait in `inner` before it
+    /// is assumed to have been lost and is replaced. defaults to an hour
+    pub fn with_start_timeout(mut self, start: usize) -> Self {
+        self.start = Duration::from_secs(start as u64);
+        self
+    }
+
+    fn sub(&self, sub: &str) -> PathBuf {
+        self.dir.join(sub)
+    }
+
+    /// the names of the files in the subdirectory `sub`, skipping hidden files
+    fn list(&self, sub: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(self.sub(sub)) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| !name.starts_with('.'))
+            .collect()
+    }
+
+    /// the names of the workers with recent heartbeats
+    fn running(&self) -> HashSet<String> {
+        let now = SystemTime::now();
+        let Ok(entries) = fs::read_dir(self.sub("workers")) else {
+            return HashSet::new();
+        };
+        entries
+            .flatten()
+            .filter(|e| {
+                e.metadata().and_then(|m| m.modified()).is_ok_and(|t| {
+                    now.duration_since(t).unwrap_or_default() < STALE
+                })
+            })
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| !name.ends_with(".exit"))
+            .collect()
+    }
+
+    /// the shell loop run by worker `name` after the setup from the template
+    fn worker_loop(&self, name: &str) -> String {
+        format!(
+            r#"POOL="{dir}"
+W={name}
+touch "$POOL/workers/$W"
+idle=0
+while :; do
+    claimed=
+    for f in "$POOL"/todo/*; do
+        [ -e "$f" ] || break
+        item="$POOL/claimed/$(basename "$f").$W"
+        mv "$f" "$item" 2>/dev/null || continue
+        claimed=1
+        bash "$item" &
+        while kill -0 $! 2>/dev/null; do
+            touch "$POOL/workers/$W"
+            sleep 1
+        done
+        rm -f "$item"
+        break
+    done
+    if [ -n "$claimed" ]; then
+        idle=0
+    elif [ $idle -ge {idle} ]; then
+        break
+    else
+        touch "$POOL/workers/$W"
+        sleep 1
+        idle=$((idle + 1))
+    fi
+done
+rm -f "$POOL/workers/$W"
+touch "$POOL/workers/$W.exit"
+"#,
+            dir = self.dir.display(),
+            idle = self.idle,
+        )
+    }
+
+    /// submit enough workers to `inner` to bring the number running or
+    /// waiting to start up to `self.workers`
+    fn launch<P>(&self)
+    where
+        P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+        Q: Queue<P>,
+    {
+        let running = self.running();
+        let mut pending = self.pending.lock().unwrap();
+        // forget pending workers that have started, exited, or waited so long
+        // to start that they were probably lost
+        let workers = self.sub("workers");
+        pending.retain(|(name, submitted)| {
+            let exit = workers.join(format!("{name}.exit"));
+            !running.contains(name)
+                && !exit.exists()
+                && submitted.elapsed() < self.start
+        });
+        for _ in running.len() + pending.len()..self.workers {
+            let n = self.launched.fetch_add(1, Ordering::SeqCst);
+            let name = format!("{}-{n}", process::id());
+            let script = self.dir.join(format!(
+                "worker-{name}.{}",
+                <Q as SubQueue<P>>::SCRIPT_EXT
+            ));
+            let script = script.to_str().unwrap();
+            self.inner.write_submit_script(std::iter::empty(), script);
+            let mut f = OpenOptions::new().append(true).open(script).unwrap();
+            f.write_all(self.worker_loop(&name).as_bytes()).unwrap();
+            drop(f);
+            self.inner.submit(script);
+            // a queue like Local may have run the whole worker already
+            let exit = workers.join(format!("{name}.exit"));
+            if exit.exists() {
+                let _ = fs::remove_file(exit);
+            } else if !self.running().contains(&name) {
+                pending.push((name, Instant::now()));
+            }
+        }
+    }
+}
+
+impl<P, Q> Submit<P> for Pool<Q>
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P>,
+{
+    fn submit_cmd(&self, filename: &str) -> process::Command {
+        self.inner.submit_cmd(filename)
+    }
+
+    /// publish `filename` to the work queue, making sure that there are
+    /// workers to run it. the returned id is the name of the published chunk
+    fn submit(&self, filename: &str) -> String {
+        let path = Path::new(filename);
+        let n = self.published.fetch_add(1, Ordering::SeqCst);
+        let id = format!(
+            "{}-{n}-{}",
+            process::id(),
+            path.file_name().unwrap().to_str().unwrap()
+        );
+        // emulate submitting `filename` with `inner`: run it from the same
+        // directory and record its output in the same place
+        let cwd = std::env::current_dir().unwrap();
+        let cmd = self.inner.submit_cmd(filename);
+        let run_dir = cwd.join(cmd.get_current_dir().unwrap_or(Path::new(".")));
+        let script = cwd.join(filename);
+        let item = format!(
+            "cd \"{dir}\"
+export PBS_O_WORKDIR=\"$PWD\" SLURM_SUBMIT_DIR=\"$PWD\"
+bash \"{script}\" > \"{script}.out\" 2>&1
+",
+            dir = run_dir.display(),
+            script = script.display(),
+        );
+        // write to a hidden file first so that workers never see a partial
+        // chunk
+        let tmp = self.sub("todo").join(format!(".{id}"));
+        fs::write(&tmp, item).unwrap();
+        fs::rename(&tmp, self.sub("todo").join(&id)).unwrap();
+        self.launch::<P>();
+        id
+    }
+}
+
+impl<P, Q> SubQueue<P> for Pool<Q>
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P>,
+{
+    const SCRIPT_EXT: &'static str = <Q as SubQueue<P>>::SCRIPT_EXT;
+
+    fn dir(&self) -> &str {
+        self.inner.dir()
+    }
+
+    fn submit_command(&self) -> &str {
+        self.inner.submit_command()
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.inner.chunk_size()
+    }
+
+    fn job_limit(&self) -> usize {
+        self.inner.job_limit()
+    }
+
+    fn sleep_int(&self) -> usize {
+        self.inner.sleep_int()
+    }
+
+    fn stat_cmd(&self) -> String {
+        self.inner.stat_cmd()
+    }
+
+    /// the chunks that are waiting to run or are being run by a live worker.
+    /// chunks claimed by dead workers are put back to run again, and workers
+    /// that have died while work remains are replaced
+    fn status(&self) -> HashSet<String> {
+        let running = self.running();
+        let mut todo = self.list("todo");
+        let mut claimed = Vec::new();
+        for name in self.list("claimed") {
+            let Some((id, worker)) = name.rsplit_once('.') else {
+                continue;
+            };
+            if running.contains(worker) {
+                claimed.push(id.to_owned());
+                continue;
+            }
+            let from = self.sub("claimed").join(&name);
+            if fs::rename(from, self.sub("todo").join(id)).is_ok() {
+                log::warn!("requeuing {id} from dead worker {worker}");
+                todo.push(id.to_owned());
+            }
+        }
+        if !todo.is_empty() {
+            self.launch::<P>();
+        }
+        todo.into_iter().chain(claimed).collect()
+    }
+
+    fn no_del(&self) -> bool {
+        self.inner.no_del()
+    }
+
+    /// remove the chunks in `job_ids` that haven't been claimed yet. chunks
+    /// already claimed by a worker run to completion
+    fn cancel(&self, job_ids: &[String]) {
+        for id in job_ids {
+            let _ = fs::remove_file(self.sub("todo").join(id));
+        }
+    }
+
+    /// the options of the inner queue. this panics if they use a remote
+    /// [Transport], since chunks are published to and collected from the pool
+    /// directory on the local filesystem
+    ///
+    /// [Transport]: super::transport::Transport
+    fn options(&self) -> &DrainOptions {
+        let options = self.inner.options();
+        if options.transport().is_remote() {
+            panic!(
+                "Pool can't use a remote transport: its directory {} is only \
+                 accessed locally",
+                self.dir.display()
+            );
+        }
+        options
+    }
+}
+
+impl<P, Q> Queue<P> for Pool<Q>
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P>,
+{
+    fn default_submit_script(&self) -> String {
+        self.inner.default_submit_script()
+    }
+
+    fn template(&self) -> &Option<String> {
+        self.inner.template()
+    }
+
+    fn program_cmd(&self, filename: &str) -> String {
+        self.inner.program_cmd(filename)
+    }
+
+    fn marker_cmd(&self, filename: &str) -> String {
+        self.inner.marker_cmd(filename)
+    }
+
+    fn script_path(&self, filename: &str) -> String {
+        self.inner.script_path(filename)
+    }
+
+    fn write_submit_script(
+        &self,
+        infiles: impl IntoIterator<Item = String>,
+        filename: &str,
+    ) {
+        self.inner.write_submit_script(infiles, filename)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::{
+        geom::Geom,
+        program::{molpro::Molpro, Job, Template},
+        queue::{local::Local, transport::Mirror, Check},
+    };
+
+    use super::*;
+
+    #[test]
+    fn drain() {
+        let template = "molpro() { cp testfiles/molpro/opt.out ${1%.inp}.out; }
+MOLPRO_CMD=molpro
+"
+        .to_owned();
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path().to_str().unwrap();
+        let queue = Pool::new(
+            Local {
+                chunk_size: 2,
+                template: Some(template),
+                ..Default::default()
+            },
+            &format!("{d}/pool"),
+            2,
+        )
+        .unwrap()
+        .with_idle(0);
+        let jobs = (0..5)
+            .map(|i| {
+                let geom = format!("H 0.0 0.0 {i}.0");
+                let program = Molpro::new(
+                    format!("{d}/job{i}"),
+                    Template::from(""),
+                    0,
+                    Geom::from_str(&geom).unwrap(),
+                );
+                Job::new(program, i)
+            })
+            .collect();
+        let mut dst = vec![0.0; 5];
+        let got = queue.drain(d, jobs, &mut dst, Check::None);
+        assert_eq!(got, Ok(5.0 * 27.13));
+        assert_eq!(dst, vec![-76.369839620286; 5]);
+        assert!(queue.list("todo").is_empty());
+        assert!(queue.list("claimed").is_empty());
+    }
+
+    /// a chunk claimed by a worker without a heartbeat is run again
+    #[test]
+    fn stale_claim() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path().to_str().unwrap();
+        let queue = Pool::new(Local::default(), &format!("{d}/pool"), 1)
+            .unwrap()
+            .with_idle(0);
+        let item = queue.sub("claimed").join("1-0-main.slurm.dead-0");
+        fs::write(item, format!("touch {d}/ran\n")).unwrap();
+        let status = <Pool<Local> as SubQueue<Molpro>>::status(&queue);
+        assert_eq!(status, HashSet::from(["1-0-main.slurm".to_owned()]));
+        assert!(Path::new(&format!("{d}/ran")).exists());
+        assert!(queue.list("todo").is_empty());
+        assert!(queue.list("claimed").is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Pool can't use a remote transport")]
+    fn remote_transport() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path().to_str().unwrap();
+        let inner = Local {
+            options: DrainOptions::new()
+                .with_transport(Mirror::new(format!("{d}/remote"))),
+            ..Default::default()
+        };
+        let queue = Pool::new(inner, &format!("{d}/pool"), 1).unwrap();
+        <Pool<Local> as SubQueue<Molpro>>::options(&queue);
+    }
+
+    #[test]
+    fn new_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let got = Pool::new(Local::default(), file.to_str().unwrap(), 1);
+        assert!(got.is_err());
+    }
+}
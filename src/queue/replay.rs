@@ -0,0 +1,423 @@
+//! recording and replaying the interaction of a drain with its queue. a
+//! [Recorder] set with [DrainOptions::with_recorder] writes a JSON-lines log
+//! of every submission, every snapshot of the queue status, and every output
+//! read in a drain. [Replay] then feeds the same log back to a drain of
+//! [Replayed] jobs, so that the decisions the drain made can be reproduced
+//! offline. this works as long as the replay makes the same calls as the
+//! recording, which holds for the drain itself but not for reads skipped by
+//! [DrainOptions::watch_outputs], so drains should be recorded without it.
+//! replays have to run in the same directory as the recording, since the jobs
+//! keep their recorded filenames. requires the `replay` feature
+//!
+//! [DrainOptions::with_recorder]: super::DrainOptions::with_recorder
+//! [DrainOptions::watch_outputs]: super::DrainOptions::watch_outputs
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::program::{
+    replayed::{self, Outcome, Replayed},
+    Job, Program, ProgramError, ProgramResult,
+};
+
+use super::{drain::JsonlWriter, marker, Queue, SubQueue, Submit};
+
+/// a [ProgramError] in a form that can be written to the log
+#[derive(Debug, Deserialize, Serialize)]
+struct Error {
+    /// the [ProgramError::kind]
+    kind: String,
+
+    /// the file named in the error
+    file: String,
+
+    /// the exit status for [ProgramError::ExitStatus]
+    #[serde(default)]
+    status: Option<i32>,
+}
+
+impl From<&ProgramError> for Error {
+    fn from(e: &ProgramError) -> Self {
+        let (file, status) = match e {
+            ProgramError::FileNotFound(f)
+            | ProgramError::ErrorInOutput(f)
+            | ProgramError::EnergyNotFound(f)
+            | ProgramError::EnergyParseError(f)
+            | ProgramError::GeomNotFound(f)
+            | ProgramError::DipoleNotFound(f)
+            | ProgramError::LabelNotFound(f)
+            | ProgramError::ParseError(f)
+            | ProgramError::ReadFileError(f, _) => (f.clone(), None),
+            ProgramError::ExitStatus(f, s) => (f.clone(), Some(*s)),
+        };
+        Self {
+            kind: e.kind().to_owned(),
+            file,
+            status,
+        }
+    }
+}
+
+impl From<Error> for ProgramError {
+    /// the [std::io::ErrorKind] of a [ProgramError::ReadFileError] is not
+    /// recorded, so it comes back as [std::io::ErrorKind::Other]
+    fn from(e: Error) -> Self {
+        match e.kind.as_str() {
+            "FileNotFound" => Self::FileNotFound(e.file),
+            "ErrorInOutput" => Self::ErrorInOutput(e.file),
+            "EnergyNotFound" => Self::EnergyNotFound(e.file),
+            "EnergyParseError" => Self::EnergyParseError(e.file),
+            "GeomNotFound" => Self::GeomNotFound(e.file),
+            "DipoleNotFound" => Self::DipoleNotFound(e.file),
+            "LabelNotFound" => Self::LabelNotFound(e.file),
+            "ParseError" => Self::ParseError(e.file),
+            "ExitStatus" => Self::ExitStatus(e.file, e.status.unwrap_or(1)),
+            _ => Self::ReadFileError(e.file, std::io::ErrorKind::Other),
+        }
+    }
+}
+
+/// one line of the log
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    /// the jobs left to run in `dir` after deduplication and the cache, with
+    /// the queue settings that decide how they are split up
+    Start {
+        dir: String,
+        chunk_size: usize,
+        job_limit: usize,
+        jobs: Vec<Job<Replayed>>,
+    },
+
+    /// the submit script `script` was submitted as `job_id`
+    Submit { script: String, job_id: String },
+
+    /// the jobs reported by [SubQueue::status]
+    Status { jobs: BTreeSet<String> },
+
+    /// the result of the `read`th read of the output of the job `filename`,
+    /// along with the modification time of its output file in seconds since
+    /// the Unix epoch and the contents of its completion marker. reads that
+    /// return the same as the one before them are not recorded
+    Read {
+        filename: String,
+        read: usize,
+        result: Result<ProgramResult, Error>,
+        modified: Option<f64>,
+        done: Option<String>,
+    },
+}
+
+/// writes the log read by [Replay]. see [DrainOptions::recorder]
+///
+/// [DrainOptions::recorder]: super::DrainOptions::recorder
+#[derive(Debug)]
+pub struct Recorder {
+    out: JsonlWriter,
+
+    /// the number of reads of each job so far and the last one recorded
+    reads: Mutex<HashMap<String, (usize, String)>>,
+}
+
+impl Recorder {
+    /// create a new log at `path`, truncating it if it already exists
+    pub fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            out: JsonlWriter::create(path, "replay log")?,
+            reads: Mutex::default(),
+        })
+    }
+
+    fn write(&self, event: &Event) {
+        self.out.write(event);
+    }
+
+    /// record the start of a drain in `dir` with `jobs` left to run
+    pub(crate) fn start<'a, P, Q>(
+        &self,
+        queue: &Q,
+        dir: &str,
+        jobs: impl IntoIterator<Item = &'a Job<P>>,
+    ) where
+        P: Program + Clone + Serialize + for<'b> Deserialize<'b> + 'a,
+        Q: SubQueue<P> + ?Sized,
+    {
+        let jobs = jobs
+            .into_iter()
+            .map(|job| Job {
+                program: Replayed::from_value(
+                    job.program.filename(),
+                    serde_json::to_value(&job.program).unwrap_or_default(),
+                ),
+                pbs_file: job.pbs_file.clone(),
+                job_id: job.job_id.clone(),
+                index: job.index,
+                coeff: job.coeff,
+                modtime: job.modtime,
+                dups: job.dups.clone(),
+                symm_dups: job.symm_dups.clone(),
+                seen: job.seen,
+            })
+            .collect();
+        self.write(&Event::Start {
+            dir: dir.to_owned(),
+            chunk_size: queue.chunk_size(),
+            job_limit: queue.job_limit(),
+            jobs,
+        });
+    }
+
+    pub(crate) fn submit(&self, script: &str, job_id: &str) {
+        self.write(&Event::Submit {
+            script: script.to_owned(),
+            job_id: job_id.to_owned(),
+        });
+    }
+
+    pub(crate) fn status(&self, jobs: &HashSet<String>) {
+        self.write(&Event::Status {
+            jobs: jobs.iter().cloned().collect(),
+        });
+    }
+
+    /// record `result`, the output of the job `filename`, if it differs from
+    /// the last one recorded for it
+    pub(crate) fn read(
+        &self,
+        filename: &str,
+        result: &Result<ProgramResult, ProgramError>,
+    ) {
+        let modified = std::fs::metadata(format!("{filename}.out"))
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64());
+        let result = result.as_ref().cloned().map_err(Error::from);
+        let done = std::fs::read_to_string(marker::done_file(filename)).ok();
+        let key = serde_json::to_string(&(&result, modified, &done))
+            .unwrap_or_default();
+        let mut reads = self.reads.lock().unwrap();
+        let (read, last) = reads.entry(filename.to_owned()).or_default();
+        if *read == 0 || key != *last {
+            self.write(&Event::Read {
+                filename: filename.to_owned(),
+                read: *read,
+                result,
+                modified,
+                done,
+            });
+            *last = key;
+        }
+        *read += 1;
+    }
+}
+
+/// the recorded events not yet replayed
+#[derive(Default)]
+struct State {
+    /// job ids by the name of their submit script without its extension,
+    /// which depends on the type of queue
+    submits: HashMap<String, VecDeque<String>>,
+    statuses: VecDeque<HashSet<String>>,
+}
+
+/// a [Queue] that replays a log written by a [Recorder]. drain the jobs from
+/// [Replay::jobs] in the recorded directory, [SubQueue::dir], with it to
+/// repeat the recorded drain
+pub struct Replay {
+    dir: String,
+    chunk_size: usize,
+    job_limit: usize,
+    template: Option<String>,
+    jobs: Vec<Job<Replayed>>,
+    state: Mutex<State>,
+}
+
+impl Replay {
+    /// load the log at `path`. this also queues up the recorded outputs for
+    /// [Replayed::read_output]
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut ret = Self {
+            dir: String::from("."),
+            chunk_size: 1,
+            job_limit: 1,
+            template: None,
+            jobs: Vec::new(),
+            state: Mutex::default(),
+        };
+        let mut state = State::default();
+        let mut outcomes: HashMap<String, VecDeque<Outcome>> = HashMap::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            match serde_json::from_str(&line?)? {
+                Event::Start {
+                    dir,
+                    chunk_size,
+                    job_limit,
+                    jobs,
+                } => {
+                    ret.dir = dir;
+                    ret.chunk_size = chunk_size;
+                    ret.job_limit = job_limit;
+                    ret.jobs = jobs;
+                }
+                Event::Submit { script, job_id } => state
+                    .submits
+                    .entry(stem(&script).to_owned())
+                    .or_default()
+                    .push_back(job_id),
+                Event::Status { jobs } => {
+                    state.statuses.push_back(jobs.into_iter().collect())
+                }
+                Event::Read {
+                    filename,
+                    read,
+                    result,
+                    modified,
+                    done,
+                } => outcomes.entry(filename).or_default().push_back(Outcome {
+                    read,
+                    result: result.map_err(ProgramError::from),
+                    modified: modified.map(|s| {
+                        SystemTime::UNIX_EPOCH + Duration::from_secs_f64(s)
+                    }),
+                    done,
+                }),
+            }
+        }
+        replayed::register(outcomes);
+        ret.state = Mutex::new(state);
+        Ok(ret)
+    }
+
+    /// the recorded jobs, ready to be drained again
+    pub fn jobs(&self) -> Vec<Job<Replayed>> {
+        self.jobs.clone()
+    }
+
+    /// the number of recorded submissions and status snapshots not yet
+    /// replayed. this is zero after a faithful replay
+    pub fn remaining(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.submits.values().map(VecDeque::len).sum::<usize>()
+            + state.statuses.len()
+    }
+}
+
+/// `script` without its extension
+fn stem(script: &str) -> &str {
+    let name = Path::new(script).file_name().map_or(0, |n| n.len());
+    match script.rfind('.') {
+        Some(i) if i >= script.len() - name => &script[..i],
+        _ => script,
+    }
+}
+
+impl Submit<Replayed> for Replay {
+    /// return the job id recorded for `filename`
+    fn submit(&self, filename: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        match state
+            .submits
+            .get_mut(stem(filename))
+            .and_then(VecDeque::pop_front)
+        {
+            Some(id) => id,
+            None => {
+                log::warn!("replay diverged: {filename} was never submitted");
+                String::new()
+            }
+        }
+    }
+}
+
+impl Queue<Replayed> for Replay {
+    fn template(&self) -> &Option<String> {
+        &self.template
+    }
+
+    fn program_cmd(&self, filename: &str) -> String {
+        format!("replay {filename}")
+    }
+
+    fn default_submit_script(&self) -> String {
+        String::new()
+    }
+}
+
+impl SubQueue<Replayed> for Replay {
+    const SCRIPT_EXT: &'static str = "replay";
+
+    fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    fn submit_command(&self) -> &str {
+        "replay"
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    fn job_limit(&self) -> usize {
+        self.job_limit
+    }
+
+    fn sleep_int(&self) -> usize {
+        0
+    }
+
+    fn stat_cmd(&self) -> String {
+        String::new()
+    }
+
+    /// return the next recorded snapshot, or an empty queue once they run out
+    fn status(&self) -> HashSet<String> {
+        let mut state = self.state.lock().unwrap();
+        state.statuses.pop_front().unwrap_or_else(|| {
+            log::warn!("replay diverged: no more status snapshots");
+            HashSet::new()
+        })
+    }
+
+    fn no_del(&self) -> bool {
+        false
+    }
+
+    fn cancel(&self, _job_ids: &[String]) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors() {
+        let errors = [
+            ProgramError::FileNotFound("a.out".into()),
+            ProgramError::ErrorInOutput("a.out".into()),
+            ProgramError::ExitStatus("a".into(), 137),
+        ];
+        for e in errors {
+            let s = serde_json::to_string(&Error::from(&e)).unwrap();
+            let got: Error = serde_json::from_str(&s).unwrap();
+            assert_eq!(ProgramError::from(got), e);
+        }
+    }
+
+    #[test]
+    fn stems() {
+        assert_eq!(stem("pts/main0.slurm"), "pts/main0");
+        assert_eq!(stem("pts.d/redo1"), "pts.d/redo1");
+        assert_eq!(stem("main"), "main");
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, process::Command};
 
 use serde::{Deserialize, Serialize};
 
@@ -7,7 +7,7 @@ use crate::program::Program;
 use crate::program::{dftbplus::DFTBPlus, molpro::Molpro};
 use crate::queue::Queue;
 
-use super::{SubQueue, Submit};
+use super::{cancel_with, DrainOptions, SubQueue, Submit};
 
 /// Slurm is a type for holding the information for submitting a slurm job.
 /// `filename` is the name of the Slurm submission script
@@ -19,6 +19,7 @@ pub struct Slurm {
     dir: &'static str,
     no_del: bool,
     pub(crate) template: Option<String>,
+    options: DrainOptions,
 }
 
 impl Slurm {
@@ -37,8 +38,16 @@ impl Slurm {
             dir,
             no_del,
             template,
+            options: DrainOptions::default(),
         }
     }
+
+    /// drain with `options` instead of the defaults. see
+    /// [SubQueue::options]
+    pub fn with_options(mut self, options: DrainOptions) -> Self {
+        self.options = options;
+        self
+    }
 }
 
 impl<P: Program + Clone + Serialize + for<'a> Deserialize<'a>> Submit<P>
@@ -114,16 +123,14 @@ where
         self.dir
     }
 
-    /// run `squeue -u $USER`. form of the output is:
+    /// run `squeue -u` for the [super::Transport::user]. form of the output is:
     ///
     ///    JOBID PARTITION   NAME     USER ST        TIME  NODES NODELIST(REASON)
     /// 30627992   compute  c3oh-   mdavis  R 46-17:12:23      1 node2
     fn stat_cmd(&self) -> String {
-        let user = std::env::var("USER").expect("couldn't find $USER env var");
-        let status = match std::process::Command::new("squeue")
-            .args(["-u", &user])
-            .output()
-        {
+        let transport = self.options.transport();
+        let mut cmd = transport.command(squeue(&transport.user()));
+        let status = match cmd.output() {
             Ok(status) => status,
             Err(e) => panic!("failed to run squeue with {e}"),
         };
@@ -132,26 +139,75 @@ where
     }
 
     fn status(&self) -> HashSet<String> {
-        let mut ret = HashSet::new();
         // wut?
-        let lines = <Slurm as SubQueue<P>>::stat_cmd(self);
-        let lines = lines.lines();
-        for line in lines {
-            if !line.contains("JOBID") {
-                let fields: Vec<_> = line.split_whitespace().collect();
-                assert!(fields.len() == 8);
-                // exclude completing jobs to combat stuck completing bug
-                if fields[4] != "CG" {
-                    ret.insert(fields[0].to_string());
-                }
-            }
-        }
-        ret
+        parse_squeue(&<Slurm as SubQueue<P>>::stat_cmd(self))
     }
 
     fn no_del(&self) -> bool {
         self.no_del
     }
+
+    /// run `scancel` on `job_ids`
+    fn cancel(&self, job_ids: &[String]) {
+        cancel_with(self.options.transport(), "scancel", job_ids);
+    }
+
+    fn options(&self) -> &DrainOptions {
+        &self.options
+    }
+}
+
+#[cfg(feature = "async")]
+impl<P> super::AsyncQueue<P> for Slurm
+where
+    Slurm: Queue<P>,
+    P: Program
+        + Clone
+        + Send
+        + Sync
+        + Serialize
+        + for<'a> Deserialize<'a>
+        + 'static,
+{
+    async fn status_async(&self) -> HashSet<String> {
+        let transport = self.options.transport();
+        let status = match tokio::process::Command::from(
+            transport.command(squeue(&transport.user())),
+        )
+        .output()
+        .await
+        {
+            Ok(status) => status,
+            Err(e) => panic!("failed to run squeue with {e}"),
+        };
+        parse_squeue(
+            &String::from_utf8(status.stdout)
+                .expect("failed to convert squeue output to String"),
+        )
+    }
+}
+
+/// the command run by [SubQueue::stat_cmd], listing the jobs of `user`
+pub(crate) fn squeue(user: &str) -> Command {
+    let mut cmd = Command::new("squeue");
+    cmd.args(["-u", user]);
+    cmd
+}
+
+/// return the ids of the jobs in the output of [squeue]
+pub(crate) fn parse_squeue(lines: &str) -> HashSet<String> {
+    let mut ret = HashSet::new();
+    for line in lines.lines() {
+        if !line.contains("JOBID") {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            assert!(fields.len() == 8);
+            // exclude completing jobs to combat stuck completing bug
+            if fields[4] != "CG" {
+                ret.insert(fields[0].to_string());
+            }
+        }
+    }
+    ret
 }
 
 #[cfg(test)]
@@ -170,6 +226,7 @@ mod tests {
             dir: "/tmp",
             no_del: false,
             template: None,
+            options: DrainOptions::default(),
         }
     }
 
@@ -200,5 +257,11 @@ mod tests {
         molpro_slurm, &slurm() => Molpro,
         cfour_slurm, &slurm() => Cfour,
         dftb_slurm, &slurm() => DFTBPlus,
+        mopac_parallel_slurm,
+            &slurm().with_options(DrainOptions::new().with_parallel(4, 2))
+            => Mopac,
+        mopac_markers_slurm,
+            &slurm().with_options(DrainOptions::new().with_markers())
+            => Mopac,
     }
 }
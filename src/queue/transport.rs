@@ -0,0 +1,438 @@
+//! access to the machine where jobs run, for submitting from a different one.
+//! a [Transport] turns the scheduler commands run by a queue into commands
+//! that run them remotely, and copies files back and forth. relative paths on
+//! the local side, which are resolved against the current directory, are
+//! mapped to the same relative paths under a remote directory, so drains
+//! should use relative paths throughout
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+/// how the commands and files of a queue reach the machine where its jobs run.
+/// see [DrainOptions::transport]
+///
+/// [DrainOptions::transport]: crate::queue::DrainOptions::transport
+pub trait Transport: Send + Sync {
+    /// the local command that runs `cmd` where the jobs run, in the remote
+    /// counterpart of its current directory
+    fn command(&self, cmd: Command) -> Command;
+
+    /// copy `files` from the local filesystem to the remote one, creating
+    /// their directories as needed
+    fn put(&self, files: &[String]);
+
+    /// copy whichever of `files` exist on the remote filesystem back to the
+    /// local one
+    fn get(&self, files: &[String]);
+
+    /// remove `files` from the remote filesystem if they exist
+    fn remove(&self, files: &[String]);
+
+    /// the local paths of the files in the remote counterparts of the local
+    /// directories `dirs`, listed all at once
+    fn list(&self, dirs: &[String]) -> HashSet<String>;
+
+    /// the user that owns the jobs on the remote machine, for listing them
+    /// with the scheduler. defaults to the local `$USER`
+    fn user(&self) -> String {
+        std::env::var("USER").expect("couldn't find $USER env var")
+    }
+
+    /// whether the jobs see a different filesystem at all. if not, the drain
+    /// skips copying files entirely
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Debug for dyn Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Transport")
+    }
+}
+
+/// the default [Transport], which runs everything on the local machine
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Direct;
+
+impl Transport for Direct {
+    fn command(&self, cmd: Command) -> Command {
+        cmd
+    }
+
+    fn put(&self, _files: &[String]) {}
+
+    fn get(&self, _files: &[String]) {}
+
+    fn remove(&self, _files: &[String]) {}
+
+    fn list(&self, _dirs: &[String]) -> HashSet<String> {
+        HashSet::new()
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+}
+
+/// a [Transport] over `ssh` and `sftp`. connections are opened for each
+/// command, so setting up connection sharing with `ControlMaster` in the ssh
+/// config is recommended
+#[derive(Clone, Debug)]
+pub struct Ssh {
+    /// the destination passed to ssh and sftp, like `user@host`
+    host: String,
+
+    /// the remote directory corresponding to the local current directory
+    dir: String,
+
+    /// the remote user, looked up the first time it's needed
+    user: OnceLock<String>,
+}
+
+impl Ssh {
+    pub fn new(host: &str, dir: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            dir: dir.to_owned(),
+            user: OnceLock::new(),
+        }
+    }
+
+    /// the remote path of the local `path`
+    fn remote(&self, path: &Path) -> String {
+        if path.is_absolute() {
+            path.display().to_string()
+        } else {
+            Path::new(&self.dir).join(path).display().to_string()
+        }
+    }
+
+    /// run `script` in sftp's batch mode
+    fn sftp(&self, script: &str) {
+        let child = Command::new("sftp")
+            .args(["-q", "-b", "-", &self.host])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("failed to run sftp with {e}");
+                return;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(script.as_bytes());
+        }
+        match child.wait_with_output() {
+            Ok(out) if out.status.success() => {}
+            Ok(out) => log::warn!(
+                "sftp failed with `{}`",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+            Err(e) => log::warn!("failed to run sftp with {e}"),
+        }
+    }
+
+    /// the sftp batch script copying the local `files` to the remote side
+    fn put_script(&self, files: &[String]) -> String {
+        let mut script = String::new();
+        for f in files {
+            let remote = self.remote(Path::new(f));
+            let (f, remote) = (sftp_quote(f), sftp_quote(&remote));
+            script.push_str(&format!("put {f} {remote}\n"));
+        }
+        script
+    }
+
+    /// the sftp batch script copying the remote counterparts of `files` back
+    fn get_script(&self, files: &[String]) -> String {
+        let mut script = String::new();
+        for f in files {
+            // a leading - keeps sftp going when a file doesn't exist yet
+            let remote = self.remote(Path::new(f));
+            let (f, remote) = (sftp_quote(f), sftp_quote(&remote));
+            script.push_str(&format!("-get {remote} {f}\n"));
+        }
+        script
+    }
+
+    /// run the shell command `line` on the remote machine, returning its
+    /// output
+    fn ssh(&self, line: &str) -> String {
+        match Command::new("ssh").arg(&self.host).arg(line).output() {
+            Ok(s) if s.status.success() => {
+                return String::from_utf8_lossy(&s.stdout).into_owned();
+            }
+            Ok(s) => log::warn!(
+                "`{line}` failed over ssh with `{}`",
+                String::from_utf8_lossy(&s.stderr).trim()
+            ),
+            Err(e) => log::warn!("failed to run ssh with {e}"),
+        }
+        String::new()
+    }
+}
+
+impl Transport for Ssh {
+    fn command(&self, cmd: Command) -> Command {
+        let dir = match cmd.get_current_dir() {
+            Some(d) => self.remote(d),
+            None => self.dir.clone(),
+        };
+        let mut line = format!("cd {} &&", quote(&dir));
+        for word in std::iter::once(cmd.get_program()).chain(cmd.get_args()) {
+            line.push(' ');
+            line.push_str(&quote(&word.to_string_lossy()));
+        }
+        let mut ret = Command::new("ssh");
+        ret.arg(&self.host).arg(line);
+        ret
+    }
+
+    fn put(&self, files: &[String]) {
+        if files.is_empty() {
+            return;
+        }
+        let dirs: BTreeSet<_> = files
+            .iter()
+            .filter_map(|f| Path::new(f).parent())
+            .map(|d| quote(&self.remote(d)))
+            .collect();
+        let dirs: Vec<_> = dirs.into_iter().collect();
+        self.ssh(&format!("mkdir -p {}", dirs.join(" ")));
+        self.sftp(&self.put_script(files));
+    }
+
+    fn get(&self, files: &[String]) {
+        if files.is_empty() {
+            return;
+        }
+        for f in files {
+            if let Some(dir) = Path::new(f).parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+        }
+        self.sftp(&self.get_script(files));
+    }
+
+    fn remove(&self, files: &[String]) {
+        if files.is_empty() {
+            return;
+        }
+        let files: Vec<_> = files
+            .iter()
+            .map(|f| quote(&self.remote(Path::new(f))))
+            .collect();
+        self.ssh(&format!("rm -f {}", files.join(" ")));
+    }
+
+    fn list(&self, dirs: &[String]) -> HashSet<String> {
+        if dirs.is_empty() {
+            return HashSet::new();
+        }
+        let remotes: HashMap<_, _> = dirs
+            .iter()
+            .map(|d| (PathBuf::from(self.remote(Path::new(d))), d))
+            .collect();
+        let quoted: Vec<_> = remotes
+            .keys()
+            .map(|d| quote(&d.display().to_string()))
+            .collect();
+        let out = self.ssh(&format!(
+            "find {} -maxdepth 1 -type f 2>/dev/null",
+            quoted.join(" ")
+        ));
+        out.lines()
+            .filter_map(|line| {
+                let path = Path::new(line);
+                let dir = remotes.get(path.parent()?)?;
+                let local = Path::new(dir).join(path.file_name()?);
+                Some(local.display().to_string())
+            })
+            .collect()
+    }
+
+    /// the user in a destination like `user@host`, or else the remote `$USER`
+    fn user(&self) -> String {
+        self.user
+            .get_or_init(|| match self.host.split_once('@') {
+                Some((user, _)) => user.to_owned(),
+                None => self.ssh("echo $USER").trim().to_owned(),
+            })
+            .clone()
+    }
+}
+
+/// a local stand-in for [Ssh] that treats the directory `root` as the remote
+/// filesystem and runs commands locally inside it. absolute paths are shared
+/// between the two sides, so only relative paths are copied
+#[derive(Clone, Debug)]
+pub struct Mirror {
+    root: PathBuf,
+}
+
+impl Mirror {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// the mirrored path of the relative `path`
+    fn remote(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        (!path.is_absolute()).then(|| self.root.join(path))
+    }
+}
+
+impl Transport for Mirror {
+    fn command(&self, mut cmd: Command) -> Command {
+        let dir = cmd.get_current_dir().unwrap_or(Path::new(""));
+        let dir = self.root.join(dir);
+        cmd.current_dir(dir);
+        cmd
+    }
+
+    fn put(&self, files: &[String]) {
+        for f in files {
+            let Some(remote) = self.remote(f) else {
+                continue;
+            };
+            if let Some(dir) = remote.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            if let Err(e) = fs::copy(f, &remote) {
+                log::warn!(
+                    "failed to copy {f} to {} with {e}",
+                    remote.display()
+                );
+            }
+        }
+    }
+
+    fn get(&self, files: &[String]) {
+        for f in files {
+            let Some(remote) = self.remote(f) else {
+                continue;
+            };
+            if !remote.exists() {
+                continue;
+            }
+            if let Some(dir) = Path::new(f).parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            if let Err(e) = fs::copy(&remote, f) {
+                log::warn!(
+                    "failed to copy {} to {f} with {e}",
+                    remote.display()
+                );
+            }
+        }
+    }
+
+    fn remove(&self, files: &[String]) {
+        for f in files {
+            if let Some(remote) = self.remote(f) {
+                let _ = fs::remove_file(remote);
+            }
+        }
+    }
+
+    fn list(&self, dirs: &[String]) -> HashSet<String> {
+        let mut ret = HashSet::new();
+        for dir in dirs {
+            let Some(entries) =
+                self.remote(dir).and_then(|d| fs::read_dir(d).ok())
+            else {
+                continue;
+            };
+            for e in entries.flatten() {
+                if !e.file_type().is_ok_and(|t| t.is_file()) {
+                    continue;
+                }
+                let local = Path::new(dir).join(e.file_name());
+                ret.insert(local.display().to_string());
+            }
+        }
+        ret
+    }
+}
+
+/// quote `s` for a POSIX shell
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// quote `s` for an sftp batch script. within double quotes, sftp only treats
+/// `"` and `\` specially, and it escapes any glob characters itself
+fn sftp_quote(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            ret.push('\\');
+        }
+        ret.push(c);
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirror_list() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("pts")).unwrap();
+        fs::write(root.path().join("pts/job0.done"), "").unwrap();
+        fs::write(root.path().join("main0.slurm"), "").unwrap();
+        let mirror = Mirror::new(root.path());
+        let got = mirror.list(&["pts".to_owned(), String::new()]);
+        let want = ["pts/job0.done", "main0.slurm"].map(String::from);
+        assert_eq!(got, HashSet::from(want));
+    }
+
+    #[test]
+    fn ssh_command() {
+        let ssh = Ssh::new("user@host", "/scratch/it's");
+        let mut cmd = Command::new("qsub");
+        cmd.arg("main0.pbs").current_dir("pts");
+        let got = ssh.command(cmd);
+        let args: Vec<_> = got.get_args().collect();
+        assert_eq!(got.get_program(), "ssh");
+        assert_eq!(
+            args,
+            [
+                "user@host",
+                r"cd '/scratch/it'\''s/pts' && 'qsub' 'main0.pbs'"
+            ]
+        );
+    }
+
+    #[test]
+    fn sftp_scripts() {
+        let ssh = Ssh::new("user@host", "/scratch/it's");
+        let files = [r#"pts/a "b".inp"#, r"pts/c\d*.out"].map(String::from);
+        assert_eq!(
+            ssh.put_script(&files),
+            r#"put "pts/a \"b\".inp" "/scratch/it's/pts/a \"b\".inp"
+put "pts/c\\d*.out" "/scratch/it's/pts/c\\d*.out"
+"#
+        );
+        assert_eq!(
+            ssh.get_script(&files[..1]),
+            r#"-get "/scratch/it's/pts/a \"b\".inp" "pts/a \"b\".inp"
+"#
+        );
+        assert_eq!(sftp_quote(r"dir\"), r#""dir\\""#);
+    }
+}
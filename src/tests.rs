@@ -1,4 +1,4 @@
-use crate::geom::Geom;
+use crate::geom::{Geom, Unit};
 use symm::Atom;
 
 #[test]
@@ -33,3 +33,110 @@ water geometry
         ])
     );
 }
+
+#[test]
+fn test_to_unit() {
+    let geom = Geom::Xyz(vec![
+        Atom::new(1, 0.0000000000, 0.7574590974, 0.5217905143),
+        Atom::new(8, 0.0000000000, 0.0000000000, -0.0657441568),
+        Atom::new(1, 0.0000000000, -0.7574590974, 0.5217905143),
+    ]);
+    let bohr = geom.to_unit(Unit::Bohr);
+    assert_ne!(*bohr, geom);
+    let Geom::Xyz(mut atoms) = bohr.into_owned() else {
+        unreachable!();
+    };
+    crate::geom::convert(&mut atoms, Unit::Bohr, Unit::Angstrom);
+    assert_eq!(Geom::Xyz(atoms), geom);
+
+    let zmat = Geom::Zmat("H\nO 1 OH\n\nOH = 1.0\n".to_owned());
+    assert_eq!(*zmat.to_unit(Unit::Bohr), zmat);
+}
+
+fn water(dy: f64, dz: f64) -> Vec<Atom> {
+    vec![
+        Atom::new(8, 0.0000000000, 0.0000000000, -0.0657441568),
+        Atom::new(1, 0.0000000000, 0.7574590974 + dy, 0.5217905143 + dz),
+        Atom::new(1, 0.0000000000, -0.7574590974, 0.5217905143),
+    ]
+}
+
+#[test]
+fn test_symmetry() {
+    use crate::geom::symmetry::Symmetry;
+    // C2v
+    let symm = Symmetry::new(&water(0.0, 0.0), 1e-8);
+    assert_eq!(symm.ops.len(), 4);
+    assert!(symm.ops[0].is_identity());
+
+    // displacing the second H along +y is the mirror image of displacing the
+    // first H along -y, with the H atoms swapped
+    let mut mirror = water(0.0, 0.0);
+    mirror[2].y -= 0.005;
+    let geoms = vec![
+        Geom::Xyz(water(0.005, 0.0)),
+        Geom::Xyz(water(0.0, 0.005)),
+        Geom::Xyz(mirror),
+        Geom::Xyz(water(0.005, 0.0)),
+        Geom::Zmat(String::new()),
+    ];
+    let got = symm.equivalent_geoms(&geoms, 1e-8);
+    assert_eq!(got, vec![None, None, Some(0), Some(0), None]);
+}
+
+#[test]
+fn test_build_jobs_symm() {
+    use crate::geom::symmetry::Symmetry;
+    use crate::program::{molpro::Molpro, Program, Template};
+    let symm = Symmetry::new(&water(0.0, 0.0), 1e-8);
+    let mut mirror = water(0.0, 0.0);
+    mirror[2].y -= 0.005;
+    let geoms = vec![
+        Geom::Xyz(water(0.005, 0.0)),
+        Geom::Xyz(water(0.0, 0.005)),
+        Geom::Xyz(mirror),
+    ];
+    let jobs = Molpro::build_jobs_symm(
+        geoms,
+        "/tmp",
+        3,
+        -2.0,
+        0,
+        0,
+        Template::from(""),
+        &symm,
+        1e-8,
+    );
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0].index, 3);
+    assert_eq!(jobs[0].symm_dups, vec![(5, -2.0)]);
+    assert!(jobs[0].dups.is_empty());
+    assert_eq!(jobs[1].index, 4);
+    assert!(jobs[1].symm_dups.is_empty());
+}
+
+#[test]
+fn test_symmetry_c3v() {
+    use crate::geom::symmetry::Symmetry;
+    // ammonia, with its C3 axis along z
+    let (s, c) = 120f64.to_radians().sin_cos();
+    let h = |x: f64, y: f64| Atom::new(1, x, y, -0.38);
+    let ammonia = |dx: f64| {
+        vec![
+            Atom::new(7, 0.0, 0.0, 0.0),
+            h(0.94 + dx, 0.0),
+            h(0.94 * c, 0.94 * s),
+            h(0.94 * c, -0.94 * s),
+        ]
+    };
+    let symm = Symmetry::new(&ammonia(0.0), 1e-6);
+    assert_eq!(symm.ops.len(), 6);
+
+    // stretching the second H is a C3 rotation of stretching the first
+    let mut other = ammonia(0.0);
+    other[2].x += 0.01 * c;
+    other[2].y += 0.01 * s;
+    let geoms = vec![Geom::Xyz(ammonia(0.01)), Geom::Xyz(other)];
+    let got = symm.equivalent_geoms(&geoms, 1e-6);
+    assert_eq!(got, vec![None, Some(0)]);
+}
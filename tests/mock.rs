@@ -0,0 +1,148 @@
+//! end-to-end drains against the scripted [Mock] queue
+
+#[cfg(feature = "replay")]
+use psqs::queue::replay::{Recorder, Replay};
+use psqs::{
+    geom::Geom,
+    program::{fake::Fake, Job, Program, Template},
+    queue::{
+        mock::{Mock, Outcome, Scenario},
+        CancelToken, Check, DrainError, DrainOptions, Queue, StderrObserver,
+    },
+};
+use tempfile::TempDir;
+
+/// the fake energy of a geometry, which just needs to differ between jobs
+fn energy(geom: &Geom) -> f64 {
+    geom.xyz().unwrap().iter().map(|a| a.z).sum()
+}
+
+/// build `n` jobs in a new temporary directory, returning the directory,
+/// the jobs, and their expected energies
+fn setup(n: usize) -> (TempDir, Vec<Job<Fake>>, Vec<f64>) {
+    let dir = tempfile::tempdir().unwrap();
+    let moles: Vec<Geom> = (0..n)
+        .map(|i| format!("H 0.0 0.0 {i}.0\nH 0.0 0.0 0.5").parse().unwrap())
+        .collect();
+    let want = moles.iter().map(energy).collect();
+    let jobs =
+        Fake::build_jobs(moles, dir.path(), 0, 1.0, 0, 0, Template::from(""));
+    (dir, jobs, want)
+}
+
+/// the number of submit scripts for resubmitted jobs
+fn redos(queue: &Mock) -> usize {
+    queue
+        .submitted()
+        .iter()
+        .filter(|s| s.rsplit('/').next().unwrap().starts_with("redo"))
+        .count()
+}
+
+#[test]
+fn drain() {
+    let (dir, jobs, want) = setup(5);
+    let dir = dir.path().to_str().unwrap();
+    let queue = Mock::new(dir, Scenario::new(), energy).with_chunk_size(2);
+    let mut dst = vec![0.0; 5];
+    let time = queue.drain(dir, jobs, &mut dst, Check::None);
+    assert_eq!(time, Ok(5.0));
+    assert_eq!(dst, want);
+    assert_eq!(queue.submitted().len(), 3);
+}
+
+#[test]
+fn resubmission() {
+    let (dir, jobs, want) = setup(4);
+    let dir = dir.path().to_str().unwrap();
+    let scenario = Scenario::new()
+        .job("job.00000001", [Outcome::Die])
+        .job("job.00000002", [Outcome::LoseOutput, Outcome::Delay(2)]);
+    let queue = Mock::new(dir, scenario, energy);
+    let mut dst = vec![0.0; 4];
+    let time = queue.drain(dir, jobs, &mut dst, Check::None);
+    assert_eq!(time, Ok(4.0));
+    assert_eq!(dst, want);
+    assert_eq!(redos(&queue), 2);
+}
+
+#[test]
+fn failed_jobs() {
+    let (dir, jobs, want) = setup(4);
+    let dir = dir.path().to_str().unwrap();
+    let scenario = Scenario::new().job("job.00000002", [Outcome::Fail]);
+    let queue = Mock::new(dir, scenario, energy);
+    let mut dst = vec![0.0; 4];
+    let res = queue.drain(dir, jobs, &mut dst, Check::None);
+    assert_eq!(res, Err(vec![2]));
+    // the rest still finished, and the failure was not resubmitted
+    assert_eq!(dst[..2], want[..2]);
+    assert_eq!(dst[3], want[3]);
+    assert_eq!(redos(&queue), 0);
+}
+
+/// interrupt a drain, then resume it from its checkpoint with a new token,
+/// reattaching to the jobs submitted the first time
+#[test]
+fn checkpoint() {
+    let (dir, jobs, want) = setup(4);
+    let dir = dir.path().to_str().unwrap();
+    let scenario = Scenario::new()
+        .job("job.00000003", [Outcome::Delay(3)])
+        .interrupt_at(1);
+    let queue = Mock::new(dir, scenario, energy);
+    let interrupted = queue.clone().with_options(
+        DrainOptions::new().with_cancel_token(CancelToken::new()),
+    );
+    let mut dst = vec![0.0; 4];
+    let check = Check::Some {
+        check_int: 1,
+        check_dir: dir.to_owned(),
+    };
+    let res =
+        interrupted.try_drain(dir, jobs, &mut dst, check, &StderrObserver);
+    let Err(DrainError::Cancelled {
+        checkpoint: Some(checkpoint),
+    }) = res
+    else {
+        panic!("expected a checkpoint, got {res:?}");
+    };
+    assert_eq!(queue.submitted().len(), 4);
+
+    let time = queue.resume(dir, &checkpoint, &mut dst, Check::None);
+    assert_eq!(time, Ok(4.0));
+    assert_eq!(dst, want);
+    assert_eq!(queue.submitted().len(), 4);
+    assert!(queue.cancelled().is_empty());
+}
+
+/// record a drain with every kind of trouble and check that replaying the log
+/// makes the same submissions and ends the same way
+#[cfg(feature = "replay")]
+#[test]
+fn replay() {
+    let (dir, jobs, want) = setup(5);
+    let dir = dir.path().to_str().unwrap();
+    let log = format!("{dir}/replay.jsonl");
+    let scenario = Scenario::new()
+        .job("job.00000000", [Outcome::Die])
+        .job("job.00000001", [Outcome::LoseOutput])
+        .job("job.00000002", [Outcome::Fail])
+        .job("job.00000004", [Outcome::Delay(2)]);
+    let queue = Mock::new(dir, scenario, energy)
+        .with_chunk_size(2)
+        .with_options(
+            DrainOptions::new().with_recorder(Recorder::new(&log).unwrap()),
+        );
+    let mut dst = vec![0.0; 5];
+    let res = queue.drain(dir, jobs, &mut dst, Check::None);
+    assert_eq!(res, Err(vec![2]));
+    assert_eq!(dst[3..], want[3..]);
+
+    let replay = Replay::load(&log).unwrap();
+    let mut got = vec![0.0; 5];
+    let got_res = replay.drain(dir, replay.jobs(), &mut got, Check::None);
+    assert_eq!(got_res, res);
+    assert_eq!(got, dst);
+    assert_eq!(replay.remaining(), 0);
+}